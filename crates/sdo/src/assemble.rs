@@ -0,0 +1,390 @@
+//! Reassembling responses the server splits across several packets.
+//!
+//! A large response arrives as a run of payload SDOs sharing a request id.
+//! The first is flagged [`PacketFlags::FIRST`], the last
+//! [`PacketFlags::LAST`] and those in between carry neither. Payloads without
+//! a packet flag are complete on their own.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    fields::{HAS_MORE_DATA, PACKET_FLAG},
+    PacketFlags, Topic, SDO,
+};
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum Error {
+    #[error("packet for request {0} arrived before its first packet")]
+    MissingFirstPacket(String),
+    #[error("request {0} started again before its last packet")]
+    MissingLastPacket(String),
+    #[error("packet for request {id} has topic {actual:?}, expected {expected:?}")]
+    TopicMismatch {
+        id: String,
+        expected: Topic,
+        actual: Topic,
+    },
+    #[error("field {field} of request {id} changed type between packets")]
+    FieldTypeMismatch { id: String, field: u32 },
+    #[error("timed out waiting for the rest of request {0}")]
+    TimedOut(String),
+}
+
+/// Fields that describe a single packet rather than the response, so the
+/// merged SDO takes them from the last packet instead of concatenating.
+const PER_PACKET_FIELDS: &[u32] = &[PACKET_FLAG, HAS_MORE_DATA];
+
+/// Buffers packets per request id until the last one arrives.
+#[derive(Debug)]
+pub struct Assembler {
+    timeout: Duration,
+    partial: HashMap<String, Partial>,
+}
+
+#[derive(Debug)]
+struct Partial {
+    sdo: SDO,
+    last_packet_at: Instant,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(30))
+    }
+}
+
+impl Assembler {
+    /// Partial responses are given up on once `timeout` passes without
+    /// another packet arriving; see [`Assembler::expire`].
+    #[must_use]
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// How long a partial response may go without another packet.
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Add a packet for request `id`, returning the whole response once its
+    /// last packet arrives. On error, anything buffered for `id` is dropped.
+    pub fn push(&mut self, id: &str, sdo: SDO) -> Result<Option<SDO>, Error> {
+        let Some(flags) = sdo.packet_flag() else {
+            return Ok(Some(sdo));
+        };
+
+        if flags.contains(PacketFlags::FIRST) {
+            if self.partial.remove(id).is_some() {
+                return Err(Error::MissingLastPacket(id.to_owned()));
+            }
+            if flags.contains(PacketFlags::LAST) {
+                return Ok(Some(finish(sdo)));
+            }
+            self.partial.insert(
+                id.to_owned(),
+                Partial {
+                    sdo,
+                    last_packet_at: Instant::now(),
+                },
+            );
+            return Ok(None);
+        }
+
+        let mut partial = self
+            .partial
+            .remove(id)
+            .ok_or_else(|| Error::MissingFirstPacket(id.to_owned()))?;
+        if partial.sdo.topic != sdo.topic {
+            return Err(Error::TopicMismatch {
+                id: id.to_owned(),
+                expected: partial.sdo.topic,
+                actual: sdo.topic,
+            });
+        }
+        merge(&mut partial.sdo, sdo).map_err(|field| Error::FieldTypeMismatch {
+            id: id.to_owned(),
+            field,
+        })?;
+        if flags.contains(PacketFlags::LAST) {
+            return Ok(Some(finish(partial.sdo)));
+        }
+        partial.last_packet_at = Instant::now();
+        self.partial.insert(id.to_owned(), partial);
+        Ok(None)
+    }
+
+    /// Drop partial responses that have timed out, returning their request
+    /// ids.
+    pub fn expire(&mut self) -> Vec<String> {
+        let expired = self
+            .partial
+            .iter()
+            .filter(|(_, p)| p.last_packet_at.elapsed() >= self.timeout)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in &expired {
+            self.partial.remove(id);
+        }
+        expired
+    }
+
+    /// Drop anything buffered for request `id`.
+    pub fn cancel(&mut self, id: &str) {
+        self.partial.remove(id);
+    }
+
+    /// How many responses are partway through arriving.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.partial.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.partial.is_empty()
+    }
+}
+
+/// Append `packet`'s rows to `sdo`, field by field. Fields missing from
+/// either side get null rows in their place, so the rows stay lined up.
+/// Returns the id of the first field whose type differs between them.
+fn merge(sdo: &mut SDO, packet: SDO) -> Result<(), u32> {
+    let merged = row_count(sdo);
+    let added = row_count(&packet);
+    let mut seen = vec![];
+    for (mut header, data) in packet.fields {
+        let id = header.field_id.unwrap_or_default();
+        seen.push(header.field_id);
+        let existing = sdo
+            .fields
+            .iter_mut()
+            .find(|(h, _)| h.field_id == header.field_id);
+        match existing {
+            Some(existing) if PER_PACKET_FIELDS.contains(&id) => *existing = (header, data),
+            Some((existing_header, Some(existing_data))) => {
+                if let Some(data) = data {
+                    existing_data.extend(data).map_err(|_| id)?;
+                    existing_header.set_rows(existing_data);
+                }
+            }
+            existing => {
+                let data = data.map(|data| {
+                    let mut padded = data.nulls(merged);
+                    padded.extend(data).expect("nulls are the same type");
+                    header.set_rows(&padded);
+                    padded
+                });
+                match existing {
+                    Some(existing) => *existing = (header, data),
+                    None => sdo.fields.push((header, data)),
+                }
+            }
+        }
+    }
+    for (header, data) in &mut sdo.fields {
+        let id = header.field_id.unwrap_or_default();
+        if seen.contains(&header.field_id) || PER_PACKET_FIELDS.contains(&id) {
+            continue;
+        }
+        if let Some(data) = data {
+            data.extend(data.nulls(added))
+                .expect("nulls are the same type");
+            header.set_rows(data);
+        }
+    }
+    Ok(())
+}
+
+/// The most rows any of `sdo`'s fields has, leaving out the per-packet ones.
+fn row_count(sdo: &SDO) -> usize {
+    sdo.fields
+        .iter()
+        .filter(|(h, _)| !PER_PACKET_FIELDS.contains(&h.field_id.unwrap_or_default()))
+        .filter_map(|(_, data)| data.as_ref().map(|d| d.null_rows().len()))
+        .max()
+        .unwrap_or_default()
+}
+
+/// The merged response is a single packet again.
+fn finish(mut sdo: SDO) -> SDO {
+    sdo.remove_field(PACKET_FLAG);
+    sdo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data::Data, fields::ORDER_NUMBER};
+
+    fn packet(flags: u32, rows: &[&str]) -> SDO {
+        let mut sdo = SDO::new(Topic::TdIosGeneral);
+        sdo.push_string_w(
+            crate::fields::SECURITY_CODE,
+            rows.iter()
+                .map(|r| Some((*r).to_owned()))
+                .collect::<Vec<_>>(),
+        );
+        sdo.push_long(PACKET_FLAG, Some(flags));
+        sdo
+    }
+
+    #[test]
+    fn ignores_unknown_packet_flags() {
+        let mut assembler = Assembler::default();
+        let first = PacketFlags::FIRST.bits() | 0x100;
+        assert!(assembler
+            .push("a", packet(first, &["BHP"]))
+            .unwrap()
+            .is_none());
+        let last = PacketFlags::LAST.bits() | 0x100;
+        let sdo = assembler
+            .push("a", packet(last, &["CBA"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(sdo.rows().len(), 2);
+        assert!(sdo.packet_flag().is_none());
+    }
+
+    fn codes(sdo: &SDO) -> Vec<String> {
+        sdo.get_field(crate::fields::SECURITY_CODE)
+            .and_then(crate::data::Data::to_vec_string)
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn merges_packets_in_order() {
+        let mut assembler = Assembler::default();
+        let mut first = packet(PacketFlags::FIRST.bits(), &["BHP"]);
+        first.push_long(HAS_MORE_DATA, Some(1));
+        assert!(assembler.push("a", first).unwrap().is_none());
+        assert!(assembler
+            .push("a", packet(0, &["CBA", "NAB"]))
+            .unwrap()
+            .is_none());
+        assert_eq!(assembler.len(), 1);
+        let mut last = packet(PacketFlags::LAST.bits(), &["WBC"]);
+        last.push_long(HAS_MORE_DATA, Some(0));
+        let sdo = assembler.push("a", last).unwrap().unwrap();
+        assert_eq!(codes(&sdo), ["BHP", "CBA", "NAB", "WBC"]);
+        assert!(!sdo.has_more_data());
+        assert!(assembler.is_empty());
+    }
+
+    #[test]
+    fn pads_fields_missing_from_some_packets() {
+        let mut assembler = Assembler::default();
+        assembler
+            .push("a", packet(PacketFlags::FIRST.bits(), &["BHP", "CBA"]))
+            .unwrap();
+        let mut last = packet(PacketFlags::LAST.bits(), &["NAB"]);
+        last.push_long(ORDER_NUMBER, Some(7));
+        last.remove_field(crate::fields::SECURITY_CODE);
+        let sdo = assembler.push("a", last).unwrap().unwrap();
+
+        let rows = sdo.rows();
+        assert_eq!(rows.len(), 3);
+        let orders = rows
+            .iter()
+            .map(|r| r.get_field(ORDER_NUMBER).and_then(Data::as_first_u32))
+            .collect::<Vec<_>>();
+        assert_eq!(orders, [None, None, Some(7)]);
+        let codes = rows
+            .iter()
+            .map(|r| {
+                r.get_field(crate::fields::SECURITY_CODE)
+                    .and_then(Data::as_first_str)
+                    .map(ToOwned::to_owned)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            codes,
+            [Some("BHP".to_owned()), Some("CBA".to_owned()), None]
+        );
+    }
+
+    #[test]
+    fn packets_without_a_flag_are_complete() {
+        let mut assembler = Assembler::default();
+        let mut sdo = SDO::new(Topic::TdIosGeneral);
+        sdo.push_string_w(crate::fields::SECURITY_CODE, Some("BHP".to_owned()));
+        let whole = assembler.push("a", sdo).unwrap().unwrap();
+        assert_eq!(codes(&whole), ["BHP"]);
+        let both = (PacketFlags::FIRST | PacketFlags::LAST).bits();
+        let whole = assembler
+            .push("b", packet(both, &["CBA"]))
+            .unwrap()
+            .unwrap();
+        assert!(whole.packet_flag().is_none());
+        assert!(assembler.is_empty());
+    }
+
+    #[test]
+    fn out_of_order_packets_are_errors() {
+        let mut assembler = Assembler::default();
+        let last = packet(PacketFlags::LAST.bits(), &["BHP"]);
+        assert!(matches!(
+            assembler.push("a", last),
+            Err(Error::MissingFirstPacket(id)) if id == "a"
+        ));
+
+        let first = || packet(PacketFlags::FIRST.bits(), &["BHP"]);
+        assembler.push("a", first()).unwrap();
+        assert!(matches!(
+            assembler.push("a", first()),
+            Err(Error::MissingLastPacket(_))
+        ));
+        assert!(assembler.is_empty());
+    }
+
+    #[test]
+    fn mismatched_packets_drop_the_response() {
+        let mut assembler = Assembler::default();
+        assembler
+            .push("a", packet(PacketFlags::FIRST.bits(), &["BHP"]))
+            .unwrap();
+        let mut other = packet(0, &["CBA"]);
+        other.topic = Topic::TdIosOrders;
+        assert!(matches!(
+            assembler.push("a", other),
+            Err(Error::TopicMismatch { .. })
+        ));
+        assert!(assembler.is_empty());
+
+        assembler
+            .push("a", packet(PacketFlags::FIRST.bits(), &["BHP"]))
+            .unwrap();
+        let mut retyped = SDO::new(Topic::TdIosGeneral);
+        retyped.push_long(crate::fields::SECURITY_CODE, Some(1));
+        retyped.push_long(PACKET_FLAG, Some(0));
+        assert!(matches!(
+            assembler.push("a", retyped),
+            Err(Error::FieldTypeMismatch { field, .. }) if field == crate::fields::SECURITY_CODE
+        ));
+        assert!(assembler.is_empty());
+    }
+
+    #[test]
+    fn expires_and_cancels_partial_responses() {
+        let mut assembler = Assembler::new(Duration::ZERO);
+        let first = || packet(PacketFlags::FIRST.bits(), &["BHP"]);
+        assembler.push("a", first()).unwrap();
+        assert_eq!(assembler.expire(), ["a"]);
+        assert!(assembler.is_empty());
+
+        let mut assembler = Assembler::default();
+        assembler.push("a", first()).unwrap();
+        assert!(assembler.expire().is_empty());
+        assembler.cancel("a");
+        assert!(assembler.is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+//! Reading SDO traffic out of captures taken outside this crate.
+
+use time::OffsetDateTime;
+
+use crate::{decode, Message};
+
+pub mod har;
+pub mod pcap;
+pub mod recording;
+pub mod websocket;
+
+/// Which way a frame travelled, from the client's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    #[must_use]
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Direction::Sent => ">>",
+            Direction::Received => "<<",
+        }
+    }
+}
+
+/// The raw bytes of one frame (a websocket message or HTTP body).
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    pub timestamp: OffsetDateTime,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+impl CapturedFrame {
+    /// Decode every message in the frame; frames may hold several back to
+    /// back.
+    pub fn messages(&self) -> Result<Vec<Message>, decode::Error> {
+        decode::read_msgs(&self.bytes).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CapturedMessage {
+    pub timestamp: OffsetDateTime,
+    pub direction: Direction,
+    pub message: Message,
+}
+
+/// Decode frames into time-ordered messages. Frames that don't decode are
+/// logged and skipped, since captures usually contain unrelated traffic.
+#[must_use]
+pub fn decode_frames(frames: &[CapturedFrame]) -> Vec<CapturedMessage> {
+    let mut messages = vec![];
+    for frame in frames {
+        match frame.messages() {
+            Ok(decoded) => messages.extend(decoded.into_iter().map(|message| CapturedMessage {
+                timestamp: frame.timestamp,
+                direction: frame.direction,
+                message,
+            })),
+            Err(error) => {
+                warn!(%error, timestamp = %frame.timestamp, len = frame.bytes.len(), "skipping undecodable frame");
+            }
+        }
+    }
+    messages.sort_by_key(|m| m.timestamp);
+    messages
+}
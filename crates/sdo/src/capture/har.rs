@@ -0,0 +1,237 @@
+//! Browser HAR captures of the Viewpoint web client.
+//!
+//! SDO payloads are taken from binary websocket frames (Chrome records these
+//! under each entry's `_webSocketMessages`) and from binary HTTP request and
+//! response bodies.
+
+use std::io::Read;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::Value;
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+use super::{decode_frames, CapturedFrame, CapturedMessage, Direction};
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("invalid json")]
+    Json(#[from] serde_json::Error),
+    #[error("not a HAR file: missing log.entries")]
+    MissingEntries,
+}
+
+/// Websocket opcode for binary frames.
+const OPCODE_BINARY: u64 = 2;
+
+/// Read every frame that may carry SDO payloads, in time order.
+pub fn read_frames(reader: impl Read) -> Result<Vec<CapturedFrame>, Error> {
+    let har: Value = serde_json::from_reader(reader)?;
+    let entries = har["log"]["entries"]
+        .as_array()
+        .ok_or(Error::MissingEntries)?;
+
+    let mut frames = vec![];
+    for entry in entries {
+        let started = entry["startedDateTime"]
+            .as_str()
+            .and_then(|s| OffsetDateTime::parse(s, &Rfc3339).ok());
+
+        if let Some(ws_messages) = entry["_webSocketMessages"].as_array() {
+            frames.extend(ws_messages.iter().filter_map(websocket_frame));
+            continue;
+        }
+
+        let Some(started) = started else {
+            continue;
+        };
+        if let Some(bytes) = body(&entry["request"]["postData"]) {
+            frames.push(CapturedFrame {
+                timestamp: started,
+                direction: Direction::Sent,
+                bytes,
+            });
+        }
+        if let Some(bytes) = body(&entry["response"]["content"]) {
+            let elapsed = entry["time"].as_f64().unwrap_or_default();
+            let Some(timestamp) = after(started, elapsed / 1000.0) else {
+                warn!(elapsed, "skipping HAR response with an invalid time");
+                continue;
+            };
+            frames.push(CapturedFrame {
+                timestamp,
+                direction: Direction::Received,
+                bytes,
+            });
+        }
+    }
+    frames.sort_by_key(|f| f.timestamp);
+    Ok(frames)
+}
+
+/// Read and decode every SDO message in a HAR file, in time order.
+pub fn read_messages(reader: impl Read) -> Result<Vec<CapturedMessage>, Error> {
+    Ok(decode_frames(&read_frames(reader)?))
+}
+
+fn websocket_frame(message: &Value) -> Option<CapturedFrame> {
+    if message["opcode"].as_u64() != Some(OPCODE_BINARY) {
+        return None;
+    }
+    let direction = match message["type"].as_str()? {
+        "send" => Direction::Sent,
+        "receive" => Direction::Received,
+        _ => return None,
+    };
+    // Seconds since the unix epoch, with fractional milliseconds.
+    let time = message["time"].as_f64()?;
+    let Some(timestamp) = after(OffsetDateTime::UNIX_EPOCH, time) else {
+        warn!(time, "skipping websocket frame with an invalid time");
+        return None;
+    };
+    let bytes = BASE64.decode(message["data"].as_str()?).ok()?;
+    Some(CapturedFrame {
+        timestamp,
+        direction,
+        bytes,
+    })
+}
+
+/// Only binary bodies can carry SDOs: base64 encoded content, or content
+/// recorded with an octet-stream mime type.
+fn body(content: &Value) -> Option<Vec<u8>> {
+    let text = content["text"].as_str().filter(|t| !t.is_empty())?;
+    if content["encoding"].as_str() == Some("base64") {
+        return BASE64.decode(text).ok();
+    }
+    content["mimeType"]
+        .as_str()
+        .filter(|m| m.contains("octet-stream"))?;
+    // Unencoded binary is stored one char per byte.
+    text.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+/// `secs` seconds after `start`, or `None` if that isn't a representable
+/// time (including when `secs` is NaN or infinite).
+fn after(start: OffsetDateTime, secs: f64) -> Option<OffsetDateTime> {
+    start.checked_add(Duration::checked_seconds_f64(secs)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{Message, Topic};
+
+    fn message(id: &str, topic: Topic) -> Vec<u8> {
+        Message::new_with_id(topic, Some(id.to_owned()))
+            .encode_exact()
+            .unwrap()
+    }
+
+    fn read(har: &Value) -> Vec<CapturedFrame> {
+        read_frames(har.to_string().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn reads_binary_http_bodies() {
+        let request = message("R_1_", Topic::TrLogin);
+        let response = message("R_1_", Topic::TdLogin);
+        let har = json!({"log": {"entries": [{
+            "startedDateTime": "2024-05-01T12:00:00Z",
+            "time": 250.0,
+            "request": {"postData": {
+                "mimeType": "application/octet-stream",
+                "text": request.iter().map(|&b| char::from(b)).collect::<String>(),
+            }},
+            "response": {"content": {"encoding": "base64", "text": BASE64.encode(&response)}},
+        }, {
+            "startedDateTime": "2024-05-01T12:00:01Z",
+            "request": {"postData": {"mimeType": "application/json", "text": "{}"}},
+            "response": {"content": {"mimeType": "text/html", "text": "<html>"}},
+        }]}});
+        let frames = read(&har);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            (frames[0].direction, &frames[0].bytes),
+            (Direction::Sent, &request)
+        );
+        assert_eq!(
+            (frames[1].direction, &frames[1].bytes),
+            (Direction::Received, &response)
+        );
+        assert_eq!(
+            frames[1].timestamp - frames[0].timestamp,
+            Duration::milliseconds(250)
+        );
+    }
+
+    #[test]
+    fn reads_binary_websocket_frames_in_both_directions() {
+        let har = json!({"log": {"entries": [{"_webSocketMessages": [
+            {"type": "send", "time": 1.0, "opcode": 2, "data": "AQ=="},
+            {"type": "receive", "time": 2.0, "opcode": 2, "data": "Ag=="},
+            {"type": "receive", "time": 3.0, "opcode": 1, "data": "text"},
+            {"type": "other", "time": 4.0, "opcode": 2, "data": "Aw=="},
+        ]}]}});
+        let frames = read(&har);
+        let frames = frames
+            .iter()
+            .map(|f| (f.direction, f.bytes.as_slice()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            frames,
+            [(Direction::Sent, &[1][..]), (Direction::Received, &[2][..])]
+        );
+    }
+
+    #[test]
+    fn orders_frames_by_time_across_entries() {
+        let har = json!({"log": {"entries": [
+            {"_webSocketMessages": [
+                {"type": "send", "time": 3.0, "opcode": 2, "data": "Aw=="},
+                {"type": "send", "time": 1.0, "opcode": 2, "data": "AQ=="},
+            ]},
+            {"_webSocketMessages": [
+                {"type": "receive", "time": 2.0, "opcode": 2, "data": "Ag=="},
+            ]},
+        ]}});
+        let bytes = read(&har).into_iter().map(|f| f.bytes).collect::<Vec<_>>();
+        assert_eq!(bytes, [[1], [2], [3]]);
+    }
+
+    #[test]
+    fn decodes_request_ids_and_unknown_topics() {
+        let mut unknown = message("R_2_", Topic::TrLogin);
+        // The payload's topic, 1012 + 1 as a varint, becomes 12345 + 1.
+        let topic = unknown.len() - 3;
+        assert_eq!(unknown[topic..topic + 2], [0xf5, 0x07]);
+        unknown.splice(topic..topic + 2, [0xba, 0x60]);
+        let mut frame = message("R_1_", Topic::TrLogin);
+        frame.extend(unknown);
+        let har = json!({"log": {"entries": [{"_webSocketMessages": [
+            {"type": "send", "time": 1.0, "opcode": 2, "data": BASE64.encode(frame)},
+        ]}]}});
+        let messages = read_messages(har.to_string().as_bytes()).unwrap();
+        let messages = messages
+            .iter()
+            .map(|m| (m.message.id.as_deref(), m.message.sdo.topic_id()))
+            .collect::<Vec<_>>();
+        assert_eq!(messages, [(Some("R_1_"), 1012), (Some("R_2_"), 12345)]);
+    }
+
+    #[test]
+    fn skips_frames_with_invalid_times() {
+        let har = r#"{"log": {"entries": [{"_webSocketMessages": [
+            {"type": "send", "time": 1700000000.5, "opcode": 2, "data": "AQI="},
+            {"type": "receive", "time": 1e300, "opcode": 2, "data": "AQI="}
+        ]}]}}"#;
+        let frames = read_frames(har.as_bytes()).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].direction, Direction::Sent);
+        assert_eq!(frames[0].bytes, [1, 2]);
+        assert_eq!(frames[0].timestamp.unix_timestamp(), 1_700_000_000);
+    }
+}
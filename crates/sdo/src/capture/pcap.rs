@@ -0,0 +1,593 @@
+//! Classic libpcap captures (as written by `tcpdump -w`) of unencrypted
+//! websocket traffic.
+//!
+//! TCP streams are reassembled per direction. A connection is only unframed
+//! once the server has accepted its HTTP upgrade, unless
+//! [`PcapConfig::assume_websocket`] is set for captures that start
+//! mid-connection. Binary websocket messages become frames, each timestamped
+//! with the packet that completed it. The pcapng format is not supported.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Read},
+    net::IpAddr,
+};
+
+use time::{Duration, OffsetDateTime};
+
+use super::{
+    decode_frames,
+    websocket::{parse_handshake, Handshake, Unframer, WebSocketMessage},
+    CapturedFrame, CapturedMessage, Direction,
+};
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("unsupported capture format (magic number {0:#010x})")]
+    #[diagnostic(help(
+        "only classic pcap files are supported; convert pcapng with `editcap -F pcap`"
+    ))]
+    UnsupportedFormat(u32),
+    #[error("unsupported link type {0}")]
+    UnsupportedLinkType(u32),
+    #[error("packet of {len} bytes is longer than the capture's snapshot length {snaplen}")]
+    PacketTooLong { len: u32, snaplen: u32 },
+}
+
+/// HTTP messages longer than this are not a websocket upgrade.
+const MAX_HANDSHAKE: usize = 64 * 1024;
+
+/// How to read a capture.
+#[derive(Debug, Clone)]
+pub struct PcapConfig {
+    assume_websocket: bool,
+    max_pending: usize,
+}
+
+impl Default for PcapConfig {
+    fn default() -> Self {
+        Self {
+            assume_websocket: false,
+            max_pending: 1024 * 1024,
+        }
+    }
+}
+
+impl PcapConfig {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Treat connections that don't start with an HTTP upgrade as websocket
+    /// frames, for captures that start after the connection opened. Off by
+    /// default, since any other TCP traffic would be unframed too.
+    #[must_use]
+    pub fn assume_websocket(mut self, assume_websocket: bool) -> Self {
+        self.assume_websocket = assume_websocket;
+        self
+    }
+
+    /// How many bytes to hold back waiting for a lost segment before giving
+    /// up on it. The stream then skips the gap and picks up at the next
+    /// segment, dropping any partly received message. Defaults to 1 MiB.
+    #[must_use]
+    pub fn max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+}
+
+/// Read every binary websocket message in the capture, in time order.
+pub fn read_frames(reader: impl Read) -> Result<Vec<CapturedFrame>, Error> {
+    read_frames_with(reader, &PcapConfig::default())
+}
+
+/// Like [`read_frames`], configured by `config`.
+pub fn read_frames_with(
+    mut reader: impl Read,
+    config: &PcapConfig,
+) -> Result<Vec<CapturedFrame>, Error> {
+    let mut header = [0; 24];
+    reader.read_exact(&mut header)?;
+    let (big_endian, nanos) = match u32::from_le_bytes([header[0], header[1], header[2], header[3]])
+    {
+        0xa1b2_c3d4 => (false, false),
+        0xd4c3_b2a1 => (true, false),
+        0xa1b2_3c4d => (false, true),
+        0x4d3c_b2a1 => (true, true),
+        magic => return Err(Error::UnsupportedFormat(magic)),
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    let snaplen = read_u32(&header[16..20]);
+    let link_type = LinkType::new(read_u32(&header[20..24]))?;
+
+    let mut streams: HashMap<FlowKey, Stream> = HashMap::new();
+    let mut frames = vec![];
+    let mut record = [0; 16];
+    loop {
+        match reader.read_exact(&mut record) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let seconds = read_u32(&record[0..4]);
+        let fraction = read_u32(&record[4..8]);
+        let len = read_u32(&record[8..12]);
+        if len > snaplen {
+            return Err(Error::PacketTooLong { len, snaplen });
+        }
+        let mut packet = vec![0; len as usize];
+        reader.read_exact(&mut packet)?;
+
+        let timestamp = OffsetDateTime::UNIX_EPOCH
+            + Duration::seconds(i64::from(seconds))
+            + if nanos {
+                Duration::nanoseconds(i64::from(fraction))
+            } else {
+                Duration::microseconds(i64::from(fraction))
+            };
+        let Some(segment) = link_type.ip_payload(&packet).and_then(parse_ip) else {
+            continue;
+        };
+        let stream = streams.entry(segment.key).or_default();
+        stream.push(&segment, config);
+        let accepted = stream.handshake(config);
+        stream.unframe(timestamp, &mut frames);
+        // The client's side of the connection carries frames too, once the
+        // server has accepted its upgrade.
+        let peer = streams
+            .get_mut(&segment.key.reversed())
+            .filter(|peer| accepted && peer.phase == Phase::Requested);
+        if let Some(peer) = peer {
+            peer.upgrade();
+            peer.unframe(timestamp, &mut frames);
+        }
+    }
+    frames.sort_by_key(|f| f.timestamp);
+    Ok(frames)
+}
+
+/// Read and decode every SDO message in the capture, in time order.
+pub fn read_messages(reader: impl Read) -> Result<Vec<CapturedMessage>, Error> {
+    Ok(decode_frames(&read_frames(reader)?))
+}
+
+fn direction(message: &WebSocketMessage) -> Direction {
+    if message.masked {
+        Direction::Sent
+    } else {
+        Direction::Received
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LinkType {
+    Null,
+    Ethernet,
+    Raw,
+    LinuxSll,
+    LinuxSll2,
+}
+
+impl LinkType {
+    fn new(link_type: u32) -> Result<Self, Error> {
+        match link_type {
+            0 => Ok(Self::Null),
+            1 => Ok(Self::Ethernet),
+            12 | 101 | 228 | 229 => Ok(Self::Raw),
+            113 => Ok(Self::LinuxSll),
+            276 => Ok(Self::LinuxSll2),
+            other => Err(Error::UnsupportedLinkType(other)),
+        }
+    }
+
+    /// Strip the link layer header, returning the IP packet.
+    fn ip_payload(self, packet: &[u8]) -> Option<&[u8]> {
+        match self {
+            Self::Null => packet.get(4..),
+            Self::Raw => Some(packet),
+            Self::LinuxSll => packet.get(16..),
+            Self::LinuxSll2 => packet.get(20..),
+            Self::Ethernet => {
+                let mut offset = 14;
+                // Skip 802.1Q VLAN tags.
+                while packet.get(offset - 2..offset)? == [0x81, 0x00] {
+                    offset += 4;
+                }
+                packet.get(offset..)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    src: (IpAddr, u16),
+    dst: (IpAddr, u16),
+}
+
+impl FlowKey {
+    /// The other direction of the same connection.
+    fn reversed(self) -> Self {
+        Self {
+            src: self.dst,
+            dst: self.src,
+        }
+    }
+}
+
+struct Segment<'a> {
+    key: FlowKey,
+    seq: u32,
+    syn: bool,
+    payload: &'a [u8],
+}
+
+const PROTOCOL_TCP: u8 = 6;
+
+fn parse_ip(packet: &[u8]) -> Option<Segment<'_>> {
+    let (src, dst, tcp) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(packet[0] & 0x0f) * 4;
+            let total_len = usize::from(u16::from_be_bytes(packet.get(2..4)?.try_into().ok()?));
+            if *packet.get(9)? != PROTOCOL_TCP {
+                return None;
+            }
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            // Ethernet frames may be padded past the end of the IP packet.
+            let end = total_len.min(packet.len());
+            (
+                IpAddr::from(src),
+                IpAddr::from(dst),
+                packet.get(header_len..end)?,
+            )
+        }
+        6 => {
+            if *packet.get(6)? != PROTOCOL_TCP {
+                return None;
+            }
+            let payload_len = usize::from(u16::from_be_bytes(packet.get(4..6)?.try_into().ok()?));
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let end = (40 + payload_len).min(packet.len());
+            (IpAddr::from(src), IpAddr::from(dst), packet.get(40..end)?)
+        }
+        _ => return None,
+    };
+
+    let src_port = u16::from_be_bytes(tcp.get(0..2)?.try_into().ok()?);
+    let dst_port = u16::from_be_bytes(tcp.get(2..4)?.try_into().ok()?);
+    let seq = u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?);
+    let data_offset = usize::from(tcp.get(12)? >> 4) * 4;
+    let flags = *tcp.get(13)?;
+    Some(Segment {
+        key: FlowKey {
+            src: (src, src_port),
+            dst: (dst, dst_port),
+        },
+        seq,
+        syn: flags & 0x02 != 0,
+        payload: tcp.get(data_offset..)?,
+    })
+}
+
+/// How far one direction of a connection has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Phase {
+    /// Waiting for the HTTP message that opens the connection.
+    #[default]
+    Handshake,
+    /// Sent an upgrade request, and waiting for the server to accept it.
+    Requested,
+    /// Carrying websocket frames.
+    Upgraded,
+    /// Not a websocket connection, or lost part of its handshake.
+    Ignored,
+}
+
+/// One direction of a TCP connection.
+#[derive(Default)]
+struct Stream {
+    next_seq: Option<u32>,
+    /// Segments that arrived ahead of a gap, keyed by sequence number.
+    pending: BTreeMap<u32, Vec<u8>>,
+    /// How many bytes are in `pending`.
+    pending_len: usize,
+    phase: Phase,
+    /// Bytes received before the connection was upgraded.
+    http: Vec<u8>,
+    unframer: Unframer,
+}
+
+impl Stream {
+    fn push(&mut self, segment: &Segment<'_>, config: &PcapConfig) {
+        if segment.syn {
+            self.next_seq = Some(segment.seq.wrapping_add(1));
+            return;
+        }
+        if segment.payload.is_empty() {
+            return;
+        }
+        // Captures that start mid-connection pick up from the first segment.
+        let next_seq = *self.next_seq.get_or_insert(segment.seq);
+        if let Some(replaced) = self.pending.insert(segment.seq, segment.payload.to_vec()) {
+            self.pending_len -= replaced.len();
+        }
+        self.pending_len += segment.payload.len();
+        let mut next_seq = self.drain_pending(next_seq);
+        if self.pending_len > config.max_pending {
+            next_seq = self.skip_gap(next_seq);
+        }
+        self.next_seq = Some(next_seq);
+    }
+
+    /// Feed every pending segment that continues the stream onwards,
+    /// trimming retransmitted bytes, and return the new next sequence number.
+    fn drain_pending(&mut self, mut next_seq: u32) -> u32 {
+        loop {
+            let ready = self.pending.iter().find_map(|(seq, payload)| {
+                #[allow(clippy::cast_possible_wrap)]
+                let ahead = seq.wrapping_sub(next_seq) as i32;
+                (ahead <= 0).then_some((*seq, payload.len()))
+            });
+            let Some((seq, len)) = ready else {
+                return next_seq;
+            };
+            let payload = self.pending.remove(&seq).unwrap_or_default();
+            self.pending_len -= len;
+            let seen = next_seq.wrapping_sub(seq) as usize;
+            if seen < len {
+                self.receive(&payload[seen..]);
+                #[allow(clippy::cast_possible_truncation)] // segments are far smaller
+                let fresh = (len - seen) as u32;
+                next_seq = next_seq.wrapping_add(fresh);
+            }
+        }
+    }
+
+    /// Give up on the missing bytes before the first pending segment and
+    /// carry on from there.
+    fn skip_gap(&mut self, next_seq: u32) -> u32 {
+        let Some(resume) = self
+            .pending
+            .keys()
+            .copied()
+            .min_by_key(|seq| seq.wrapping_sub(next_seq))
+        else {
+            return next_seq;
+        };
+        warn!(
+            lost = resume.wrapping_sub(next_seq),
+            "skipping lost tcp segment"
+        );
+        match self.phase {
+            Phase::Upgraded => self.unframer.reset(),
+            Phase::Handshake | Phase::Requested => {
+                self.phase = Phase::Ignored;
+                self.http.clear();
+            }
+            Phase::Ignored => {}
+        }
+        self.drain_pending(resume)
+    }
+
+    fn receive(&mut self, bytes: &[u8]) {
+        match self.phase {
+            Phase::Handshake | Phase::Requested => self.http.extend_from_slice(bytes),
+            Phase::Upgraded => self.unframer.push(bytes),
+            Phase::Ignored => {}
+        }
+    }
+
+    /// Move past the HTTP message that opens the connection once it has
+    /// arrived, returning whether it was the server accepting an upgrade.
+    fn handshake(&mut self, config: &PcapConfig) -> bool {
+        if self.phase != Phase::Handshake {
+            return false;
+        }
+        match parse_handshake(&self.http) {
+            None if self.http.len() > MAX_HANDSHAKE => {
+                self.phase = Phase::Ignored;
+                self.http.clear();
+            }
+            None => {}
+            Some((Handshake::Request, len)) => {
+                self.http.drain(..len);
+                self.phase = Phase::Requested;
+            }
+            Some((Handshake::Accepted, len)) => {
+                self.http.drain(..len);
+                self.upgrade();
+                return true;
+            }
+            Some((Handshake::Other, 0)) if config.assume_websocket => self.upgrade(),
+            Some((Handshake::Other, _)) => {
+                self.phase = Phase::Ignored;
+                self.http.clear();
+            }
+        }
+        false
+    }
+
+    /// Start unframing, beginning with anything received so far.
+    fn upgrade(&mut self) {
+        self.phase = Phase::Upgraded;
+        self.unframer.push(&std::mem::take(&mut self.http));
+    }
+
+    /// Collect every complete binary message as a frame.
+    fn unframe(&mut self, timestamp: OffsetDateTime, frames: &mut Vec<CapturedFrame>) {
+        while let Some(message) = self.unframer.next_message() {
+            if !message.is_binary() {
+                continue;
+            }
+            frames.push(CapturedFrame {
+                timestamp,
+                direction: direction(&message),
+                bytes: message.payload,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: u16 = 50000;
+    const SERVER: u16 = 80;
+
+    /// A classic pcap file with raw IPv4 link type.
+    struct Capture {
+        bytes: Vec<u8>,
+        seq: HashMap<u16, u32>,
+    }
+
+    impl Capture {
+        fn new() -> Self {
+            let mut bytes = vec![];
+            bytes.extend(0xa1b2_c3d4_u32.to_le_bytes());
+            bytes.extend(2_u16.to_le_bytes());
+            bytes.extend(4_u16.to_le_bytes());
+            bytes.extend([0; 8]);
+            bytes.extend(65535_u32.to_le_bytes());
+            bytes.extend(101_u32.to_le_bytes());
+            Self {
+                bytes,
+                seq: HashMap::new(),
+            }
+        }
+
+        /// Send the next `payload` from port `src`, or lose it in transit.
+        fn send(&mut self, src: u16, payload: &[u8], lost: bool) {
+            let dst = if src == CLIENT { SERVER } else { CLIENT };
+            let seq = self.seq.entry(src).or_insert(1000);
+            let mut tcp = vec![];
+            tcp.extend(src.to_be_bytes());
+            tcp.extend(dst.to_be_bytes());
+            tcp.extend(seq.to_be_bytes());
+            tcp.extend([0, 0, 0, 0, 0x50, 0x18, 0, 0, 0, 0, 0, 0]);
+            tcp.extend(payload);
+            *seq += u32::try_from(payload.len()).unwrap();
+            if lost {
+                return;
+            }
+
+            let (src_ip, dst_ip) = if src == CLIENT { (1, 2) } else { (2, 1) };
+            let mut ip = vec![0x45, 0];
+            ip.extend(u16::try_from(20 + tcp.len()).unwrap().to_be_bytes());
+            ip.extend([0, 0, 0, 0, 64, PROTOCOL_TCP, 0, 0]);
+            ip.extend([10, 0, 0, src_ip, 10, 0, 0, dst_ip]);
+            ip.extend(tcp);
+            let len = u32::try_from(ip.len()).unwrap();
+            self.bytes.extend([0; 8]);
+            self.bytes.extend(len.to_le_bytes());
+            self.bytes.extend(len.to_le_bytes());
+            self.bytes.extend(ip);
+        }
+
+        fn handshake(&mut self) {
+            self.send(
+                CLIENT,
+                b"GET / HTTP/1.1\r\nUpgrade: websocket\r\n\r\n",
+                false,
+            );
+            self.send(
+                SERVER,
+                b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n",
+                false,
+            );
+        }
+
+        fn read(&self, config: &PcapConfig) -> Vec<(Direction, Vec<u8>)> {
+            read_frames_with(&self.bytes[..], config)
+                .unwrap()
+                .into_iter()
+                .map(|f| (f.direction, f.bytes))
+                .collect()
+        }
+    }
+
+    fn binary(payload: &[u8], masked: bool) -> Vec<u8> {
+        let mut frame = vec![0x82, u8::try_from(payload.len()).unwrap()];
+        if masked {
+            // A zero mask leaves the payload as it is.
+            frame[1] |= 0x80;
+            frame.extend([0; 4]);
+        }
+        frame.extend(payload);
+        frame
+    }
+
+    #[test]
+    fn unframes_upgraded_connections() {
+        let mut capture = Capture::new();
+        capture.handshake();
+        capture.send(CLIENT, &binary(&[1], true), false);
+        capture.send(SERVER, &binary(&[2], false), false);
+        let frames = capture.read(&PcapConfig::new());
+        assert_eq!(
+            frames,
+            [(Direction::Sent, vec![1]), (Direction::Received, vec![2])]
+        );
+    }
+
+    #[test]
+    fn ignores_connections_without_an_upgrade() {
+        let mut capture = Capture::new();
+        capture.send(CLIENT, &binary(&[1], true), false);
+        capture.send(SERVER, &binary(&[2], false), false);
+        assert!(capture.read(&PcapConfig::new()).is_empty());
+        let frames = capture.read(&PcapConfig::new().assume_websocket(true));
+        assert_eq!(frames.len(), 2);
+    }
+
+    #[test]
+    fn ignores_refused_upgrades() {
+        let mut capture = Capture::new();
+        capture.send(
+            CLIENT,
+            b"GET / HTTP/1.1\r\nUpgrade: websocket\r\n\r\n",
+            false,
+        );
+        capture.send(SERVER, b"HTTP/1.1 403 Forbidden\r\n\r\n", false);
+        capture.send(CLIENT, &binary(&[1], true), false);
+        capture.send(SERVER, &binary(&[2], false), false);
+        assert!(capture.read(&PcapConfig::new()).is_empty());
+    }
+
+    #[test]
+    fn skips_lost_segments() {
+        let mut capture = Capture::new();
+        capture.handshake();
+        capture.send(SERVER, &binary(&[1], false), false);
+        capture.send(SERVER, &binary(&[2], false), true);
+        for i in 3..10 {
+            capture.send(SERVER, &binary(&[i], false), false);
+        }
+        let frames = capture.read(&PcapConfig::new().max_pending(8));
+        let payloads = frames.into_iter().map(|(_, b)| b[0]).collect::<Vec<_>>();
+        // Held back until more than 8 bytes were pending, then resynced.
+        assert_eq!(payloads, [1, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn rejects_packets_longer_than_the_snapshot_length() {
+        let mut capture = Capture::new();
+        capture.bytes[16..20].copy_from_slice(&16_u32.to_le_bytes());
+        capture.send(CLIENT, b"GET / HTTP/1.1\r\n\r\n", false);
+        let error = read_frames(&capture.bytes[..]).unwrap_err();
+        assert!(matches!(error, Error::PacketTooLong { snaplen: 16, .. }));
+    }
+}
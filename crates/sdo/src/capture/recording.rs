@@ -0,0 +1,327 @@
+//! A compact on-disk format for recording sessions, and a replayer.
+//!
+//! A recording starts with the magic bytes `SDOREC`, a version byte and the
+//! recording's start time as a zigzag varint of microseconds since the unix
+//! epoch. Each frame follows as:
+//!
+//! - zigzag varint: microseconds since the start time
+//! - `u8`: direction, `0` for sent and `1` for received
+//! - varint: length of the frame
+//! - the frame's raw bytes
+//!
+//! Frames are stored exactly as they went over the wire, so a recording can
+//! be decoded again after the decoder improves.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    thread,
+    time::Instant,
+};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use integer_encoding::{VarIntReader, VarIntWriter};
+use time::{Duration, OffsetDateTime};
+
+use super::{CapturedFrame, CapturedMessage, Direction};
+
+const MAGIC: &[u8; 6] = b"SDOREC";
+const VERSION: u8 = 1;
+
+/// The longest frame a recording may hold, so a corrupt length can't make us
+/// allocate without bound.
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("not a session recording")]
+    InvalidMagic,
+    #[error("unsupported recording version {0}")]
+    UnsupportedVersion(u8),
+    #[error("invalid direction {0}")]
+    InvalidDirection(u8),
+    #[error("frame of {0} bytes is longer than the maximum")]
+    FrameTooLong(usize),
+    #[error("timestamp out of range")]
+    InvalidTimestamp,
+}
+
+/// Writes frames to a recording as they are sent and received.
+pub struct Recorder<W: Write> {
+    writer: W,
+    start: OffsetDateTime,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Start a recording now.
+    pub fn new(writer: W) -> Result<Self, Error> {
+        Self::with_start(writer, OffsetDateTime::now_utc())
+    }
+
+    /// Start a recording with an explicit start time, e.g. when converting
+    /// an existing capture.
+    pub fn with_start(mut writer: W, start: OffsetDateTime) -> Result<Self, Error> {
+        writer.write_all(MAGIC)?;
+        writer.write_u8(VERSION)?;
+        writer.write_varint(micros(start - OffsetDateTime::UNIX_EPOCH))?;
+        Ok(Self { writer, start })
+    }
+
+    /// Record a frame that was just sent or received.
+    pub fn record(&mut self, direction: Direction, bytes: &[u8]) -> Result<(), Error> {
+        self.record_frame(&CapturedFrame {
+            timestamp: OffsetDateTime::now_utc(),
+            direction,
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    pub fn record_frame(&mut self, frame: &CapturedFrame) -> Result<(), Error> {
+        self.writer
+            .write_varint(micros(frame.timestamp - self.start))?;
+        self.writer.write_u8(match frame.direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        })?;
+        self.writer.write_varint(frame.bytes.len())?;
+        self.writer.write_all(&frame.bytes)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads frames back out of a recording, in the order they were written.
+pub struct Reader<R: Read> {
+    reader: BufReader<R>,
+    start: OffsetDateTime,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(reader: R) -> Result<Self, Error> {
+        let mut reader = BufReader::new(reader);
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let start = after(OffsetDateTime::UNIX_EPOCH, reader.read_varint()?)?;
+        Ok(Self { reader, start })
+    }
+
+    /// When the recording was started.
+    #[must_use]
+    pub fn start(&self) -> OffsetDateTime {
+        self.start
+    }
+
+    /// Decode every frame into messages, skipping frames that don't decode.
+    pub fn messages(self) -> Result<Vec<CapturedMessage>, Error> {
+        let frames = self.collect::<Result<Vec<_>, _>>()?;
+        Ok(super::decode_frames(&frames))
+    }
+
+    fn read_frame(&mut self) -> Result<CapturedFrame, Error> {
+        let offset: i64 = self.reader.read_varint()?;
+        let direction = match self.reader.read_u8()? {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            other => return Err(Error::InvalidDirection(other)),
+        };
+        let len: usize = self.reader.read_varint()?;
+        if len > MAX_FRAME_LEN {
+            return Err(Error::FrameTooLong(len));
+        }
+        let mut bytes = vec![0; len];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(CapturedFrame {
+            timestamp: after(self.start, offset)?,
+            direction,
+            bytes,
+        })
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<CapturedFrame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.reader.fill_buf() {
+            Ok([]) => None,
+            Ok(_) => Some(self.read_frame()),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+/// How fast to replay a recording.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pace {
+    /// With the same gaps between frames as when it was recorded.
+    Original,
+    /// Faster (or slower) than recorded, e.g. `Speed(10.0)` for ten times as
+    /// fast.
+    Speed(f64),
+    /// As fast as the consumer can take frames.
+    Instant,
+}
+
+/// Yields frames at the pace they were recorded at (or scaled), sleeping
+/// the current thread between them.
+pub struct Replayer<I> {
+    frames: I,
+    pace: Pace,
+    /// When replay started, and the timestamp of the first frame.
+    origin: Option<(Instant, OffsetDateTime)>,
+}
+
+impl Pace {
+    /// How many times faster than recorded, or `None` for no waiting.
+    #[must_use]
+    pub fn speed(self) -> Option<f64> {
+        match self {
+            Pace::Original => Some(1.0),
+            Pace::Speed(speed) if speed > 0.0 => Some(speed),
+            Pace::Speed(_) | Pace::Instant => None,
+        }
+    }
+
+    /// How long after `first` to replay a frame recorded at `timestamp`,
+    /// or `None` to replay it straight away.
+    #[must_use]
+    pub fn delay(
+        self,
+        first: OffsetDateTime,
+        timestamp: OffsetDateTime,
+    ) -> Option<std::time::Duration> {
+        let speed = self.speed()?;
+        std::time::Duration::try_from((timestamp - first) / speed).ok()
+    }
+}
+
+impl<I: Iterator<Item = CapturedFrame>> Replayer<I> {
+    pub fn new(frames: impl IntoIterator<IntoIter = I>, pace: Pace) -> Self {
+        Self {
+            frames: frames.into_iter(),
+            pace,
+            origin: None,
+        }
+    }
+}
+
+impl<I: Iterator<Item = CapturedFrame>> Iterator for Replayer<I> {
+    type Item = CapturedFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.frames.next()?;
+        let (started, first) = *self
+            .origin
+            .get_or_insert_with(|| (Instant::now(), frame.timestamp));
+        let Some(due) = self.pace.delay(first, frame.timestamp) else {
+            return Some(frame);
+        };
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            thread::sleep(wait);
+        }
+        Some(frame)
+    }
+}
+
+/// `micros` microseconds after `start`.
+fn after(start: OffsetDateTime, micros: i64) -> Result<OffsetDateTime, Error> {
+    start
+        .checked_add(Duration::microseconds(micros))
+        .ok_or(Error::InvalidTimestamp)
+}
+
+fn micros(duration: Duration) -> i64 {
+    i64::try_from(duration.whole_microseconds()).unwrap_or(i64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start() -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap()
+    }
+
+    fn frame(micros: i64, direction: Direction, bytes: &[u8]) -> CapturedFrame {
+        CapturedFrame {
+            timestamp: start() + Duration::microseconds(micros),
+            direction,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    fn header() -> Vec<u8> {
+        Recorder::with_start(vec![], start()).unwrap().into_inner()
+    }
+
+    #[test]
+    fn round_trips_frames() {
+        let frames = [
+            frame(0, Direction::Sent, &[1, 2, 3]),
+            frame(1_500, Direction::Received, &[]),
+            frame(-7, Direction::Received, &[4; 300]),
+        ];
+        let mut recorder = Recorder::with_start(vec![], start()).unwrap();
+        for frame in &frames {
+            recorder.record_frame(frame).unwrap();
+        }
+        let bytes = recorder.into_inner();
+        let reader = Reader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.start(), start());
+        let read = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(read.len(), frames.len());
+        for (read, frame) in read.iter().zip(&frames) {
+            assert_eq!(read.timestamp, frame.timestamp);
+            assert_eq!(read.direction, frame.direction);
+            assert_eq!(read.bytes, frame.bytes);
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            Reader::new(&b"SDOREX\x01\x00"[..]),
+            Err(Error::InvalidMagic)
+        ));
+        assert!(matches!(
+            Reader::new(&b"SDOREC\x02\x00"[..]),
+            Err(Error::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_overlong_frames() {
+        let mut bytes = header();
+        bytes.extend([0, 1]);
+        bytes.write_varint(MAX_FRAME_LEN + 1).unwrap();
+        let mut reader = Reader::new(&bytes[..]).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(Error::FrameTooLong(len))) if len == MAX_FRAME_LEN + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_timestamps_out_of_range() {
+        let mut bytes = header();
+        bytes.write_varint(i64::MAX).unwrap();
+        bytes.extend([0, 0]);
+        let mut reader = Reader::new(&bytes[..]).unwrap();
+        assert!(matches!(reader.next(), Some(Err(Error::InvalidTimestamp))));
+    }
+}
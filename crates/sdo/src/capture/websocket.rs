@@ -0,0 +1,236 @@
+//! Unframing websocket messages out of a reassembled TCP byte stream.
+
+/// A complete (possibly defragmented) websocket data message.
+#[derive(Debug, Clone)]
+pub struct WebSocketMessage {
+    pub opcode: u8,
+    /// Clients must mask every frame they send and servers must not, so this
+    /// tells us which way the message went.
+    pub masked: bool,
+    pub payload: Vec<u8>,
+}
+
+impl WebSocketMessage {
+    pub const OPCODE_TEXT: u8 = 1;
+    pub const OPCODE_BINARY: u8 = 2;
+
+    #[must_use]
+    pub fn is_binary(&self) -> bool {
+        self.opcode == Self::OPCODE_BINARY
+    }
+}
+
+/// The HTTP message that opens a websocket connection, as seen from one
+/// direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handshake {
+    /// The client's `GET` request.
+    Request,
+    /// A `101 Switching Protocols` response with `Upgrade: websocket`.
+    Accepted,
+    /// Any other HTTP message, or bytes that aren't HTTP at all.
+    Other,
+}
+
+/// Parse the HTTP message at the front of `buf`, returning what it is and
+/// how many bytes it takes up. Bytes that don't start like HTTP are
+/// [`Handshake::Other`] with length 0. Returns `None` until there are enough
+/// bytes to tell.
+#[must_use]
+pub fn parse_handshake(buf: &[u8]) -> Option<(Handshake, usize)> {
+    let starts = |prefix: &[u8]| {
+        let len = prefix.len().min(buf.len());
+        buf[..len] == prefix[..len]
+    };
+    if buf.is_empty() {
+        return None;
+    }
+    if !starts(b"GET ") && !starts(b"HTTP/") {
+        return Some((Handshake::Other, 0));
+    }
+    let end = buf.windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let head = String::from_utf8_lossy(&buf[..end]);
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    if status.starts_with("GET ") {
+        return Some((Handshake::Request, end));
+    }
+    let switching = status.split(' ').nth(1) == Some("101");
+    let upgrade = lines
+        .filter_map(|l| l.split_once(':'))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("upgrade")
+                && value.trim().eq_ignore_ascii_case("websocket")
+        });
+    if switching && upgrade {
+        Some((Handshake::Accepted, end))
+    } else {
+        Some((Handshake::Other, end))
+    }
+}
+
+/// Incrementally splits one direction of a websocket connection into
+/// messages. Feed it the bytes after the HTTP upgrade with
+/// [`Unframer::push`] as they arrive and drain it with
+/// [`Unframer::next_message`].
+#[derive(Debug, Default)]
+pub struct Unframer {
+    buf: Vec<u8>,
+    fragments: Option<WebSocketMessage>,
+}
+
+impl Unframer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Drop any partly received frame or message, after bytes have been
+    /// lost. The next bytes pushed are taken to start a frame.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.fragments = None;
+    }
+
+    /// Get the next complete data message, if enough bytes have arrived.
+    /// Control frames are skipped.
+    pub fn next_message(&mut self) -> Option<WebSocketMessage> {
+        loop {
+            let (frame, len) = parse_frame(&self.buf)?;
+            self.buf.drain(..len);
+            if frame.rsv1 {
+                warn!("skipping compressed websocket frame");
+                continue;
+            }
+            match frame.opcode {
+                0 => {
+                    let Some(ref mut message) = self.fragments else {
+                        warn!("continuation frame without a message to continue");
+                        continue;
+                    };
+                    message.payload.extend(frame.payload);
+                }
+                1 | 2 => {
+                    self.fragments = Some(WebSocketMessage {
+                        opcode: frame.opcode,
+                        masked: frame.masked,
+                        payload: frame.payload,
+                    });
+                }
+                // Close, ping and pong.
+                _ => continue,
+            }
+            if frame.fin {
+                return self.fragments.take();
+            }
+        }
+    }
+}
+
+struct Frame {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    masked: bool,
+    payload: Vec<u8>,
+}
+
+/// Parse one frame from the front of `buf`, returning it and its length.
+fn parse_frame(buf: &[u8]) -> Option<(Frame, usize)> {
+    let [b0, b1, ..] = *buf else {
+        return None;
+    };
+    let masked = b1 & 0x80 != 0;
+    let (len, mut offset) = match b1 & 0x7f {
+        126 => (
+            u64::from(u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?)),
+            4,
+        ),
+        127 => (u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?), 10),
+        len => (u64::from(len), 2),
+    };
+    let mask = if masked {
+        let mask: [u8; 4] = buf.get(offset..offset + 4)?.try_into().ok()?;
+        offset += 4;
+        Some(mask)
+    } else {
+        None
+    };
+    let end = offset.checked_add(usize::try_from(len).ok()?)?;
+    let mut payload = buf.get(offset..end)?.to_vec();
+    if let Some(mask) = mask {
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+    }
+    Some((
+        Frame {
+            fin: b0 & 0x80 != 0,
+            rsv1: b0 & 0x40 != 0,
+            opcode: b0 & 0x0f,
+            masked,
+            payload,
+        },
+        end,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_handshakes() {
+        let request = b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\n\r\n\x82";
+        assert_eq!(
+            parse_handshake(request),
+            Some((Handshake::Request, request.len() - 1))
+        );
+        let accepted = b"HTTP/1.1 101 Switching Protocols\r\nupgrade: WebSocket\r\n\r\n";
+        assert_eq!(
+            parse_handshake(accepted),
+            Some((Handshake::Accepted, accepted.len()))
+        );
+        let refused = b"HTTP/1.1 400 Bad Request\r\nUpgrade: websocket\r\n\r\n";
+        assert_eq!(
+            parse_handshake(refused),
+            Some((Handshake::Other, refused.len()))
+        );
+        assert_eq!(parse_handshake(b"HTTP/1.1 101 Switching"), None);
+        assert_eq!(parse_handshake(b"HT"), None);
+        assert_eq!(
+            parse_handshake(b"\x82\x01\x00"),
+            Some((Handshake::Other, 0))
+        );
+    }
+
+    #[test]
+    fn joins_fragments_and_unmasks() {
+        let mask = [1, 2, 3, 4];
+        let mut unframer = Unframer::new();
+        // Unfinished binary frame, a ping, then the masked continuation.
+        unframer.push(&[0x02, 0x02, 10, 11]);
+        unframer.push(&[0x89, 0x00]);
+        unframer.push(&[0x80, 0x82, 1, 2, 3, 4, 0x0c ^ mask[0], 0x0d ^ mask[1]]);
+        let message = unframer.next_message().unwrap();
+        assert!(message.is_binary());
+        assert_eq!(message.payload, [10, 11, 12, 13]);
+        assert!(unframer.next_message().is_none());
+    }
+
+    #[test]
+    fn waits_for_whole_frames() {
+        let mut unframer = Unframer::new();
+        unframer.push(&[0x82, 0x7e, 0x01]);
+        assert!(unframer.next_message().is_none());
+        unframer.push(&[0x00]);
+        unframer.push(&[7; 255]);
+        assert!(unframer.next_message().is_none());
+        unframer.push(&[7]);
+        assert_eq!(unframer.next_message().unwrap().payload, [7; 256]);
+    }
+}
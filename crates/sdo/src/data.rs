@@ -23,6 +23,94 @@ pub enum Data {
 }
 
 impl Data {
+    /// Whether each row is null.
+    #[must_use]
+    pub fn null_rows(&self) -> Vec<bool> {
+        fn nulls<T>(rows: &[Option<T>]) -> Vec<bool> {
+            rows.iter().map(Option::is_none).collect()
+        }
+        match self {
+            Data::StringW(v) => nulls(v),
+            Data::Bool(v) => nulls(v),
+            Data::Long(v) | Data::Short(v) => nulls(v),
+            Data::LongLong(v) => nulls(v),
+            Data::AsciiString(v) => nulls(v),
+            Data::SDO(v) => nulls(v),
+            Data::Double(v) => nulls(v),
+            Data::Float(v) => nulls(v),
+            Data::DateTime(v) => nulls(v),
+            Data::Char(v) => nulls(v),
+            Data::Binary(v) => nulls(v),
+            Data::Unknown => vec![],
+        }
+    }
+
+    /// Just row `i`, if there is one.
+    #[must_use]
+    pub fn row(&self, i: usize) -> Option<Data> {
+        fn row<T: Clone>(rows: &[Option<T>], i: usize) -> Option<Vec<Option<T>>> {
+            rows.get(i).map(|row| vec![row.clone()])
+        }
+        Some(match self {
+            Data::StringW(v) => Data::StringW(row(v, i)?),
+            Data::Bool(v) => Data::Bool(row(v, i)?),
+            Data::Long(v) => Data::Long(row(v, i)?),
+            Data::Short(v) => Data::Short(row(v, i)?),
+            Data::LongLong(v) => Data::LongLong(row(v, i)?),
+            Data::AsciiString(v) => Data::AsciiString(row(v, i)?),
+            Data::SDO(v) => Data::SDO(row(v, i)?),
+            Data::Double(v) => Data::Double(row(v, i)?),
+            Data::Float(v) => Data::Float(row(v, i)?),
+            Data::DateTime(v) => Data::DateTime(row(v, i)?),
+            Data::Char(v) => Data::Char(row(v, i)?),
+            Data::Binary(v) => Data::Binary(row(v, i)?),
+            Data::Unknown => return None,
+        })
+    }
+
+    /// `rows` null rows of the same type as this.
+    #[must_use]
+    pub fn nulls(&self, rows: usize) -> Data {
+        fn nulls<T>(rows: usize) -> Vec<Option<T>> {
+            std::iter::repeat_with(|| None).take(rows).collect()
+        }
+        match self {
+            Data::StringW(_) => Data::StringW(nulls(rows)),
+            Data::Bool(_) => Data::Bool(nulls(rows)),
+            Data::Long(_) => Data::Long(nulls(rows)),
+            Data::Short(_) => Data::Short(nulls(rows)),
+            Data::LongLong(_) => Data::LongLong(nulls(rows)),
+            Data::AsciiString(_) => Data::AsciiString(nulls(rows)),
+            Data::SDO(_) => Data::SDO(nulls(rows)),
+            Data::Double(_) => Data::Double(nulls(rows)),
+            Data::Float(_) => Data::Float(nulls(rows)),
+            Data::DateTime(_) => Data::DateTime(nulls(rows)),
+            Data::Char(_) => Data::Char(nulls(rows)),
+            Data::Binary(_) => Data::Binary(nulls(rows)),
+            Data::Unknown => Data::Unknown,
+        }
+    }
+
+    /// Append `other`'s rows after ours. Fails, leaving both untouched, if
+    /// they hold different types.
+    pub fn extend(&mut self, other: Data) -> Result<(), Data> {
+        match (self, other) {
+            (Data::StringW(a), Data::StringW(b)) => a.extend(b),
+            (Data::Bool(a), Data::Bool(b)) => a.extend(b),
+            (Data::Long(a), Data::Long(b)) | (Data::Short(a), Data::Short(b)) => a.extend(b),
+            (Data::LongLong(a), Data::LongLong(b)) => a.extend(b),
+            (Data::AsciiString(a), Data::AsciiString(b)) => a.extend(b),
+            (Data::SDO(a), Data::SDO(b)) => a.extend(b),
+            (Data::Double(a), Data::Double(b)) => a.extend(b),
+            (Data::Float(a), Data::Float(b)) => a.extend(b),
+            (Data::DateTime(a), Data::DateTime(b)) => a.extend(b),
+            (Data::Char(a), Data::Char(b)) => a.extend(b),
+            (Data::Binary(a), Data::Binary(b)) => a.extend(b),
+            (_, other) => return Err(other),
+        }
+        Ok(())
+    }
+
     pub fn to_string(&self) -> Option<String> {
         match self {
             Data::StringW(s) => Some(s.iter().filter_map(Option::as_ref).join(", ")),
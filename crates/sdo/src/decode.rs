@@ -1,4 +1,4 @@
-use std::{io::{Cursor, Read}, mem::transmute};
+use std::io::{Cursor, Read};
 
 use byteorder::{ReadBytesExt, BigEndian, LittleEndian};
 use integer_encoding::{VarIntReader, VarIntWriter};
@@ -6,7 +6,7 @@ use time::{OffsetDateTime, macros::datetime};
 
 use super::{AsciiString, WireType, DataType, Topic, Data, SDO, Message, Field};
 
-static REF_DATETIME: OffsetDateTime = datetime!(2014-01-01 0:00 UTC);
+pub(crate) static REF_DATETIME: OffsetDateTime = datetime!(2014-01-01 0:00 UTC);
 
 #[derive(Debug, thiserror::Error, miette::Diagnostic)]
 pub enum Error {
@@ -22,6 +22,27 @@ pub enum Error {
     MissingDateTimePrecision,
     #[error("invalid datetime precision")]
     InvalidDateTimePrecision,
+    #[error("datetime out of range")]
+    InvalidDateTime,
+    #[error("invalid utf-8 string")]
+    InvalidUtf8(#[from] std::string::FromUtf8Error),
+    #[error("invalid utf-16 string")]
+    InvalidUtf16(#[from] std::string::FromUtf16Error),
+    #[error("length {0} runs past the end of the buffer")]
+    LengthOutOfBounds(u64),
+}
+
+/// Read `len` bytes, checking first that there are that many left so a
+/// corrupt length can't ask for a huge allocation.
+pub(crate) fn read_len(buf: &mut Cursor<Vec<u8>>, len: u64) -> Result<Vec<u8>, Error> {
+    let remaining = (buf.get_ref().len() as u64).saturating_sub(buf.position());
+    if len > remaining {
+        return Err(Error::LengthOutOfBounds(len));
+    }
+    #[allow(clippy::cast_possible_truncation)] // no more than the buffer's length
+    let mut bytes = vec![0; len as usize];
+    buf.read_exact(&mut bytes)?;
+    Ok(bytes)
 }
 
 fn decode_field(buf: &mut Cursor<Vec<u8>>, single_row: bool) -> Result<Option<(Field, Option<Data>)>, Error> {
@@ -109,7 +130,7 @@ fn decode_field_header(buf: &mut Cursor<Vec<u8>>, field: &mut Field) -> Result<b
 }
 
 #[instrument(skip(buf, field))]
-fn decode_field_values(buf: &mut Cursor<Vec<u8>>, field: &Field) -> Result<Data, Error> {
+pub(crate) fn decode_field_values(buf: &mut Cursor<Vec<u8>>, field: &Field) -> Result<Data, Error> {
     match field.data_type {
         DataType::StringW => Ok(Data::StringW(read_rows::<String>(buf, field)?)),
         DataType::EncString | DataType::String => Ok(Data::AsciiString(read_rows::<AsciiString>(buf, field)?)),
@@ -140,8 +161,7 @@ impl ReadType for AsciiString {
     fn read_bytes(buf: &mut Cursor<Vec<u8>>, _header: &Field) -> Result<Box<Self>, Error> {
         let len: u32 = buf.read_varint()?;
         if len > 0 {
-            let mut str = vec![0; len as usize];
-            buf.read_exact(&mut str)?;
+            let str = read_len(buf, u64::from(len))?;
             return Ok(Box::new(AsciiString(String::from_utf8_lossy(&str).to_string())))
         }
         Ok(Box::default())
@@ -152,9 +172,7 @@ impl ReadType for Vec<u8> {
     fn read_bytes(buf: &mut Cursor<Vec<u8>>, _header: &Field) -> Result<Box<Vec<u8>>, Error> {
         let len: u32 = buf.read_varint()?;
         if len > 0 {
-            let mut bytes = vec![0; len as usize];
-            buf.read_exact(&mut bytes)?;
-            return Ok(Box::new(bytes))
+            return Ok(Box::new(read_len(buf, u64::from(len))?))
         }
         Ok(Box::default())
     }
@@ -169,25 +187,27 @@ impl ReadType for OffsetDateTime {
             return Err(Error::MissingDateTimePrecision)
         }
         let ms: u64 = buf.read_varint()?;
-        match extra_info.first() {
+        let ticks = i64::try_from(ms).map_err(|_| Error::InvalidDateTime);
+        let offset = match extra_info.first() {
             Some(3) => { // o.DateTimePrecision.Seconds:
-                Ok(Box::new(REF_DATETIME + time::Duration::seconds(ms as i64)))
+                time::Duration::seconds(ticks?)
             },
             Some(2) => { // o.DateTimePrecision.Milliseconds:
-                Ok(Box::new(REF_DATETIME + time::Duration::milliseconds(ms as i64)))
+                time::Duration::milliseconds(ticks?)
             },
             Some(1) => { // o.DateTimePrecision.Microseconds:
                 if ms >= i64::MAX as u64 - 1 {
                     warn!(?ms, ?extra_info, "received invalid datetime");
                     return Ok(Box::new(REF_DATETIME));
                 }
-                Ok(Box::new(REF_DATETIME + time::Duration::microseconds(ms as i64))) // FIXME
+                time::Duration::microseconds(ticks?) // FIXME
             },
             Some(0) => { // o.DateTimePrecision.Nanoseconds:
-                Ok(Box::new(REF_DATETIME + time::Duration::microseconds((ms / 1000) as i64)))
+                time::Duration::microseconds(ticks? / 1000)
             },
-            _ => Err(Error::InvalidDateTimePrecision)
-        }
+            _ => return Err(Error::InvalidDateTimePrecision)
+        };
+        REF_DATETIME.checked_add(offset).map(Box::new).ok_or(Error::InvalidDateTime)
     }
 }
 
@@ -200,17 +220,14 @@ impl ReadType for String {
             match buf.read_u8()? {
                 0 => {
                     // trace!(?len, remaining = buf.remaining_slice().len(), "decoding utf8 string");
-                    let mut str = vec![0; len as usize];
-                    buf.read_exact(&mut str)?;
-                    return Ok(Box::new(String::from_utf8(str).unwrap()))
+                    let str = read_len(buf, u64::from(len))?;
+                    return Ok(Box::new(String::from_utf8(str)?))
                 },
                 1 => {
                     // trace!(?len, "decoding utf16 string");
-                    let mut str = vec![];
-                    for _ in 0..len {
-                        str.push(buf.read_u16::<BigEndian>().unwrap());
-                    }
-                    return Ok(Box::new(String::from_utf16(&str).unwrap()))
+                    let bytes = read_len(buf, u64::from(len) * 2)?;
+                    let str = bytes.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect::<Vec<_>>();
+                    return Ok(Box::new(String::from_utf16(&str)?))
                 },
                 o => {
                     warn!("The unicode encoding type '{o}' is invalid and could not be decoded.");
@@ -319,9 +336,9 @@ pub fn read_sdo(buf: &mut Cursor<Vec<u8>>) -> Result<SDO, Error> {
     if o > 0 {
         buf.set_position(buf.position() + u64::from(o));
     }
-    let topic = buf.read_varint::<u32>()? as i32 - 1;
-    let topic: Topic = unsafe { transmute(topic) };
+    let topic = i64::from(buf.read_varint::<u32>()?) - 1;
     trace!(?single_row, ?version, ?topic, ?o);
+    let mut sdo = SDO::with_topic_id(topic);
     let mut fields = vec![];
     while buf.position() < buf.get_ref().len() as u64 {
         // decode field
@@ -332,10 +349,8 @@ pub fn read_sdo(buf: &mut Cursor<Vec<u8>>) -> Result<SDO, Error> {
             break;
         }
     }
-    Ok(SDO {
-        topic,
-        fields
-    })
+    sdo.fields = fields;
+    Ok(sdo)
 }
 
 #[instrument(skip(buf))]
@@ -345,10 +360,10 @@ pub fn read_msg(buf: &mut Cursor<Vec<u8>>) -> Result<Message, Error> {
     trace!(?header);
     let id = if header.topic == Topic::UndefinedTopic {
         if let Some(field) = header.fields.get(0) {
-            match field.1.as_ref().unwrap() {
-                Data::StringW(ref msg_id) => Some(msg_id.get(0).and_then(Option::as_ref).unwrap().to_string()),
-                Data::AsciiString(ref msg_id) => msg_id.get(0).and_then(Option::as_ref).map(|s| s.0.to_string()),
-                Data::Short(ref msg_id) => Some(msg_id.get(0).and_then(Option::as_ref).unwrap().to_string()),
+            match &field.1 {
+                Some(Data::StringW(ref msg_id)) => msg_id.get(0).and_then(Option::as_ref).map(ToString::to_string),
+                Some(Data::AsciiString(ref msg_id)) => msg_id.get(0).and_then(Option::as_ref).map(|s| s.0.to_string()),
+                Some(Data::Short(ref msg_id)) => msg_id.get(0).and_then(Option::as_ref).map(ToString::to_string),
                 _ => {
                     warn!(data_type = ?field.0.data_type);
                     return Err(Error::InvalidHeaderId);
@@ -369,4 +384,138 @@ pub fn read_msg(buf: &mut Cursor<Vec<u8>>) -> Result<Message, Error> {
     })
 }
 
+/// Values read back to back from one buffer, e.g. the messages in a frame.
+/// Ends after the last value, or after the first error since nothing past it
+/// can be found.
+pub struct ReadAll<T> {
+    buf: Cursor<Vec<u8>>,
+    read: fn(&mut Cursor<Vec<u8>>) -> Result<T, Error>,
+    failed: bool,
+}
+
+impl<T> ReadAll<T> {
+    /// How far into the buffer reading has got.
+    #[must_use]
+    pub fn position(&self) -> u64 {
+        self.buf.position()
+    }
+}
+
+impl<T> Iterator for ReadAll<T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.buf.position() >= self.buf.get_ref().len() as u64 {
+            return None;
+        }
+        let value = (self.read)(&mut self.buf);
+        self.failed = value.is_err();
+        Some(value)
+    }
+}
+
+/// Every message in `bytes`, which may hold several back to back.
+#[must_use]
+pub fn read_msgs(bytes: &[u8]) -> ReadAll<Message> {
+    ReadAll { buf: Cursor::new(bytes.to_vec()), read: read_msg, failed: false }
+}
+
+/// Every SDO in `bytes`, which may hold several back to back.
+#[must_use]
+pub fn read_sdos(bytes: &[u8]) -> ReadAll<SDO> {
+    ReadAll { buf: Cursor::new(bytes.to_vec()), read: read_sdo, failed: false }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
+    use super::*;
+    use crate::{fields::{USER_NAME, IS_PINGABLE}, Message};
+
+    fn sample() -> Vec<u8> {
+        let mut message = Message::new_with_id(Topic::TrLogin, Some("R_1_".to_owned()));
+        message.sdo.push_string_w(USER_NAME, Some("user".to_owned()));
+        message.sdo.push_bool(IS_PINGABLE, vec![Some(true), None, Some(false)]);
+        message.encode().unwrap()
+    }
+
+    #[test]
+    fn random_bytes_do_not_panic() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..10_000 {
+            let len = rng.gen_range(0..64);
+            let bytes = (0..len).map(|_| rng.gen()).collect::<Vec<u8>>();
+            let _ = read_msg(&mut Cursor::new(bytes.clone()));
+            let _ = read_sdo(&mut Cursor::new(bytes));
+        }
+    }
+
+    #[test]
+    fn corrupted_messages_do_not_panic() {
+        let message = sample();
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..10_000 {
+            let mut bytes = message.clone();
+            for _ in 0..rng.gen_range(1..4) {
+                let i = rng.gen_range(0..bytes.len());
+                bytes[i] = rng.gen();
+            }
+            bytes.truncate(rng.gen_range(0..=bytes.len()));
+            let _ = read_msg(&mut Cursor::new(bytes));
+        }
+    }
+
+    #[test]
+    fn unknown_topics_keep_their_id() {
+        // Single row SDO on topic 5, which isn't defined.
+        let bytes = vec![0x10, 6, 0];
+        let sdo = read_sdo(&mut Cursor::new(bytes.clone())).unwrap();
+        assert_eq!((sdo.topic, sdo.topic_id()), (Topic::Unknown, 5));
+        assert_eq!(sdo.encode().unwrap(), [0x17, 6, 0]);
+        assert_eq!(SDO::with_topic_id(1686).topic, Topic::TdIosOrders);
+        assert!(SDO::new(Topic::Unknown).encode().is_err());
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let mut sdo = SDO::new(Topic::TrLogin);
+        sdo.push_string_w(USER_NAME, Some("ab".to_owned()));
+        let mut bytes = sdo.encode().unwrap();
+        // The last character, before the terminating zero.
+        let last = bytes.len() - 2;
+        bytes[last] = 0xff;
+        let error = read_sdo(&mut Cursor::new(bytes)).unwrap_err();
+        assert!(matches!(error, Error::InvalidUtf8(_)));
+    }
+
+    #[test]
+    fn huge_length_is_an_error() {
+        // A single row TrLogin SDO with a binary field claiming 2^32 - 1 bytes.
+        let bytes = vec![0x10, 0xf5, 0x07, 0x30, 0x02, 0xff, 0xff, 0xff, 0xff, 0x0f];
+        let error = read_sdo(&mut Cursor::new(bytes)).unwrap_err();
+        assert!(matches!(error, Error::LengthOutOfBounds(0xffff_ffff)));
+    }
+
+    #[test]
+    fn reads_messages_back_to_back() {
+        let mut bytes = sample();
+        bytes.extend(sample());
+        let messages = read_msgs(&bytes).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|m| m.id.as_deref() == Some("R_1_")));
+    }
+
+    #[test]
+    fn reading_back_to_back_stops_at_the_first_error() {
+        let mut bytes = sample();
+        // A binary field claiming more bytes than there are.
+        bytes.extend([0x10, 0xf5, 0x07, 0x30, 0x02, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+        bytes.extend(sample());
+        let mut messages = read_msgs(&bytes);
+        assert!(messages.next().unwrap().is_ok());
+        assert!(messages.next().unwrap().is_err());
+        assert!(messages.next().is_none());
+        assert!(read_sdos(&[]).next().is_none());
+    }
+}
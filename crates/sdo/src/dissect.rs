@@ -0,0 +1,456 @@
+//! Byte-level dissection of encoded SDOs, for reverse-engineering topics.
+//!
+//! [`dissect_sdo`] and [`dissect_msg`] walk the encoding the same way as
+//! [`crate::decode::read_sdo`], but instead of building an SDO they record
+//! what every byte range was: header bits, topic, each field's type byte,
+//! field id, row count, null bitmap, extra info and values.
+
+use std::{fmt::Write as _, io::Cursor};
+
+use byteorder::ReadBytesExt;
+use integer_encoding::VarIntReader;
+use serde_json::{json, Value};
+
+use crate::{
+    decode::{decode_field_values, read_len, Error},
+    fields, json, DataType, Field, Topic, WireType,
+};
+
+/// What a span of bytes encodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    SdoHeader,
+    Extension,
+    Topic,
+    FieldType,
+    FieldId,
+    RowCount,
+    NullFlags,
+    ExtraInfo,
+    Value,
+    End,
+}
+
+impl SpanKind {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SpanKind::SdoHeader => "sdo header",
+            SpanKind::Extension => "extension",
+            SpanKind::Topic => "topic",
+            SpanKind::FieldType => "field type",
+            SpanKind::FieldId => "field id",
+            SpanKind::RowCount => "row count",
+            SpanKind::NullFlags => "null flags",
+            SpanKind::ExtraInfo => "extra info",
+            SpanKind::Value => "value",
+            SpanKind::End => "end of sdo",
+        }
+    }
+}
+
+/// A range of bytes and what it was decoded as. `depth` is how deeply
+/// nested the SDO the span belongs to is.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub start: u64,
+    pub end: u64,
+    pub depth: usize,
+    pub kind: SpanKind,
+    pub description: String,
+}
+
+/// Dissection stopped partway through, usually because the input is
+/// truncated or corrupt. `spans` covers everything before `offset`, which is
+/// often the interesting part.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("dissection stopped at offset {offset:#x}")]
+pub struct Incomplete {
+    pub spans: Vec<Span>,
+    pub offset: u64,
+    #[source]
+    pub error: Error,
+}
+
+/// Dissect a single encoded SDO.
+pub fn dissect_sdo(bytes: &[u8]) -> Result<Vec<Span>, Incomplete> {
+    let mut dissector = Dissector::new(bytes);
+    let result = dissector.sdo(0);
+    dissector.finish(result)
+}
+
+/// Dissect an encoded message: the header SDO followed by the payload SDO.
+pub fn dissect_msg(bytes: &[u8]) -> Result<Vec<Span>, Incomplete> {
+    let mut dissector = Dissector::new(bytes);
+    let result = dissector.sdo(0).and_then(|()| dissector.sdo(0));
+    dissector.finish(result)
+}
+
+/// Render spans as aligned text: offset, bytes, then an indented description.
+#[must_use]
+pub fn to_text(bytes: &[u8], spans: &[Span]) -> String {
+    // Long values are cut short so the description column stays aligned.
+    const MAX_BYTES: usize = 8;
+
+    let mut out = String::new();
+    for span in spans {
+        // Spans from other input may not fit this one; show what does.
+        let start = usize::try_from(span.start).map_or(bytes.len(), |s| s.min(bytes.len()));
+        let end = usize::try_from(span.end).map_or(bytes.len(), |e| e.clamp(start, bytes.len()));
+        let range = &bytes[start..end];
+        let mut hex = range
+            .iter()
+            .take(MAX_BYTES)
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if range.len() > MAX_BYTES {
+            hex.push_str(" ..");
+        }
+        let _ = write!(
+            out,
+            "{:06x}  {hex:<width$}  {}{}",
+            span.start,
+            "  ".repeat(span.depth),
+            span.kind.as_str(),
+            width = MAX_BYTES * 3 + 2,
+        );
+        if span.description.is_empty() {
+            out.push('\n');
+        } else {
+            let _ = writeln!(out, ": {}", span.description);
+        }
+    }
+    out
+}
+
+/// Render spans as a JSON array of `{start, end, depth, kind, description}`.
+#[must_use]
+pub fn to_json(spans: &[Span]) -> Value {
+    spans
+        .iter()
+        .map(|span| {
+            json!({
+                "start": span.start,
+                "end": span.end,
+                "depth": span.depth,
+                "kind": span.kind.as_str(),
+                "description": span.description,
+            })
+        })
+        .collect()
+}
+
+struct Dissector {
+    buf: Cursor<Vec<u8>>,
+    spans: Vec<Span>,
+}
+
+impl Dissector {
+    fn new(bytes: &[u8]) -> Self {
+        Self {
+            buf: Cursor::new(bytes.to_vec()),
+            spans: vec![],
+        }
+    }
+
+    fn finish(self, result: Result<(), Error>) -> Result<Vec<Span>, Incomplete> {
+        match result {
+            Ok(()) => Ok(self.spans),
+            Err(error) => Err(Incomplete {
+                offset: self.buf.position(),
+                spans: self.spans,
+                error,
+            }),
+        }
+    }
+
+    fn push(&mut self, start: u64, depth: usize, kind: SpanKind, description: String) {
+        self.spans.push(Span {
+            start,
+            end: self.buf.position(),
+            depth,
+            kind,
+            description,
+        });
+    }
+
+    fn sdo(&mut self, depth: usize) -> Result<(), Error> {
+        let start = self.buf.position();
+        let i = self.buf.read_u8()?;
+        let version = i & 0x0f;
+        let single_row = 16 == 16 & i;
+        let extensions = i >> 5;
+        self.push(
+            start,
+            depth,
+            SpanKind::SdoHeader,
+            format!(
+                "version {version}, {}, {extensions} extension bytes",
+                if single_row {
+                    "single row"
+                } else {
+                    "multi-row"
+                }
+            ),
+        );
+        if extensions > 0 {
+            let start = self.buf.position();
+            read_len(&mut self.buf, u64::from(extensions))?;
+            self.push(start, depth, SpanKind::Extension, "skipped".to_owned());
+        }
+
+        let start = self.buf.position();
+        let topic = i64::from(self.buf.read_varint::<u32>()?) - 1;
+        let description = i32::try_from(topic)
+            .ok()
+            .and_then(Topic::from_id)
+            .map_or_else(
+                || format!("unknown ({topic})"),
+                |t| format!("{t:?} ({topic})"),
+            );
+        self.push(start, depth, SpanKind::Topic, description);
+
+        while self.buf.position() < self.buf.get_ref().len() as u64 {
+            if !self.field(depth, single_row)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Mirrors `decode::decode_field_header` and `decode_field_values`,
+    /// recording a span for each part.
+    fn field(&mut self, depth: usize, single_row: bool) -> Result<bool, Error> {
+        let mut field = Field::new(single_row);
+
+        let start = self.buf.position();
+        let r1 = self.buf.read_u8()?;
+        if r1 == 0 {
+            self.push(start, depth, SpanKind::End, String::new());
+            return Ok(false);
+        }
+        field.data_type = DataType::from(r1 >> 3);
+        field.wire_type = WireType::from((r1 >> 1) & 3);
+        let has_null = 1 == (1 & r1);
+        self.push(
+            start,
+            depth + 1,
+            SpanKind::FieldType,
+            format!(
+                "{:?}, {:?}{}",
+                field.data_type,
+                field.wire_type,
+                if has_null { ", has nulls" } else { "" }
+            ),
+        );
+
+        let start = self.buf.position();
+        let r2: u32 = self.buf.read_varint()?;
+        let id = r2 >> 1;
+        let has_extra_info = 1 == (1 & r2);
+        field.field_id = Some(id);
+        self.push(
+            start,
+            depth + 1,
+            SpanKind::FieldId,
+            format!(
+                "{} ({id}){}",
+                fields::name(id).unwrap_or("unknown"),
+                if has_extra_info {
+                    ", has extra info"
+                } else {
+                    ""
+                }
+            ),
+        );
+
+        if single_row {
+            if has_null {
+                field.null_flags = Some(vec![128]);
+            }
+        } else {
+            let start = self.buf.position();
+            field.rows = self.buf.read_varint()?;
+            self.push(start, depth + 1, SpanKind::RowCount, field.rows.to_string());
+            if has_null {
+                let start = self.buf.position();
+                let flags = read_len(&mut self.buf, u64::from(field.rows.div_ceil(8)))?;
+                let nulls = (0..field.rows)
+                    .filter(|i| is_null(&flags, *i))
+                    .map(|i| i.to_string())
+                    .collect::<Vec<_>>();
+                self.push(
+                    start,
+                    depth + 1,
+                    SpanKind::NullFlags,
+                    format!("null rows [{}]", nulls.join(", ")),
+                );
+                field.null_flags = Some(flags);
+            }
+        }
+
+        if has_extra_info {
+            let start = self.buf.position();
+            let len: u32 = self.buf.read_varint()?;
+            let extra_info = read_len(&mut self.buf, u64::from(len))?;
+            self.push(
+                start,
+                depth + 1,
+                SpanKind::ExtraInfo,
+                format!("{extra_info:?}"),
+            );
+            field.extra_info = Some(extra_info).filter(|e| !e.is_empty());
+        }
+
+        self.values(depth + 1, &field)?;
+        Ok(true)
+    }
+
+    fn values(&mut self, depth: usize, field: &Field) -> Result<(), Error> {
+        // Booleans are bit-packed across rows, so they get a single span.
+        if field.data_type == DataType::Boolean {
+            let start = self.buf.position();
+            let data = decode_field_values(&mut self.buf, field)?;
+            self.push(start, depth, SpanKind::Value, describe(&data));
+            return Ok(());
+        }
+
+        let mut row = field.clone();
+        row.rows = 1;
+        row.null_flags = None;
+        for i in 0..field.rows {
+            if field
+                .null_flags
+                .as_ref()
+                .is_some_and(|flags| is_null(flags, i))
+            {
+                continue;
+            }
+            let start = self.buf.position();
+            if field.data_type == DataType::SDO {
+                // The row's span goes before the nested SDO's own spans and
+                // covers all of them, even if the nested SDO is cut short.
+                let index = self.spans.len();
+                let result = self.sdo(depth + 1);
+                self.spans.insert(
+                    index,
+                    Span {
+                        start,
+                        end: self.buf.position(),
+                        depth,
+                        kind: SpanKind::Value,
+                        description: format!("[{i}] SDO"),
+                    },
+                );
+                result?;
+            } else {
+                let data = decode_field_values(&mut self.buf, &row)?;
+                self.push(
+                    start,
+                    depth,
+                    SpanKind::Value,
+                    format!("[{i}] {}", describe(&data)),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_null(flags: &[u8], row: u32) -> bool {
+    usize::try_from(row / 8)
+        .ok()
+        .and_then(|byte| flags.get(byte))
+        .is_some_and(|f| f & (1 << (7 - (row % 8))) != 0)
+}
+
+fn describe(data: &crate::Data) -> String {
+    json::data_to_json(data).1.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fields::{ORDER_NUMBER, SECURITY_CODE},
+        SDO,
+    };
+
+    fn order() -> SDO {
+        let mut inner = SDO::new(Topic::TdIosOrders);
+        inner.push_string_w(SECURITY_CODE, Some("BHP".to_owned()));
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        sdo.push_long(ORDER_NUMBER, vec![Some(1), None]);
+        sdo.push_sdo(fields::ORDER_DETAILS, vec![Some(inner)]);
+        sdo
+    }
+
+    #[test]
+    fn spans_cover_the_whole_sdo_in_order() {
+        let bytes = order().encode().unwrap();
+        let spans = dissect_sdo(&bytes).unwrap();
+        assert_eq!(spans[0].start, 0);
+        assert_eq!(spans.last().unwrap().end, bytes.len() as u64);
+        assert!(spans.iter().all(|s| s.start <= s.end));
+        assert!(spans.windows(2).all(|w| w[0].start <= w[1].start));
+        assert!(spans
+            .iter()
+            .any(|s| s.kind == SpanKind::NullFlags && s.description == "null rows [1]"));
+    }
+
+    #[test]
+    fn nested_sdo_rows_span_the_nested_sdo() {
+        let bytes = order().encode().unwrap();
+        let spans = dissect_sdo(&bytes).unwrap();
+        let row = spans
+            .iter()
+            .position(|s| s.description == "[0] SDO")
+            .unwrap();
+        let nested = spans[row + 1..]
+            .iter()
+            .take_while(|s| s.depth > spans[row].depth)
+            .collect::<Vec<_>>();
+        assert_eq!(nested[0].kind, SpanKind::SdoHeader);
+        assert_eq!(nested[0].start, spans[row].start);
+        assert_eq!(nested.last().unwrap().kind, SpanKind::End);
+        assert_eq!(nested.last().unwrap().end, spans[row].end);
+    }
+
+    #[test]
+    fn truncated_input_keeps_the_spans_before_the_error() {
+        let bytes = order().encode().unwrap();
+        let full = dissect_sdo(&bytes).unwrap();
+        let cut = full[full.len() / 2].end;
+        let truncated = &bytes[..=usize::try_from(cut).unwrap()];
+        let incomplete = dissect_sdo(truncated).unwrap_err();
+        assert!(incomplete.offset <= truncated.len() as u64);
+        assert!(incomplete.spans.len() >= full.len() / 2);
+        assert!(incomplete.spans.len() < full.len());
+        // The text rendering copes with the partial spans.
+        assert!(!to_text(truncated, &incomplete.spans).is_empty());
+    }
+
+    #[test]
+    fn corrupt_lengths_are_errors() {
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        sdo.push_long(ORDER_NUMBER, vec![Some(1), Some(2)]);
+        let mut bytes = sdo.encode().unwrap();
+        let spans = dissect_sdo(&bytes).unwrap();
+        let span = |kind| spans.iter().find(|s| s.kind == kind).unwrap();
+        let offset = |at: u64| usize::try_from(at).unwrap();
+        // Claim u32::MAX rows with nulls, which would need 512 MiB of flags.
+        bytes[offset(span(SpanKind::FieldType).start)] |= 1;
+        let rows = span(SpanKind::RowCount);
+        bytes.splice(
+            offset(rows.start)..offset(rows.end),
+            [0xff, 0xff, 0xff, 0xff, 0x0f],
+        );
+        let incomplete = dissect_sdo(&bytes).unwrap_err();
+        assert!(matches!(incomplete.error, Error::LengthOutOfBounds(_)));
+        assert!(incomplete
+            .spans
+            .last()
+            .is_some_and(|s| s.kind == SpanKind::RowCount));
+    }
+}
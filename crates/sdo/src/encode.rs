@@ -1,83 +1,179 @@
 use std::io::Write;
 
-use byteorder::WriteBytesExt;
+use byteorder::{WriteBytesExt, BigEndian, LittleEndian};
 use integer_encoding::VarIntWriter;
+use time::OffsetDateTime;
 
-use super::{Topic, Message, SDO, Field, Data, DataType, fields::{PAGE_SIZE, TIMEOUT}};
+use super::{Topic, Message, SDO, Field, Data, DataType, WireType, decode::REF_DATETIME, fields::{PAGE_SIZE, REQUEST_ID, TIMEOUT}, util::format_timeout};
 
 #[derive(Debug, thiserror::Error, miette::Diagnostic)]
 pub enum Error {
     #[error("io error")]
     Io(#[from] std::io::Error),
+    #[error("datetime {0} is before 2014-01-01 or too far after it")]
+    InvalidDateTime(OffsetDateTime),
+    #[error("char {0:?} doesn't fit in a byte")]
+    InvalidChar(char),
+    #[error("{0} isn't a whole number that fits in a varint")]
+    InvalidVarint(f64),
+    #[error("{0} rows is more than a field can hold")]
+    TooManyRows(usize),
+    #[error("topic {0} can't be encoded")]
+    InvalidTopic(i64),
 }
 
 pub fn encode_field(header: &Field, data: &Option<Data>) -> Result<Vec<u8>, Error> {
+    encode_field_rows(header, data, true)
+}
+
+/// Encode a field. Outside of a single row SDO the row count and null flags
+/// are written too, which lets a field carry more than one value.
+pub fn encode_field_rows(header: &Field, data: &Option<Data>, single_row: bool) -> Result<Vec<u8>, Error> {
     let mut buf = vec![];
-    let has_null = false;
+    let extra_info = encode_field_header(&mut buf, header, data.as_ref(), single_row)?;
+    if let Some(data) = data {
+        encode_values(&mut buf, header, extra_info.as_deref(), data)?;
+    }
+    Ok(buf)
+}
+
+/// Write the type, id, row count, null flags and extra info, returning the
+/// extra info the values are encoded with.
+fn encode_field_header(buf: &mut Vec<u8>, header: &Field, data: Option<&Data>, single_row: bool) -> Result<Option<Vec<u8>>, Error> {
+    let nulls = data.map_or_else(|| vec![true], Data::null_rows);
+    let has_null = nulls.contains(&true);
     buf.write_u8(((header.data_type as u8) << 3) + ((header.wire_type as u8) << 1) + u8::from(has_null))?;
 
-    buf.write_varint((header.field_id.unwrap_or(0) << 1) + u32::from(header.data_type == DataType::DateTime))?;
+    let extra_info = match header.extra_info {
+        Some(ref extra_info) if !extra_info.is_empty() => Some(extra_info.clone()),
+        // Datetimes need a precision; default to milliseconds.
+        _ if header.data_type == DataType::DateTime => Some(vec![2]),
+        _ => None,
+    };
+    buf.write_varint((header.field_id.unwrap_or(0) << 1) + u32::from(extra_info.is_some()))?;
 
-    match &data {
-        Some(Data::StringW(array)) => {
+    if !single_row {
+        buf.write_varint(u32::try_from(nulls.len()).map_err(|_| Error::TooManyRows(nulls.len()))?)?;
+        if has_null {
+            let mut flags = vec![0u8; nulls.len().div_ceil(8)];
+            for (i, _) in nulls.iter().enumerate().filter(|(_, null)| **null) {
+                flags[i / 8] |= 1 << (7 - (i % 8));
+            }
+            buf.write_all(&flags)?;
+        }
+    }
+    if let Some(ref extra_info) = extra_info {
+        buf.write_varint(extra_info.len())?;
+        buf.write_all(extra_info)?;
+    }
+    Ok(extra_info)
+}
+
+fn encode_values(buf: &mut Vec<u8>, header: &Field, extra_info: Option<&[u8]>, data: &Data) -> Result<(), Error> {
+    match data {
+        Data::StringW(array) => {
             for str in array.iter().flatten() {
                 buf.write_varint(str.len() + 1)?;
                 buf.write_u8(0)?;
                 buf.write_all(str.as_bytes())?;
             }
         },
-        Some(Data::AsciiString(array)) => {
+        Data::AsciiString(array) => {
             for str in array.iter().flatten() {
                 buf.write_varint(str.0.len() + 1)?;
                 buf.write_all(str.0.as_bytes())?;
             }
         },
-        Some(Data::Long(array) | Data::Short(array)) => {
+        Data::Long(array) | Data::Short(array) => {
             for value in array.iter().flatten() {
                 buf.write_varint(**value)?;
             }
         },
-        Some(Data::SDO(array)) => {
+        Data::SDO(array) => {
             for sdo in array.iter().flatten() {
                 buf.write_all(&sdo.encode()?)?;
             }
         },
-        Some(Data::Bool(array)) => {
-            // buf.write_u8(0x28);
-            let mut bytes = vec![];
-            let mut n = 0;
-            let mut s = 0u8;
-            for (_r, value) in array.iter().enumerate() {
-                if let Some(value) = value {
-                    if n > 0 && n % 8 == 0 {
-                        bytes.push(s);
-                        s = 0;
-                        n = 0;
-                    }
-                    s += u8::from(*value) << (7 - (n % 8));
-                    n += 1;
+        Data::Bool(array) => buf.write_all(&pack_bools(array))?,
+        Data::LongLong(array) => {
+            for value in array.iter().flatten() {
+                buf.write_varint(**value)?;
+            }
+        },
+        Data::Double(array) => {
+            for value in array.iter().flatten() {
+                if header.wire_type == WireType::Varint {
+                    buf.write_varint(float_varint(**value)?)?;
+                } else {
+                    buf.write_f64::<LittleEndian>(**value)?;
                 }
             }
-            if n > 0 {
-                bytes.push(s);
+        },
+        Data::Float(array) => {
+            for value in array.iter().flatten() {
+                if header.wire_type == WireType::Varint {
+                    buf.write_varint(float_varint(f64::from(**value))?)?;
+                } else {
+                    buf.write_f32::<BigEndian>(**value)?;
+                }
+            }
+        },
+        Data::DateTime(array) => {
+            for value in array.iter().flatten() {
+                let since = **value - REF_DATETIME;
+                let ticks = match extra_info {
+                    Some([3]) => i128::from(since.whole_seconds()),
+                    Some([1]) => since.whole_microseconds(),
+                    Some([0]) => since.whole_nanoseconds(),
+                    _ => since.whole_milliseconds(),
+                };
+                buf.write_varint(u64::try_from(ticks).map_err(|_| Error::InvalidDateTime(**value))?)?;
+            }
+        },
+        Data::Char(array) => {
+            for value in array.iter().flatten() {
+                buf.write_u8(u8::try_from(**value).map_err(|_| Error::InvalidChar(**value))?)?;
             }
-            // buf.write_varint(bytes.len());
-            for b in bytes {
-                buf.write_u8(b)?;
+        },
+        Data::Binary(array) => {
+            for bytes in array.iter().flatten() {
+                buf.write_varint(bytes.len())?;
+                buf.write_all(bytes)?;
             }
-        }
-        type_ => warn!(?type_, "unknown type")
+        },
+        Data::Unknown => warn!("unknown type"),
+    }
+    Ok(())
+}
+
+/// Booleans are packed eight to a byte, most significant bit first, skipping
+/// nulls.
+fn pack_bools(array: &[Option<bool>]) -> Vec<u8> {
+    let values = array.iter().flatten().collect::<Vec<_>>();
+    values.chunks(8).map(|chunk| chunk.iter().enumerate().fold(0, |byte, (n, value)| byte | (u8::from(**value) << (7 - n)))).collect()
+}
+
+/// Varint floats are whole numbers that fit in a `u32`, as that's how they
+/// are decoded.
+fn float_varint(value: f64) -> Result<u32, Error> {
+    if value.fract() == 0.0 && (0.0..=f64::from(u32::MAX)).contains(&value) {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // checked above
+        Ok(value as u32)
+    } else {
+        Err(Error::InvalidVarint(value))
     }
-    Ok(buf)
 }
 
 impl SDO {
     pub fn encode(&self) -> Result<Vec<u8>, Error> {
         let mut buf = vec![];
-        buf.write_u8(0x17)?;
-        buf.write_varint((self.topic as i32 + 1) as u32)?;
+        // Only fall back to the multi-row layout when a field needs it.
+        let single_row = self.fields.iter().all(|(_, data)| data.as_ref().map_or(1, |d| d.null_rows().len()) == 1);
+        buf.write_u8(if single_row { 0x17 } else { 0x07 })?;
+        let topic = self.topic_id();
+        buf.write_varint(u32::try_from(topic + 1).map_err(|_| Error::InvalidTopic(topic))?)?;
         for (header, data) in &self.fields {
-            buf.write_all(&encode_field(header, data)?)?;
+            buf.write_all(&encode_field_rows(header, data, single_row)?)?;
         }
         buf.write_u8(0)?;
         Ok(buf)
@@ -85,20 +181,88 @@ impl SDO {
 }
 
 impl Message {
+    /// Encode a request, adding `TIMEOUT` and `PAGE_SIZE` to the payload.
     pub fn encode(&self) -> Result<Vec<u8>, Error> {
-        let mut buf = vec![];
-        let mut header_sdo = SDO::new(Topic::UndefinedTopic);
-        header_sdo.push_string_w(74, vec![self.id.clone()]);
-        buf.write_all(&header_sdo.encode()?)?;
-
         let mut payload = self.sdo.clone();
-        if self.timeout.is_some() {
-            payload.push_string_w(TIMEOUT, self.timeout.clone());
+        if let Some(timeout) = self.timeout {
+            payload.push_string_w(TIMEOUT, Some(format_timeout(timeout)));
         }
         payload.push_string_w(PAGE_SIZE, Some(self.page_size.unwrap_or(1000).to_string()));
-        buf.write_all(&payload.encode()?)?;
+        encode_msg(self.id.clone(), &payload)
+    }
 
-        Ok(buf)
+    /// Encode the header and payload exactly as they are, e.g. for replies
+    /// or to round-trip a decoded message.
+    pub fn encode_exact(&self) -> Result<Vec<u8>, Error> {
+        encode_msg(self.id.clone(), &self.sdo)
     }
 }
 
+fn encode_msg(id: Option<String>, payload: &SDO) -> Result<Vec<u8>, Error> {
+    let mut header_sdo = SDO::new(Topic::UndefinedTopic);
+    header_sdo.push_string_w(REQUEST_ID, vec![id]);
+    let mut buf = header_sdo.encode()?;
+    buf.write_all(&payload.encode()?)?;
+    Ok(buf)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use time::macros::datetime;
+
+    use super::*;
+    use crate::{decode::read_sdo, fields::ORDER_NUMBER};
+
+    fn encode_data(data: Data) -> Result<Vec<u8>, Error> {
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        sdo.push_data(ORDER_NUMBER, data);
+        sdo.encode()
+    }
+
+    fn round_trip(data: Data) -> Data {
+        let bytes = encode_data(data).unwrap();
+        read_sdo(&mut Cursor::new(bytes)).unwrap().get_field(ORDER_NUMBER).unwrap().clone()
+    }
+
+    #[test]
+    fn datetimes_before_2014_are_errors() {
+        let before = datetime!(2013-12-31 23:59 UTC);
+        assert!(matches!(encode_data(Data::DateTime(vec![Some(Box::new(before))])), Err(Error::InvalidDateTime(_))));
+        let after = datetime!(2024-05-01 12:30 UTC);
+        assert_eq!(format!("{:?}", round_trip(Data::DateTime(vec![Some(Box::new(after))]))), format!("{:?}", Data::DateTime(vec![Some(Box::new(after))])));
+    }
+
+    #[test]
+    fn chars_must_fit_in_a_byte() {
+        assert!(matches!(encode_data(Data::Char(vec![Some(Box::new('€'))])), Err(Error::InvalidChar('€'))));
+        assert!(matches!(round_trip(Data::Char(vec![Some(Box::new('é'))])), Data::Char(c) if *c[0].as_deref().unwrap() == 'é'));
+    }
+
+    #[test]
+    fn varint_floats_must_be_whole() {
+        let field = |value| {
+            let mut sdo = SDO::new(Topic::TdIosOrders);
+            let data = Data::Double(vec![Some(Box::new(value))]);
+            let mut header = Field::for_data(ORDER_NUMBER, &data);
+            header.wire_type = WireType::Varint;
+            sdo.fields.push((header, Some(data)));
+            sdo.encode()
+        };
+        assert!(field(42.0).is_ok());
+        assert!(matches!(field(0.5), Err(Error::InvalidVarint(_))));
+        assert!(matches!(field(-1.0), Err(Error::InvalidVarint(_))));
+        assert!(matches!(field(f64::from(u32::MAX) + 1.0), Err(Error::InvalidVarint(_))));
+    }
+
+    #[test]
+    fn packs_bools_across_bytes_skipping_nulls() {
+        let mut bools = vec![Some(true); 10];
+        bools[1] = None;
+        bools[2] = Some(false);
+        assert_eq!(pack_bools(&bools), [0b1011_1111, 0b1000_0000]);
+        assert!(matches!(round_trip(Data::Bool(bools.clone())), Data::Bool(b) if b == bools));
+    }
+}
@@ -0,0 +1,150 @@
+//! Errors reported by the server.
+//!
+//! A request the server can't handle is answered on `TdError` instead of its
+//! usual topic, with the same request id.
+
+use std::fmt;
+
+use crate::{
+    fields::{ERROR_MESSAGE, ERROR_MESSAGE_JSON, ERROR_NAME, ERROR_NUMBER, STATUS_DESCRIPTION},
+    request::{Error, FromField, FromSdo},
+    Topic, SDO,
+};
+
+/// An error the server sent on `TdError`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SdoServerError {
+    pub number: Option<u32>,
+    pub name: Option<String>,
+    pub message: Option<String>,
+    /// `ERROR_MESSAGE_JSON`, parsed. Kept as a string if it isn't valid
+    /// JSON.
+    pub details: Option<serde_json::Value>,
+    pub status_description: Option<String>,
+}
+
+impl SdoServerError {
+    /// The error `sdo` carries, if it is a `TdError`.
+    #[must_use]
+    pub fn from_response(sdo: &SDO) -> Option<Self> {
+        (sdo.topic == Topic::TdError).then(|| Self::read(sdo))
+    }
+
+    fn read(sdo: &SDO) -> Self {
+        let string = |field| Option::<String>::read_field(sdo, field).ok().flatten();
+        let details = string(ERROR_MESSAGE_JSON).map(|json| {
+            serde_json::from_str(&json).unwrap_or_else(|error| {
+                warn!(%error, "invalid error json");
+                serde_json::Value::String(json)
+            })
+        });
+        Self {
+            number: Option::<u32>::read_field(sdo, ERROR_NUMBER).ok().flatten(),
+            name: string(ERROR_NAME),
+            message: string(ERROR_MESSAGE),
+            details,
+            status_description: string(STATUS_DESCRIPTION),
+        }
+    }
+}
+
+/// Every field is optional, so this never fails.
+impl FromSdo for SdoServerError {
+    fn from_sdo(sdo: &SDO) -> Result<Self, Error> {
+        Ok(Self::read(sdo))
+    }
+}
+
+impl fmt::Display for SdoServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "server error")?;
+        if let Some(number) = self.number {
+            write!(f, " {number}")?;
+        }
+        if let Some(name) = &self.name {
+            write!(f, " ({name})")?;
+        }
+        if let Some(message) = self.message.as_ref().or(self.status_description.as_ref()) {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SdoServerError {}
+
+impl miette::Diagnostic for SdoServerError {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        match (&self.name, self.number) {
+            (Some(name), _) => Some(Box::new(name)),
+            (None, Some(number)) => Some(Box::new(number)),
+            (None, None) => None,
+        }
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        // Already shown if there's no message.
+        self.message.as_ref()?;
+        self.status_description
+            .as_ref()
+            .map(|status| Box::new(status) as Box<dyn fmt::Display>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::Diagnostic;
+
+    use super::*;
+
+    fn td_error() -> SDO {
+        let mut sdo = SDO::new(Topic::TdError);
+        sdo.push_long(ERROR_NUMBER, Some(42));
+        sdo.push_string_w(STATUS_DESCRIPTION, Some("try again".to_owned()));
+        sdo
+    }
+
+    #[test]
+    fn only_td_error_responses_are_errors() {
+        assert!(SdoServerError::from_response(&SDO::new(Topic::TdLogin)).is_none());
+        assert!(SdoServerError::from_response(&SDO::new(Topic::TdError)).is_some());
+    }
+
+    #[test]
+    fn reads_every_field() {
+        let mut sdo = td_error();
+        sdo.push_string_w(ERROR_NAME, Some("Busy".to_owned()));
+        sdo.push_string_w(ERROR_MESSAGE, Some("server busy".to_owned()));
+        sdo.push_string_w(ERROR_MESSAGE_JSON, Some(r#"{"retry": 5}"#.to_owned()));
+        let error = SdoServerError::from_response(&sdo).unwrap();
+        assert_eq!(error.details, Some(serde_json::json!({"retry": 5})));
+        assert_eq!(error.to_string(), "server error 42 (Busy): server busy");
+        assert_eq!(error.code().unwrap().to_string(), "Busy");
+        assert_eq!(error.help().unwrap().to_string(), "try again");
+    }
+
+    #[test]
+    fn falls_back_to_the_status_without_a_message() {
+        let error = SdoServerError::from_response(&td_error()).unwrap();
+        assert_eq!(error.message, None);
+        assert_eq!(error.to_string(), "server error 42: try again");
+        assert_eq!(error.code().unwrap().to_string(), "42");
+        assert!(error.help().is_none());
+
+        let error = SdoServerError::from_response(&SDO::new(Topic::TdError)).unwrap();
+        assert_eq!(error, SdoServerError::default());
+        assert_eq!(error.to_string(), "server error");
+        assert!(error.code().is_none());
+    }
+
+    #[test]
+    fn keeps_invalid_json_details_as_a_string() {
+        let mut sdo = td_error();
+        sdo.push_string_w(ERROR_MESSAGE_JSON, Some("not json".to_owned()));
+        let error = SdoServerError::from_response(&sdo).unwrap();
+        assert_eq!(
+            error.details,
+            Some(serde_json::Value::String("not json".to_owned()))
+        );
+    }
+}
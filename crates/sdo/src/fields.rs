@@ -1,285 +1,309 @@
-pub const IS_PINGABLE: u32 = 7574;
-pub const PRODUCT_FULL_VERSION: u32 = 490;
-
-pub const USER_NAME: u32 = 77;
-pub const COMPANY_NAME: u32 = 486;
-pub const CLIENT_TYPE: u32 = 489;
-pub const ACS_LOGIN_TOKEN: u32 = 6140;
-pub const PASSWORD: u32 = 78;
-
-pub const PAGE_SIZE: u32 = 1316;
-pub const TIMEOUT: u32 = 3804;
-
-pub const ERROR_NAME: u32 = 3741;
-pub const SEQ_NO: u32 = 494;
-
-pub const EXCHANGE: u32 = 5;
-pub const DATA_SOURCE: u32 = 87;
-
-pub const SESSION_TYPE: u32 = 2955;
-
-pub const AMEND_IF_EXISTS: u32 = 7123;
-
-pub const ACCOUNT_CODE: u32 = 75;
-pub const ACCOUNT_NAME: u32 = 264;
-pub const ACCOUNT_DESIGNATION: u32 = 5683;
-pub const ACCOUNT_ID: u32 = 76;
-pub const ACCOUNT_GROUPS: u32 = 848;
-pub const ORGANISATION: u32 = 3508;
-
-pub const ACCESS_MODE: u32 = 3433;
-pub const FILTER_BY: u32 = 791;
-pub const FILTER_MODE: u32 = 3556;
-pub const FILTER_TEXT: u32 = 7113;
-pub const ACCOUNT_LINK_MODE: u32 = 2131;
-pub const INCLUDE_INACTIVE: u32 = 5788;
-pub const ONLY_IN_GROUP: u32 = 6588;
-
-pub const EXEMPT_FROM_ACCOUNT_MASTER_LIMITS: u32 = 8968;
-
-pub const COMMAND_TEXT: u32 = 4889;
-pub const PAGE_DIRECTION: u32 = 1935;
-
-pub const IOS_NAME: u32 = 940;
-pub const IOS_ID: u32 = 944;
-pub const SERVICE_ID: u32 = 1703;
-pub const USER_PROFILE_CODE: u32 = 4503;
-pub const SERVICE_KEY: u32 = 3799;
-pub const LOGGED_IN: u32 = 3156;
-pub const ACCOUNT_GROUP: u32 = 6588;
-
-pub const WATCH_KEY: u32 = 7831;
-pub const HINT_WATCH_KEYS: u32 = 974;
-pub const WATCH_REQUEST_ID: u32 = 7832;
-
-pub const DESTINATION: u32 = 2064;
-pub const ATTRIBUTE_CATEGORY_NUMBER: u32 = 4542;
-pub const ATTRIBUTE_CODE: u32 = 3511;
-pub const ATTRIBUTE_DESCRIPTION: u32 = 3512;
-pub const ATTRIBUTE_DATA_TYPE_NUMBER: u32 = 1938;
-pub const ATTRIBUTE_VALUE_RANGE: u32 = 4698;
-pub const ATTRIBUTE_DEFAULT_VALUE: u32 = 601;
-pub const ATTRIBUTE_HIDDEN: u32 = 5861;
-pub const ATTRIBUTE_READ_ONLY: u32 = 1557;
-pub const ATTRIBUTE_LONG_DESCRIPTION: u32 = 7718;
-pub const ATTRIBUTE_FLAGS_MASK1: u32 = 3971;
-pub const ATTRIBUTE_FLAGS_MASK2: u32 = 8012;
-pub const ENTRY_TYPE: u32 = 4955;
-pub const ATTRIBUTE_NOT_AMENDABLE: u32 = 3838;
-pub const INPUT_WIRE_VALUE: u32 = 9220;
-pub const OUTPUT_WIRE_VALUE: u32 = 9221;
-pub const RESTRICTED_TO_SIDE: u32 = 10502;
-pub const RESTRICTED_TO_LEG_SIDE: u32 = 10501;
-pub const SEND_EXTERNAL_FLAG_BIT_MASK: u32 = 10460;
-pub const ATTRIBUTE_PROPERTY_VALUE: u32 = 10507;
-pub const ATTRIBUTE_SET_BY_DEFAULT: u32 = 470;
-pub const LOCALIZATION_CONTEXT: u32 = 9839;
-pub const ATTRIBUTE_VALUE_LIST: u32 = 5541;
-pub const ATTRIBUTE_DISABLED: u32 = 3157;
-
-pub const PORTFOLIO_CODE: u32 = 5047;
-pub const PORTFOLIO_NAME: u32 = 4136;
-pub const INCLUDE_REMOVED_LINKS: u32 = 3567;
-pub const PORTFOLIO_CASH_CODE: u32 = 462;
-pub const LIMIT_GROUP: u32 = 7132;
-pub const COMMISSION_GROUP: u32 = 529;
-pub const MARGIN_LENDER_NAME: u32 = 3906;
-pub const CFD_PROVIDER_NAME: u32 = 7107;
-pub const OPTION_CATEGORY: u32 = 7439;
-pub const FEE_GROUP: u32 = 7539;
-pub const UPLOAD_SOURCE: u32 = 7363;
-pub const SETTLEMENT_METHOD: u32 = 4124;
-pub const ADVISOR_NAME: u32 = 4456;
-pub const MARKET_MAKER_NAME: u32 = 8167;
-pub const MARGIN_COVER_DISPLAY_STATUS: u32 = 10005;
-pub const MARGIN_COVER_STATUS: u32 = 10004;
-pub const MARGIN_COVER_STATUS_REASON: u32 = 9964;
-pub const PORTFOLIO_EMAIL_ADDRESS: u32 = 9949;
-pub const ACCOUNT_EXECUTIVE_EMAIL_ADDRESS: u32 = 9950;
-pub const MARGIN_COVER_STATUS_UPDATE_DATETIME: u32 = 10389;
-pub const SHARING_CASH_ACCOUNT: u32 = 1714;
-
-pub const ACCESS_TYPE: u32 = 262;
-pub const LINK_REMOVED: u32 = 3567;
-pub const LINK_SEQUENCE_NUMBER: u32 = 7022;
-
-pub const INCLUDE_PORTFOLIOS_WITH_SAME_CASH_ACCOUNT: u32 = 10439;
-
-pub const ATTRIBUTE_ACCOUNT_CODE: u32 = 5754;
-
-pub const PORTFOLIO_CASH_NAME: u32 = 4415;
-pub const VERSION_STAMP: u32 = 2001;
-pub const CREATE_DATETIME: u32 = 4278;
-pub const UPDATE_DATETIME: u32 = 4279;
-pub const CURRENCY_CODE: u32 = 4984;
-pub const CASH_BALANCE: u32 = 7146;
-pub const UNSETTLED_BUY_VALUE: u32 = 1971;
-pub const UNSETTLED_BUY_CHARGES: u32 = 7297;
-pub const UNSETTLED_SELL_VALUE: u32 = 1972;
-pub const UNSETTLED_SELL_CHARGES: u32 = 7298;
-pub const YESTERDAY_EQUITY_SELL_VALUE: u32 = 3932;
-pub const YESTERDAY_EQUITY_SELL_CHARGES: u32 = 7299;
-pub const IN_MARKET_BUY_VALUE: u32 = 4936;
-pub const IN_MARKET_SELL_VALUE: u32 = 4937;
-pub const NET_CASH: u32 = 1102;
-pub const OPTION_UNSETTLED_BUY_VALUE: u32 = 6099;
-pub const OPTION_UNSETTLED_BUY_CHARGES: u32 = 7504;
-pub const OPTION_UNSETTLED_SELL_VALUE: u32 = 3931;
-pub const OPTION_UNSETTLED_SELL_CHARGES: u32 = 7505;
-pub const OPTION_COLLATERAL_VALUE: u32 = 6083;
-pub const OPTION_CLOSING_BUYS: u32 = 4277;
-pub const OPTION_PREMIUM_MARGIN: u32 = 1468;
-pub const OPTION_RISK_MARGIN: u32 = 1640;
-pub const OPTION_TOTAL_MARGIN: u32 = 6082;
-pub const OPTION_MARGIN_CASH: u32 = 3910;
-pub const OPTION_NET_CASH: u32 = 3897;
-pub const CLEARING_HOUSE_MARGIN: u32 = 8490;
-pub const EXTERNAL_VALUE: u32 = 8621;
-pub const NET_UNSETTLED_BUY_VALUE_TODAY: u32 = 9887;
-pub const NET_UNSETTLED_SELL_VALUE_TODAY: u32 = 9888;
-pub const NET_UNSETTLED_VALUE_TODAY: u32 = 9889;
-pub const GLV: u32 = 6029;
-pub const FREE_EQUITY: u32 = 6030;
-pub const TOTAL_INITIAL_MARGIN: u32 = 10455;
-pub const TOTAL_CFD_REALISED_PROFIT: u32 = 10456;
-pub const TOTAL_CFD_UNREALISED_PROFIT: u32 = 10457;
-pub const TOTAL_CFD_COLLATERAL_VALUE: u32 = 10458;
-pub const TOTAL_NON_CFD_MARKET_VALUE: u32 = 10459;
-pub const REALIZED_LOSS_START_OF_DAY_VALUE: u32 = 10692;
-pub const MARGIN_LENDER_TOTAL_FINANCED_VALUE: u32 = 10757;
-pub const TRUST_BALANCE: u32 = 10976;
-pub const TOTAL_CFD_REALIZED_PROFIT_IN_SETTLEMENT_CURRENCY: u32 = 11186;
-pub const ACCRUED_INTEREST: u32 = 5838;
-pub const FACILITY_LIMIT: u32 = 4819;
-pub const MULTI_SETTLEMENT_CALCULATION_METHOD: u32 = 11663;
-pub const DEFAULT_CASH_SETTLEMENT_DAYS: u32 = 11664;
-
-pub const INCLUDE_POSITIONS_FROM_PORTFOLIOS_WITH_SAME_CASH_ACCOUNT: u32 = 10439;
-
-pub const SECURITY_CODE: u32 = 4;
-pub const SETTLEMENT_CURRENCY: u32 = 7741;
-pub const ACTUAL_VOLUME: u32 = 7183;
-pub const AVAILABLE_VOLUME: u32 = 344;
-pub const MARKET_VALUE: u32 = 1081;
-
-pub const TOTAL_PROFIT: u32 = 7246;
-pub const TODAY_PROFIT: u32 = 1508;
-pub const CLOSED_PROFIT: u32 = 6910;
-
-pub const BOARD: u32 = 88;
-pub const ASK_LEVEL_MAX: u32 = 23;
-pub const BID_LEVEL_MAX: u32 = 36;
-pub const SECURITY_TEXT: u32 = 7576;
-
-pub const BID_COUNT: u32 = 37;
-pub const BID_PRICE: u32 = 40;
-pub const BID_VOLUME: u32 = 41;
-pub const BID_ORDER_TYPE: u32 = 57059;
-pub const BID_ORDER_NUMBER: u32 = 3164;
-pub const BID_ACTION: u32 = 32;
-pub const BID_DATA_SOURCE: u32 = 6852;
-
-pub const ASK_COUNT: u32 = 24;
-pub const ASK_PRICE: u32 = 27;
-pub const ASK_VOLUME: u32 = 28;
-pub const ASK_ORDER_TYPE: u32 = 57050;
-pub const ASK_ORDER_NUMBER: u32 = 3165;
-pub const ASK_ACTION: u32 = 19;
-pub const ASK_DATA_SOURCE: u32 = 6853;
-
-pub const SEC_ID: u32 = 3;
-
-pub const TRADE_DATE: u32 = 52;
-pub const FROM_TRADE_TIME: u32 = 1763;
-pub const TO_TRADE_TIME: u32 = 709;
-pub const TRADE_VOLUME: u32 = 2007;
-pub const TRADE_VOLUME_OPERATOR: u32 = 2259;
-pub const TRADE_PRICE: u32 = 1480;
-pub const TRADE_PRICE_OPERATOR: u32 = 2303;
-pub const TRADE_VALUE: u32 = 49;
-pub const TRADE_VALUE_OPERATOR: u32 = 16;
-pub const BUY_BROKER_NUMBER: u32 = 43;
-pub const SELL_BROKER_NUMBER: u32 = 31;
-
-pub const CURRENT_DAY_TRADES_ONLY: u32 = 1843;
-
-pub const ORDER_FILTER: u32 = 2299;
-pub const ORDER_GROUP: u32 = 480;
-pub const BACK_OFFICE_STATUS: u32 = 7188;
-pub const DESTINATION_EXCLUDE: u32 = 8400;
-pub const RETRIEVE_SECURITY_DESCRIPTION: u32 = 11757;
-pub const SECURITY_TYPE_RANGE: u32 = 5311;
-pub const ORDER_FILTER_CUSTOM_DAYS: u32 = 590;
-
-pub const ROOT_PARENT_ORDER_NUMBER: u32 = 6640;
-pub const ORDER_NUMBER: u32 = 1264;
-pub const PARENT_ORDER_NUMBER: u32 = 45513;
-pub const SUB_DESTINATION: u32 = 4509;
-pub const BUY_OR_SELL: u32 = 437;
-pub const PRICING_INSTRUCTIONS: u32 = 493;
-pub const ORDER_STATE: u32 = 3486;
-pub const LAST_ACTION: u32 = 1251;
-pub const ACTION_STATUS: u32 = 45517;
-pub const ORDER_VOLUME: u32 = 1548;
-pub const ORDER_PRICE: u32 = 1480;
-pub const REMAINING_VOLUME: u32 = 4619;
-pub const DONE_VOLUME_TOTAL: u32 = 680;
-pub const DONE_VALUE_TOTAL: u32 = 676;
-pub const UNCOMMITTED_VOLUME: u32 = 4781;
-pub const AVERAGE_PRICE: u32 = 347;
-pub const INTERNAL_ORDER_STATUS: u32 = 3962;
-pub const EXTERNAL_ORDER_STATUS: u32 = 1267;
-pub const LIFETIME: u32 = 45514;
-pub const CURRENCY: u32 = 569;
-
-pub const REQUEST_ID: u32 = 74;
-pub const TARGET_ID: u32 = 8766;
-pub const TARGET_NAME: u32 = 1796;
-pub const IS_TEST_DATA: u32 = 3564;
-pub const PACKET_FLAG: u32 = 68;
-pub const HAS_MORE_DATA: u32 = 4578;
-pub const MESSAGE_SOURCE: u32 = 6;
-pub const IS_WATCH_UPDATES: u32 = 2020;
-pub const WATCH_TOPIC: u32 = 1848;
-pub const WATCH_KEY_INDEX: u32 = 974;
-
-pub const TRADE_NUMBER: u32 = 54;
-pub const TRADE_GMT_DATETIME: u32 = 3974;
-pub const PRICING_TRADE_HISTORICAL_EX_DATA_SOURCE: u32 = 10523;
-pub const PRICING_TRADE_HISTORICAL_EX_TRADE_PRICE: u32 = 51;
-pub const PRICING_TRADE_HISTORICAL_EX_TRADE_VOLUME: u32 = 50;
-pub const PRICING_TRADE_HISTORICAL_EX_TRADE_VALUE: u32 = 49;
-pub const TRADE_DATETIME: u32 = 3965;
-pub const COND_CODES_FLAG: u32 = 3972;
-pub const ACTION_FLAG: u32 = 3971;
-
-pub const ERROR_NUMBER: u32 = 66;
-pub const ERROR_MESSAGE: u32 = 719;
-pub const ERROR_MESSAGE_JSON: u32 = 11911;
-pub const ORDER_CREATE_ORDER_STATE: u32 = 11911;
-pub const STATUS_DESCRIPTION: u32 = 2898;
-
-pub const ORDER_MATCH_ID: u32 = 6820;
-pub const EXPIRY_DATE_TIME: u32 = 45519;
-pub const WORK: u32 = 5330;
-pub const ACKNOWLEDGE_ORDER: u32 = 3994;
-pub const PRIMARY_CLIENT_ORDER_ID: u32 = 45509;
-pub const SECONDARY_CLIENT_ORDER_ID: u32 = 45509;
-pub const ORDER_DETAILS: u32 = 45515;
-pub const SIDE_CODE: u32 = 6860;
-pub const ORDER_GIVER: u32 = 4185;
-pub const ORDER_TAKER: u32 = 7177;
-pub const IGNORE_LIMIT_WARNINGS: u32 = 5996;
-pub const EXECUTION_INSTRUCTIONS_DICTIONARY: u32 = 6366;
-pub const CUSTOM_COLUMNS_DICTIONARY: u32 = 6729;
-pub const TRAILER_CODE_ON_MASK: u32 = 7554;
-pub const ORDER_TAG: u32 = 7567;
-pub const USE_DEFAULT_ORDER_ATTRIBUTES: u32 = 4031;
-pub const BASKET_NAME: u32 = 8267;
-pub const BROKER_BOOK_TYPE: u32 = 9056;
-pub const BROKER_BOOK_VALUE: u32 = 9057;
-pub const ORDER_VALUE: u32 = 1281;
-pub const SELL_ORDER_GROUPING_OPTION: u32 = 9776;
-pub const IS_LEG: u32 = 9085;
-pub const FAIL_IF_SECURITY_CODE_CHANGE: u32 = 4070;
-pub const TRADING_PASSWORD: u32 = 58039;
+macro_rules! fields {
+    ($($name:ident = $id:literal,)*) => {
+        $(pub const $name: u32 = $id;)*
+
+        /// Every known field as `(name, id)`, in declaration order. Some ids
+        /// have more than one name; lookups by id return the first.
+        pub const ALL: &[(&str, u32)] = &[$((stringify!($name), $id),)*];
+    };
+}
+
+/// Get the name of a field id, if it is known.
+#[must_use]
+pub fn name(id: u32) -> Option<&'static str> {
+    ALL.iter().find(|(_, i)| *i == id).map(|(name, _)| *name)
+}
+
+/// Get the id of a field by name.
+#[must_use]
+pub fn by_name(name: &str) -> Option<u32> {
+    ALL.iter().find(|(n, _)| *n == name).map(|(_, id)| *id)
+}
+
+fields! {
+    IS_PINGABLE = 7574,
+    PRODUCT_FULL_VERSION = 490,
+
+    USER_NAME = 77,
+    COMPANY_NAME = 486,
+    CLIENT_TYPE = 489,
+    ACS_LOGIN_TOKEN = 6140,
+    PASSWORD = 78,
+
+    PAGE_SIZE = 1316,
+    TIMEOUT = 3804,
+
+    ERROR_NAME = 3741,
+    SEQ_NO = 494,
+
+    EXCHANGE = 5,
+    DATA_SOURCE = 87,
+
+    SESSION_TYPE = 2955,
+
+    AMEND_IF_EXISTS = 7123,
+
+    ACCOUNT_CODE = 75,
+    ACCOUNT_NAME = 264,
+    ACCOUNT_DESIGNATION = 5683,
+    ACCOUNT_ID = 76,
+    ACCOUNT_GROUPS = 848,
+    ORGANISATION = 3508,
+
+    ACCESS_MODE = 3433,
+    FILTER_BY = 791,
+    FILTER_MODE = 3556,
+    FILTER_TEXT = 7113,
+    ACCOUNT_LINK_MODE = 2131,
+    INCLUDE_INACTIVE = 5788,
+    ONLY_IN_GROUP = 6588,
+
+    EXEMPT_FROM_ACCOUNT_MASTER_LIMITS = 8968,
+
+    COMMAND_TEXT = 4889,
+    PAGE_DIRECTION = 1935,
+
+    IOS_NAME = 940,
+    IOS_ID = 944,
+    SERVICE_ID = 1703,
+    USER_PROFILE_CODE = 4503,
+    SERVICE_KEY = 3799,
+    LOGGED_IN = 3156,
+    ACCOUNT_GROUP = 6588,
+
+    WATCH_KEY = 7831,
+    HINT_WATCH_KEYS = 974,
+    WATCH_REQUEST_ID = 7832,
+
+    DESTINATION = 2064,
+    ATTRIBUTE_CATEGORY_NUMBER = 4542,
+    ATTRIBUTE_CODE = 3511,
+    ATTRIBUTE_DESCRIPTION = 3512,
+    ATTRIBUTE_DATA_TYPE_NUMBER = 1938,
+    ATTRIBUTE_VALUE_RANGE = 4698,
+    ATTRIBUTE_DEFAULT_VALUE = 601,
+    ATTRIBUTE_HIDDEN = 5861,
+    ATTRIBUTE_READ_ONLY = 1557,
+    ATTRIBUTE_LONG_DESCRIPTION = 7718,
+    ATTRIBUTE_FLAGS_MASK1 = 3971,
+    ATTRIBUTE_FLAGS_MASK2 = 8012,
+    ENTRY_TYPE = 4955,
+    ATTRIBUTE_NOT_AMENDABLE = 3838,
+    INPUT_WIRE_VALUE = 9220,
+    OUTPUT_WIRE_VALUE = 9221,
+    RESTRICTED_TO_SIDE = 10502,
+    RESTRICTED_TO_LEG_SIDE = 10501,
+    SEND_EXTERNAL_FLAG_BIT_MASK = 10460,
+    ATTRIBUTE_PROPERTY_VALUE = 10507,
+    ATTRIBUTE_SET_BY_DEFAULT = 470,
+    LOCALIZATION_CONTEXT = 9839,
+    ATTRIBUTE_VALUE_LIST = 5541,
+    ATTRIBUTE_DISABLED = 3157,
+
+    PORTFOLIO_CODE = 5047,
+    PORTFOLIO_NAME = 4136,
+    INCLUDE_REMOVED_LINKS = 3567,
+    PORTFOLIO_CASH_CODE = 462,
+    LIMIT_GROUP = 7132,
+    COMMISSION_GROUP = 529,
+    MARGIN_LENDER_NAME = 3906,
+    CFD_PROVIDER_NAME = 7107,
+    OPTION_CATEGORY = 7439,
+    FEE_GROUP = 7539,
+    UPLOAD_SOURCE = 7363,
+    SETTLEMENT_METHOD = 4124,
+    ADVISOR_NAME = 4456,
+    MARKET_MAKER_NAME = 8167,
+    MARGIN_COVER_DISPLAY_STATUS = 10005,
+    MARGIN_COVER_STATUS = 10004,
+    MARGIN_COVER_STATUS_REASON = 9964,
+    PORTFOLIO_EMAIL_ADDRESS = 9949,
+    ACCOUNT_EXECUTIVE_EMAIL_ADDRESS = 9950,
+    MARGIN_COVER_STATUS_UPDATE_DATETIME = 10389,
+    SHARING_CASH_ACCOUNT = 1714,
+
+    ACCESS_TYPE = 262,
+    LINK_REMOVED = 3567,
+    LINK_SEQUENCE_NUMBER = 7022,
+
+    INCLUDE_PORTFOLIOS_WITH_SAME_CASH_ACCOUNT = 10439,
+
+    ATTRIBUTE_ACCOUNT_CODE = 5754,
+
+    PORTFOLIO_CASH_NAME = 4415,
+    VERSION_STAMP = 2001,
+    CREATE_DATETIME = 4278,
+    UPDATE_DATETIME = 4279,
+    CURRENCY_CODE = 4984,
+    CASH_BALANCE = 7146,
+    UNSETTLED_BUY_VALUE = 1971,
+    UNSETTLED_BUY_CHARGES = 7297,
+    UNSETTLED_SELL_VALUE = 1972,
+    UNSETTLED_SELL_CHARGES = 7298,
+    YESTERDAY_EQUITY_SELL_VALUE = 3932,
+    YESTERDAY_EQUITY_SELL_CHARGES = 7299,
+    IN_MARKET_BUY_VALUE = 4936,
+    IN_MARKET_SELL_VALUE = 4937,
+    NET_CASH = 1102,
+    OPTION_UNSETTLED_BUY_VALUE = 6099,
+    OPTION_UNSETTLED_BUY_CHARGES = 7504,
+    OPTION_UNSETTLED_SELL_VALUE = 3931,
+    OPTION_UNSETTLED_SELL_CHARGES = 7505,
+    OPTION_COLLATERAL_VALUE = 6083,
+    OPTION_CLOSING_BUYS = 4277,
+    OPTION_PREMIUM_MARGIN = 1468,
+    OPTION_RISK_MARGIN = 1640,
+    OPTION_TOTAL_MARGIN = 6082,
+    OPTION_MARGIN_CASH = 3910,
+    OPTION_NET_CASH = 3897,
+    CLEARING_HOUSE_MARGIN = 8490,
+    EXTERNAL_VALUE = 8621,
+    NET_UNSETTLED_BUY_VALUE_TODAY = 9887,
+    NET_UNSETTLED_SELL_VALUE_TODAY = 9888,
+    NET_UNSETTLED_VALUE_TODAY = 9889,
+    GLV = 6029,
+    FREE_EQUITY = 6030,
+    TOTAL_INITIAL_MARGIN = 10455,
+    TOTAL_CFD_REALISED_PROFIT = 10456,
+    TOTAL_CFD_UNREALISED_PROFIT = 10457,
+    TOTAL_CFD_COLLATERAL_VALUE = 10458,
+    TOTAL_NON_CFD_MARKET_VALUE = 10459,
+    REALIZED_LOSS_START_OF_DAY_VALUE = 10692,
+    MARGIN_LENDER_TOTAL_FINANCED_VALUE = 10757,
+    TRUST_BALANCE = 10976,
+    TOTAL_CFD_REALIZED_PROFIT_IN_SETTLEMENT_CURRENCY = 11186,
+    ACCRUED_INTEREST = 5838,
+    FACILITY_LIMIT = 4819,
+    MULTI_SETTLEMENT_CALCULATION_METHOD = 11663,
+    DEFAULT_CASH_SETTLEMENT_DAYS = 11664,
+
+    INCLUDE_POSITIONS_FROM_PORTFOLIOS_WITH_SAME_CASH_ACCOUNT = 10439,
+
+    SECURITY_CODE = 4,
+    SETTLEMENT_CURRENCY = 7741,
+    ACTUAL_VOLUME = 7183,
+    AVAILABLE_VOLUME = 344,
+    MARKET_VALUE = 1081,
+
+    TOTAL_PROFIT = 7246,
+    TODAY_PROFIT = 1508,
+    CLOSED_PROFIT = 6910,
+
+    BOARD = 88,
+    ASK_LEVEL_MAX = 23,
+    BID_LEVEL_MAX = 36,
+    SECURITY_TEXT = 7576,
+
+    BID_COUNT = 37,
+    BID_PRICE = 40,
+    BID_VOLUME = 41,
+    BID_ORDER_TYPE = 57059,
+    BID_ORDER_NUMBER = 3164,
+    BID_ACTION = 32,
+    BID_DATA_SOURCE = 6852,
+
+    ASK_COUNT = 24,
+    ASK_PRICE = 27,
+    ASK_VOLUME = 28,
+    ASK_ORDER_TYPE = 57050,
+    ASK_ORDER_NUMBER = 3165,
+    ASK_ACTION = 19,
+    ASK_DATA_SOURCE = 6853,
+
+    SEC_ID = 3,
+
+    TRADE_DATE = 52,
+    FROM_TRADE_TIME = 1763,
+    TO_TRADE_TIME = 709,
+    TRADE_VOLUME = 2007,
+    TRADE_VOLUME_OPERATOR = 2259,
+    TRADE_PRICE = 1480,
+    TRADE_PRICE_OPERATOR = 2303,
+    TRADE_VALUE = 49,
+    TRADE_VALUE_OPERATOR = 16,
+    BUY_BROKER_NUMBER = 43,
+    SELL_BROKER_NUMBER = 31,
+
+    CURRENT_DAY_TRADES_ONLY = 1843,
+
+    ORDER_FILTER = 2299,
+    ORDER_GROUP = 480,
+    BACK_OFFICE_STATUS = 7188,
+    DESTINATION_EXCLUDE = 8400,
+    RETRIEVE_SECURITY_DESCRIPTION = 11757,
+    SECURITY_TYPE_RANGE = 5311,
+    ORDER_FILTER_CUSTOM_DAYS = 590,
+
+    ROOT_PARENT_ORDER_NUMBER = 6640,
+    ORDER_NUMBER = 1264,
+    PARENT_ORDER_NUMBER = 45513,
+    SUB_DESTINATION = 4509,
+    BUY_OR_SELL = 437,
+    PRICING_INSTRUCTIONS = 493,
+    ORDER_STATE = 3486,
+    LAST_ACTION = 1251,
+    ACTION_STATUS = 45517,
+    ORDER_VOLUME = 1548,
+    ORDER_PRICE = 1480,
+    REMAINING_VOLUME = 4619,
+    DONE_VOLUME_TOTAL = 680,
+    DONE_VALUE_TOTAL = 676,
+    UNCOMMITTED_VOLUME = 4781,
+    AVERAGE_PRICE = 347,
+    INTERNAL_ORDER_STATUS = 3962,
+    EXTERNAL_ORDER_STATUS = 1267,
+    LIFETIME = 45514,
+    CURRENCY = 569,
+
+    REQUEST_ID = 74,
+    TARGET_ID = 8766,
+    TARGET_NAME = 1796,
+    IS_TEST_DATA = 3564,
+    PACKET_FLAG = 68,
+    HAS_MORE_DATA = 4578,
+    MESSAGE_SOURCE = 6,
+    IS_WATCH_UPDATES = 2020,
+    WATCH_TOPIC = 1848,
+    WATCH_KEY_INDEX = 974,
+
+    TRADE_NUMBER = 54,
+    TRADE_GMT_DATETIME = 3974,
+    PRICING_TRADE_HISTORICAL_EX_DATA_SOURCE = 10523,
+    PRICING_TRADE_HISTORICAL_EX_TRADE_PRICE = 51,
+    PRICING_TRADE_HISTORICAL_EX_TRADE_VOLUME = 50,
+    PRICING_TRADE_HISTORICAL_EX_TRADE_VALUE = 49,
+    TRADE_DATETIME = 3965,
+    COND_CODES_FLAG = 3972,
+    ACTION_FLAG = 3971,
+
+    ERROR_NUMBER = 66,
+    ERROR_MESSAGE = 719,
+    ERROR_MESSAGE_JSON = 11911,
+    ORDER_CREATE_ORDER_STATE = 11911,
+    STATUS_DESCRIPTION = 2898,
+
+    ORDER_MATCH_ID = 6820,
+    EXPIRY_DATE_TIME = 45519,
+    WORK = 5330,
+    ACKNOWLEDGE_ORDER = 3994,
+    PRIMARY_CLIENT_ORDER_ID = 45509,
+    SECONDARY_CLIENT_ORDER_ID = 45509,
+    ORDER_DETAILS = 45515,
+    SIDE_CODE = 6860,
+    ORDER_GIVER = 4185,
+    ORDER_TAKER = 7177,
+    IGNORE_LIMIT_WARNINGS = 5996,
+    EXECUTION_INSTRUCTIONS_DICTIONARY = 6366,
+    CUSTOM_COLUMNS_DICTIONARY = 6729,
+    TRAILER_CODE_ON_MASK = 7554,
+    ORDER_TAG = 7567,
+    USE_DEFAULT_ORDER_ATTRIBUTES = 4031,
+    BASKET_NAME = 8267,
+    BROKER_BOOK_TYPE = 9056,
+    BROKER_BOOK_VALUE = 9057,
+    ORDER_VALUE = 1281,
+    SELL_ORDER_GROUPING_OPTION = 9776,
+    IS_LEG = 9085,
+    FAIL_IF_SECURITY_CODE_CHANGE = 4070,
+    TRADING_PASSWORD = 58039,
+}
@@ -0,0 +1,174 @@
+//! Logging in to an IOS (order server).
+//!
+//! Order operations need an IOS session on top of the market data login.
+//! [`IosServicesRequest`] lists the IOS services the user can use, and
+//! [`IosLoginRequest`] logs in to one of them.
+
+use crate::{fields::IOS_NAME, request::Error, FromSdo, SdoRequest, SDO};
+
+/// Lists the IOS services available to the logged in user.
+#[derive(Debug, Clone, Default, SdoRequest)]
+#[sdo(request = TdIosGeneral, response = TdIosGeneral, reply = IosServices)]
+pub struct IosServicesRequest {}
+
+#[derive(Debug, Clone, Default)]
+pub struct IosServices {
+    pub services: Vec<IosService>,
+}
+
+impl FromSdo for IosServices {
+    fn from_sdo(sdo: &SDO) -> Result<Self, Error> {
+        let services = sdo
+            .rows()
+            .iter()
+            // An empty list still has its response-wide fields.
+            .filter(|row| row.get_field(IOS_NAME).is_some())
+            .map(IosService::from_sdo)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { services })
+    }
+}
+
+/// One IOS service, as listed by [`IosServicesRequest`].
+#[derive(Debug, Clone, FromSdo)]
+pub struct IosService {
+    #[sdo(field = IOS_NAME)]
+    pub ios_name: String,
+    #[sdo(field = IOS_ID)]
+    pub ios_id: Option<u32>,
+    #[sdo(field = SERVICE_ID)]
+    pub service_id: Option<u32>,
+    #[sdo(field = SERVICE_KEY)]
+    pub service_key: Option<String>,
+}
+
+/// Logs in to an IOS service.
+#[derive(Debug, Clone, SdoRequest)]
+#[sdo(request = TdIosLogin, response = TdIosLogin, reply = IosLoginResponse)]
+pub struct IosLoginRequest {
+    #[sdo(field = IOS_NAME)]
+    ios_name: String,
+    #[sdo(field = IOS_ID)]
+    ios_id: Option<u32>,
+    #[sdo(field = SERVICE_ID)]
+    service_id: Option<u32>,
+    #[sdo(field = SERVICE_KEY)]
+    service_key: Option<String>,
+    #[sdo(field = USER_PROFILE_CODE)]
+    user_profile_code: Option<String>,
+}
+
+impl IosLoginRequest {
+    #[must_use]
+    pub fn new(service: &IosService) -> Self {
+        Self {
+            ios_name: service.ios_name.clone(),
+            ios_id: service.ios_id,
+            service_id: service.service_id,
+            service_key: service.service_key.clone(),
+            user_profile_code: None,
+        }
+    }
+
+    /// Log in with a particular user profile, for users with several.
+    #[must_use]
+    pub fn user_profile_code(mut self, code: impl Into<String>) -> Self {
+        self.user_profile_code = Some(code.into());
+        self
+    }
+}
+
+/// The `TdIosLogin` reply.
+#[derive(Debug, Clone, FromSdo)]
+pub struct IosLoginResponse {
+    #[sdo(field = LOGGED_IN)]
+    pub logged_in: Option<bool>,
+    #[sdo(field = IOS_NAME)]
+    pub ios_name: Option<String>,
+    #[sdo(field = USER_PROFILE_CODE)]
+    pub user_profile_code: Option<String>,
+    #[sdo(field = ERROR_NUMBER)]
+    pub error_number: Option<u32>,
+    #[sdo(field = ERROR_MESSAGE)]
+    pub error_message: Option<String>,
+}
+
+impl IosLoginResponse {
+    /// Without an explicit `LOGGED_IN`, a reply without an error counts as
+    /// logged in.
+    #[must_use]
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in
+            .unwrap_or(self.error_number.is_none() && self.error_message.is_none())
+    }
+
+    /// `Ok` if the login succeeded, else why it didn't.
+    pub fn into_result(self) -> Result<Self, crate::login::Error> {
+        if self.is_logged_in() {
+            Ok(self)
+        } else {
+            Err(crate::login::Error::Rejected {
+                number: self.error_number,
+                message: self.error_message,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fields::{HAS_MORE_DATA, IOS_ID, SERVICE_KEY},
+        Topic,
+    };
+
+    #[test]
+    fn lists_a_service_per_row() {
+        let mut sdo = SDO::new(Topic::TdIosGeneral);
+        sdo.push_string_w(
+            IOS_NAME,
+            vec![Some("ASX".to_owned()), Some("CXA".to_owned())],
+        );
+        sdo.push_long(IOS_ID, vec![Some(1), None]);
+        sdo.push_string_w(SERVICE_KEY, vec![None, Some("key".to_owned())]);
+        let services = IosServices::from_sdo(&sdo).unwrap().services;
+        let names = services
+            .iter()
+            .map(|s| s.ios_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["ASX", "CXA"]);
+        assert_eq!((services[0].ios_id, services[1].ios_id), (Some(1), None));
+        assert_eq!(services[1].service_key.as_deref(), Some("key"));
+    }
+
+    #[test]
+    fn leaves_out_rows_without_a_service() {
+        let mut sdo = SDO::new(Topic::TdIosGeneral);
+        sdo.push_long(HAS_MORE_DATA, Some(0));
+        assert!(IosServices::from_sdo(&sdo).unwrap().services.is_empty());
+
+        sdo.push_string_w(IOS_NAME, Some("ASX".to_owned()));
+        let services = IosServices::from_sdo(&sdo).unwrap().services;
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].ios_name, "ASX");
+    }
+
+    #[test]
+    fn logs_in_to_a_listed_service() {
+        let service = IosService {
+            ios_name: "ASX".to_owned(),
+            ios_id: Some(1),
+            service_id: None,
+            service_key: Some("key".to_owned()),
+        };
+        let sdo = IosLoginRequest::new(&service)
+            .user_profile_code("P1")
+            .to_sdo();
+        assert_eq!(sdo.topic, Topic::TdIosLogin);
+        let listed = IosService::from_sdo(&sdo).unwrap();
+        assert_eq!(listed.ios_name, "ASX");
+        assert_eq!((listed.ios_id, listed.service_id), (Some(1), None));
+        assert_eq!(listed.service_key.as_deref(), Some("key"));
+    }
+}
@@ -0,0 +1,503 @@
+//! Readable JSON transcoding of SDOs.
+//!
+//! An SDO becomes `{"topic": "TrLogin", "fields": {...}}`. Fields are keyed
+//! by their name in [`crate::fields`], or by their numeric id when the name
+//! is unknown, and each value is tagged with its data type so that it can be
+//! converted back losslessly:
+//!
+//! ```json
+//! {
+//!   "topic": "TrLogin",
+//!   "fields": {
+//!     "USER_NAME": { "StringW": "alice" },
+//!     "ACCOUNT_GROUPS": { "Long": [1, 2, null] },
+//!     "9999": { "SDO": { "topic": "UndefinedTopic", "fields": {} } },
+//!     "9998": null,
+//!     "9997": [{ "Long": 1 }, { "Long": 2 }]
+//!   }
+//! }
+//! ```
+//!
+//! Single row fields are written as a bare value, multi-row fields as an
+//! array. A field without any data is `null`. Fields keep their order; a
+//! field that appears more than once is an array of its values, placed where
+//! it first appears. Datetimes are RFC 3339 strings and binary data is a hex
+//! string. Topics missing from [`Topic`] are written as their numeric id.
+
+use serde_json::{json, Map, Number, Value};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    data::AsciiString,
+    fields,
+    util::{format_timeout, from_hex, parse_timeout, to_hex},
+    Data, Field, Message, Topic, SDO,
+};
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum Error {
+    #[error("expected a JSON object for an SDO")]
+    ExpectedObject,
+    #[error("expected a JSON object of fields")]
+    ExpectedFields,
+    #[error("unknown topic {0}")]
+    UnknownTopic(String),
+    #[error("unknown field {0}")]
+    UnknownField(String),
+    #[error("field {0} must be an object with a single data type key")]
+    InvalidField(String),
+    #[error("unknown data type {data_type} for field {field}")]
+    UnknownDataType { field: String, data_type: String },
+    #[error("invalid {data_type} value for field {field}")]
+    InvalidValue { field: String, data_type: String },
+    #[error("invalid timeout {0}")]
+    InvalidTimeout(String),
+    #[error("invalid page size {0}")]
+    InvalidPageSize(String),
+}
+
+/// Convert an SDO into a JSON object keyed by field name.
+#[must_use]
+pub fn to_json(sdo: &SDO) -> Value {
+    let mut fields = Map::new();
+    for (header, data) in &sdo.fields {
+        let id = header.field_id.unwrap_or_default();
+        let key = fields::name(id).map_or_else(|| id.to_string(), ToOwned::to_owned);
+        // Fields without data decode as `Unknown`.
+        let value = match data {
+            None | Some(Data::Unknown) => Value::Null,
+            Some(data) => {
+                let (data_type, value) = data_to_json(data);
+                json!({ data_type: value })
+            }
+        };
+        match fields.get_mut(&key) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(first) => *first = json!([first.take(), value]),
+            None => {
+                fields.insert(key, value);
+            }
+        }
+    }
+    json!({
+        "topic": topic_to_json(sdo),
+        "fields": fields,
+    })
+}
+
+/// Build an SDO from JSON in the format produced by [`to_json`].
+///
+/// The topic may be given by name or by numeric id, and fields by name or
+/// by numeric id.
+pub fn from_json(value: &Value) -> Result<SDO, Error> {
+    let object = value.as_object().ok_or(Error::ExpectedObject)?;
+    let mut sdo = match object.get("topic") {
+        None | Some(Value::Null) => SDO::new(Topic::UndefinedTopic),
+        Some(Value::String(name)) => {
+            SDO::new(Topic::from_name(name).ok_or_else(|| Error::UnknownTopic(name.clone()))?)
+        }
+        Some(Value::Number(id)) => SDO::with_topic_id(
+            id.as_i64()
+                .ok_or_else(|| Error::UnknownTopic(id.to_string()))?,
+        ),
+        Some(other) => return Err(Error::UnknownTopic(other.to_string())),
+    };
+
+    let Some(fields) = object.get("fields") else {
+        return Ok(sdo);
+    };
+    for (key, value) in fields.as_object().ok_or(Error::ExpectedFields)? {
+        let id = key
+            .parse()
+            .ok()
+            .or_else(|| fields::by_name(key))
+            .ok_or_else(|| Error::UnknownField(key.clone()))?;
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            if let Some(data) = field_from_json(key, value)? {
+                sdo.push_data(id, data);
+            } else {
+                let mut header = Field::new(true);
+                header.field_id = Some(id);
+                sdo.fields.push((header, None));
+            }
+        }
+    }
+    Ok(sdo)
+}
+
+/// The topic's name, or its id when it is unknown.
+fn topic_to_json(sdo: &SDO) -> Value {
+    if sdo.topic == Topic::Unknown {
+        json!(sdo.topic_id())
+    } else {
+        json!(format!("{:?}", sdo.topic))
+    }
+}
+
+/// Read `null` or `{data_type: value}`.
+fn field_from_json(key: &str, value: &Value) -> Result<Option<Data>, Error> {
+    if value.is_null() {
+        return Ok(None);
+    }
+    match value
+        .as_object()
+        .map(|o| o.iter().collect::<Vec<_>>())
+        .as_deref()
+    {
+        Some([(data_type, value)]) => data_from_json(key, data_type, value).map(Some),
+        _ => Err(Error::InvalidField(key.to_owned())),
+    }
+}
+
+/// Convert a message into `{"id": ..., "sdo": {...}}`.
+#[must_use]
+pub fn message_to_json(message: &Message) -> Value {
+    let mut object = Map::new();
+    object.insert("id".to_owned(), json!(message.id));
+    if let Some(timeout) = message.timeout {
+        object.insert("timeout".to_owned(), json!(format_timeout(timeout)));
+    }
+    if let Some(page_size) = message.page_size {
+        object.insert("page_size".to_owned(), json!(page_size));
+    }
+    object.insert("sdo".to_owned(), to_json(&message.sdo));
+    Value::Object(object)
+}
+
+/// Build a message from JSON in the format produced by [`message_to_json`].
+pub fn message_from_json(value: &Value) -> Result<Message, Error> {
+    let object = value.as_object().ok_or(Error::ExpectedObject)?;
+    let sdo = from_json(object.get("sdo").ok_or(Error::ExpectedObject)?)?;
+    Ok(Message {
+        id: object
+            .get("id")
+            .and_then(Value::as_str)
+            .map(ToOwned::to_owned),
+        timeout: match object.get("timeout") {
+            None | Some(Value::Null) => None,
+            Some(timeout) => Some(
+                timeout
+                    .as_str()
+                    .and_then(parse_timeout)
+                    .ok_or_else(|| Error::InvalidTimeout(timeout.to_string()))?,
+            ),
+        },
+        page_size: match object.get("page_size") {
+            None | Some(Value::Null) => None,
+            Some(page_size) => Some(
+                page_size
+                    .as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| Error::InvalidPageSize(page_size.to_string()))?,
+            ),
+        },
+        sdo,
+    })
+}
+
+/// Convert field data into its data type and value, which [`to_json`]
+/// writes as `{data_type: value}`.
+#[must_use]
+pub fn data_to_json(data: &Data) -> (&'static str, Value) {
+    let (data_type, value) = match data {
+        Data::StringW(v) => ("StringW", rows_to_json(v, |s| json!(s))),
+        Data::AsciiString(v) => ("AsciiString", rows_to_json(v, |s| json!(s.0))),
+        Data::Bool(v) => (
+            "Bool",
+            if let [value] = v.as_slice() {
+                json!(value)
+            } else {
+                json!(v)
+            },
+        ),
+        Data::Long(v) => ("Long", rows_to_json(v, |n| json!(n))),
+        Data::Short(v) => ("Short", rows_to_json(v, |n| json!(n))),
+        Data::LongLong(v) => ("LongLong", rows_to_json(v, |n| json!(n))),
+        Data::Double(v) => ("Double", rows_to_json(v, |n| float_to_json(*n))),
+        Data::Float(v) => ("Float", rows_to_json(v, |n| float_to_json(f64::from(*n)))),
+        Data::DateTime(v) => (
+            "DateTime",
+            rows_to_json(v, |d| d.format(&Rfc3339).map_or(Value::Null, Value::String)),
+        ),
+        Data::Char(v) => ("Char", rows_to_json(v, |c| json!(c))),
+        Data::Binary(v) => ("Binary", rows_to_json(v, |b| json!(to_hex(b)))),
+        Data::SDO(v) => ("SDO", rows_to_json(v, to_json)),
+        Data::Unknown => ("Unknown", Value::Null),
+    };
+    (data_type, value)
+}
+
+fn rows_to_json<T>(rows: &[Option<Box<T>>], f: impl Fn(&T) -> Value) -> Value {
+    let mut values = rows
+        .iter()
+        .map(|row| row.as_deref().map_or(Value::Null, &f));
+    if rows.len() == 1 {
+        values.next().unwrap_or_default()
+    } else {
+        Value::Array(values.collect())
+    }
+}
+
+fn float_to_json(n: f64) -> Value {
+    Number::from_f64(n).map_or(Value::Null, Value::Number)
+}
+
+fn data_from_json(field: &str, data_type: &str, value: &Value) -> Result<Data, Error> {
+    let rows = match value {
+        Value::Array(rows) => rows.iter().collect(),
+        value => vec![value],
+    };
+    let invalid = || Error::InvalidValue {
+        field: field.to_owned(),
+        data_type: data_type.to_owned(),
+    };
+
+    let data = match data_type {
+        "StringW" => Data::StringW(
+            rows_from_json(&rows, |v| v.as_str().map(ToOwned::to_owned)).ok_or_else(invalid)?,
+        ),
+        "AsciiString" => Data::AsciiString(
+            rows_from_json(&rows, |v| v.as_str().map(|s| AsciiString(s.to_owned())))
+                .ok_or_else(invalid)?,
+        ),
+        "Bool" => Data::Bool(
+            rows.iter()
+                .map(|v| {
+                    if v.is_null() {
+                        Some(None)
+                    } else {
+                        v.as_bool().map(Some)
+                    }
+                })
+                .collect::<Option<_>>()
+                .ok_or_else(invalid)?,
+        ),
+        "Long" => Data::Long(
+            rows_from_json(&rows, |v| v.as_u64().and_then(|n| u32::try_from(n).ok()))
+                .ok_or_else(invalid)?,
+        ),
+        "Short" => Data::Short(
+            rows_from_json(&rows, |v| v.as_u64().and_then(|n| u32::try_from(n).ok()))
+                .ok_or_else(invalid)?,
+        ),
+        "LongLong" => Data::LongLong(rows_from_json(&rows, Value::as_u64).ok_or_else(invalid)?),
+        "Double" => Data::Double(rows_from_json(&rows, Value::as_f64).ok_or_else(invalid)?),
+        #[allow(clippy::cast_possible_truncation)]
+        "Float" => Data::Float(
+            rows_from_json(&rows, |v| v.as_f64().map(|n| n as f32)).ok_or_else(invalid)?,
+        ),
+        "DateTime" => Data::DateTime(
+            rows_from_json(&rows, |v| {
+                v.as_str()
+                    .and_then(|s| OffsetDateTime::parse(s, &Rfc3339).ok())
+            })
+            .ok_or_else(invalid)?,
+        ),
+        "Char" => Data::Char(
+            rows_from_json(&rows, |v| {
+                let mut chars = v.as_str()?.chars();
+                chars.next().filter(|_| chars.next().is_none())
+            })
+            .ok_or_else(invalid)?,
+        ),
+        "Binary" => Data::Binary(
+            rows_from_json(&rows, |v| v.as_str().and_then(from_hex)).ok_or_else(invalid)?,
+        ),
+        "SDO" => Data::SDO(
+            rows.iter()
+                .map(|v| {
+                    if v.is_null() {
+                        Ok(None)
+                    } else {
+                        from_json(v).map(|s| Some(Box::new(s)))
+                    }
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => {
+            return Err(Error::UnknownDataType {
+                field: field.to_owned(),
+                data_type: data_type.to_owned(),
+            })
+        }
+    };
+    Ok(data)
+}
+
+/// Nulls become empty rows; any other value `f` can't convert fails the
+/// whole field.
+fn rows_from_json<T>(
+    rows: &[&Value],
+    f: impl Fn(&Value) -> Option<T>,
+) -> Option<Vec<Option<Box<T>>>> {
+    rows.iter()
+        .map(|v| {
+            if v.is_null() {
+                Some(None)
+            } else {
+                f(v).map(|t| Some(Box::new(t)))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+    use crate::fields::{ORDER_NUMBER, SECURITY_CODE, USER_NAME};
+
+    fn every_type() -> SDO {
+        let mut nested = SDO::new(Topic::UndefinedTopic);
+        nested.push_string_w(SECURITY_CODE, Some("BHP".to_owned()));
+        let mut sdo = SDO::new(Topic::TrLogin);
+        sdo.push_string_w(USER_NAME, Some("alice".to_owned()));
+        sdo.push_data(
+            1,
+            Data::AsciiString(vec![Some(Box::new(AsciiString("a".to_owned())))]),
+        );
+        sdo.push_bool(2, vec![Some(true), None, Some(false)]);
+        sdo.push_long(ORDER_NUMBER, vec![Some(1), None]);
+        sdo.push_short(3, Some(7));
+        sdo.push_data(4, Data::LongLong(vec![Some(Box::new(u64::MAX))]));
+        sdo.push_data(5, Data::Double(vec![Some(Box::new(1.5))]));
+        sdo.push_data(6, Data::Float(vec![Some(Box::new(-2.25))]));
+        sdo.push_data(
+            7,
+            Data::DateTime(vec![Some(Box::new(datetime!(2024-05-01 12:30 UTC)))]),
+        );
+        sdo.push_data(8, Data::Char(vec![Some(Box::new('x'))]));
+        sdo.push_data(
+            9,
+            Data::Binary(vec![
+                Some(Box::new(vec![0xde, 0xad])),
+                Some(Box::new(vec![])),
+            ]),
+        );
+        sdo.push_sdo(10, vec![Some(nested), None]);
+        sdo
+    }
+
+    #[test]
+    fn round_trips_every_data_type() {
+        let sdo = every_type();
+        let json = to_json(&sdo);
+        let back = from_json(&json).unwrap();
+        assert_eq!(to_json(&back), json);
+        assert_eq!(back.encode().unwrap(), sdo.encode().unwrap());
+    }
+
+    #[test]
+    fn keys_fields_by_name_or_id_in_order() {
+        let mut sdo = SDO::new(Topic::TrLogin);
+        sdo.push_long(9999, Some(2));
+        sdo.push_string_w(USER_NAME, Some("alice".to_owned()));
+        sdo.push_long(9999, Some(1));
+        let json = to_json(&sdo);
+        assert_eq!(
+            json,
+            json!({
+                "topic": "TrLogin",
+                "fields": {
+                    "9999": [{ "Long": 2 }, { "Long": 1 }],
+                    "USER_NAME": { "StringW": "alice" },
+                },
+            })
+        );
+        let keys = json["fields"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["9999", "USER_NAME"]);
+        let back = from_json(&json).unwrap();
+        let ids = back
+            .fields
+            .iter()
+            .map(|(h, _)| h.field_id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [Some(9999), Some(9999), Some(USER_NAME)]);
+    }
+
+    #[test]
+    fn reads_fields_by_id_and_without_data() {
+        let sdo = from_json(&json!({
+            "topic": 1012,
+            "fields": { "77": { "StringW": "alice" }, "9999": null },
+        }))
+        .unwrap();
+        assert_eq!(
+            sdo.get_field(USER_NAME).and_then(Data::as_first_str),
+            Some("alice")
+        );
+        assert_eq!(sdo.fields[1].0.field_id, Some(9999));
+        assert!(sdo.fields[1].1.is_none());
+    }
+
+    #[test]
+    fn fields_without_data_decode_as_null() {
+        let json = json!({ "topic": "TrLogin", "fields": { "9999": null } });
+        let bytes = from_json(&json).unwrap().encode().unwrap();
+        let decoded = crate::decode::read_sdos(&bytes).next().unwrap().unwrap();
+        assert_eq!(to_json(&decoded), json);
+    }
+
+    #[test]
+    fn rejects_invalid_timeouts_and_page_sizes() {
+        let message = |timeout: Value, page_size: Value| {
+            message_from_json(&json!({
+                "id": "a",
+                "timeout": timeout,
+                "page_size": page_size,
+                "sdo": { "topic": "TrLogin" },
+            }))
+        };
+        let ok = message(json!("30"), json!(50)).unwrap();
+        assert_eq!(ok.timeout, Some(std::time::Duration::from_secs(30)));
+        assert_eq!(ok.page_size, Some(50));
+        let ok = message(Value::Null, Value::Null).unwrap();
+        assert_eq!((ok.timeout, ok.page_size), (None, None));
+        assert!(matches!(
+            message(json!("30 seconds"), Value::Null),
+            Err(Error::InvalidTimeout(_))
+        ));
+        assert!(matches!(
+            message(json!(30), Value::Null),
+            Err(Error::InvalidTimeout(_))
+        ));
+        assert!(matches!(
+            message(Value::Null, json!(-1)),
+            Err(Error::InvalidPageSize(_))
+        ));
+        assert!(matches!(
+            message(Value::Null, json!(u64::MAX)),
+            Err(Error::InvalidPageSize(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_topics_are_written_as_their_id() {
+        let json = to_json(&SDO::with_topic_id(12345));
+        assert_eq!(json, json!({ "topic": 12345, "fields": {} }));
+        assert_eq!(from_json(&json).unwrap().topic_id(), 12345);
+        assert!(matches!(
+            from_json(&json!({ "topic": "Unknown" })),
+            Err(Error::UnknownTopic(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_untagged_fields() {
+        let json = json!({ "fields": { "USER_NAME": "alice" } });
+        assert!(matches!(from_json(&json), Err(Error::InvalidField(_))));
+        let json = json!({ "fields": { "USER_NAME": { "StringW": "a", "Long": 1 } } });
+        assert!(matches!(from_json(&json), Err(Error::InvalidField(_))));
+        let json = json!({ "fields": [{ "id": 77, "type": "StringW", "value": "a" }] });
+        assert!(matches!(from_json(&json), Err(Error::ExpectedFields)));
+    }
+}
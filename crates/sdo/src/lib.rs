@@ -2,13 +2,27 @@
 #![feature(int_roundings, let_chains)]
 #[macro_use]
 extern crate tracing;
+// Lets the derive macros refer to `::sdo` from inside this crate too.
+extern crate self as sdo;
+
+use std::time::Duration;
 
 use bitflags::bitflags;
 
+pub mod assemble;
+pub mod capture;
 pub mod data;
 pub mod decode;
+pub mod dissect;
 pub mod encode;
+pub mod error;
 pub mod fields;
+pub mod ios;
+pub mod json;
+pub mod login;
+pub mod request;
+pub mod request_id;
+pub mod router;
 pub mod util;
 
 use data::{AsciiString, Data};
@@ -16,7 +30,11 @@ use fields::{
     HAS_MORE_DATA, IS_TEST_DATA, IS_WATCH_UPDATES, MESSAGE_SOURCE, PACKET_FLAG, REQUEST_ID,
     TARGET_ID, TARGET_NAME, WATCH_KEY_INDEX, WATCH_REQUEST_ID, WATCH_TOPIC,
 };
-use util::generate_request_id;
+use request_id::RequestIdGenerator;
+
+pub use error::SdoServerError;
+pub use request::{FromSdo, SdoRequest};
+pub use sdo_derive::{FromSdo, SdoRequest};
 
 pub const BROADCAST_UPDATE_ADDRESS: &str = "-1";
 pub const BROADCAST_ADDRESS: &str = "-2";
@@ -43,7 +61,15 @@ pub enum ClientType {
     All = 65535,
 }
 
+/// Which way to page through a list, sent as `PAGE_DIRECTION`.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PageDirection {
+    Forward = 0,
+    Backward = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[repr(i32)]
 pub enum Topic {
     Td1 = 1,
@@ -103,6 +129,9 @@ pub enum Topic {
     TdIosOrders = 1686,
     UndefinedTopic = -1,
     UserDefinedTopic = -2,
+    /// A topic missing from this enum. Its id is kept in
+    /// [`SDO::unknown_topic`].
+    Unknown = i32::MIN,
     TdPricestepsGeneral = 2452,
     TrCmdExchList = 3088,
     TdCmdExchList = 3089,
@@ -156,18 +185,153 @@ pub enum Topic {
     // Other(i32),
 }
 
+impl Topic {
+    /// Every known topic, in declaration order.
+    pub const ALL: &'static [Topic] = &[
+        Topic::Td1,
+        Topic::Td4,
+        Topic::Tr12,
+        Topic::Td845,
+        Topic::Td847,
+        Topic::Tr922,
+        Topic::Tr970,
+        Topic::Tr1684,
+        Topic::Tr1724,
+        Topic::Td1981,
+        Topic::Tr2361,
+        Topic::Tr2939,
+        Topic::Tr2963,
+        Topic::Tr3424,
+        Topic::Tr1748,
+        Topic::Tr1749,
+        Topic::Tr2748,
+        Topic::Td2751,
+        Topic::Tr2753,
+        Topic::Td2756,
+        Topic::TdQuote,
+        Topic::TdExtPrice,
+        Topic::TrWatchlist,
+        Topic::TrLogin,
+        Topic::TrLoginInfo,
+        Topic::TdLogin,
+        Topic::TdLoginInfo,
+        Topic::TdLogout,
+        Topic::TdAction,
+        Topic::TdStartWatch,
+        Topic::TrStopWatch,
+        Topic::TdMetaRequest,
+        Topic::TrMetaRequest,
+        Topic::TdError,
+        Topic::TrForceError,
+        Topic::TdMessage,
+        Topic::TdAlert,
+        Topic::TrPing,
+        Topic::TdPing,
+        Topic::TdIosAccDetail,
+        Topic::TdIosLogin,
+        Topic::TdIosGeneral,
+        Topic::TdControl,
+        Topic::TdTopDown,
+        Topic::TdTopUp,
+        Topic::TdTopVal,
+        Topic::TdTopVol,
+        Topic::TdTopupPoint,
+        Topic::TdTopdownPoint,
+        Topic::TrIosGetPortfolio,
+        Topic::TdIosPortfolio,
+        Topic::TdIosAlertMessage,
+        Topic::TdNewsLibrary,
+        Topic::TdExchangeTime,
+        Topic::TdIosOrders,
+        Topic::UndefinedTopic,
+        Topic::UserDefinedTopic,
+        Topic::TdPricestepsGeneral,
+        Topic::TrCmdExchList,
+        Topic::TdCmdExchList,
+        Topic::TrCaf,
+        Topic::TdCaf,
+        Topic::TdIosPortfolioGroup,
+        Topic::TdIosCashManagementTrust,
+        Topic::MExchanges,
+        Topic::Unknown351,
+        Topic::Unknown484,
+        Topic::Unknown614,
+        Topic::Unknown1581,
+        Topic::Unknown1687,
+        Topic::Unknown1725Destination,
+        Topic::Unknown1868,
+        Topic::Unknown1994,
+        Topic::Unknown2025,
+        Topic::Unknown2324,
+        Topic::Unknown2329,
+        Topic::Unknown2412,
+        Topic::Unknown2465,
+        Topic::Unknown2519,
+        Topic::Unknown2771,
+        Topic::Unknown2777,
+        Topic::Unknown2783,
+        Topic::Unknown2787,
+        Topic::Unknown2781,
+        Topic::Unknown2757,
+        Topic::Unknown2840,
+        Topic::Unknown2872,
+        Topic::Unknown2951,
+        Topic::Unknown2977,
+        Topic::Unknown3097,
+        Topic::Unknown3137,
+        Topic::Unknown3260,
+        Topic::Unknown3314,
+        Topic::Unknown3338,
+        Topic::Unknown3339,
+        Topic::Unknown3348,
+        Topic::Unknown3349,
+        Topic::Unknown3455,
+        Topic::Unknown3468,
+        Topic::Unknown3479,
+        Topic::Unknown3491,
+        Topic::Unknown3530,
+        Topic::Unknown3643,
+        Topic::Unknown3651,
+        Topic::Unknown3751,
+    ];
+
+    /// Get the topic with the given wire id, if it is known.
+    #[must_use]
+    pub fn from_id(id: i32) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| *t as i32 == id)
+    }
+
+    /// Get a topic by its variant name, e.g. `"TrLogin"`.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| format!("{t:?}") == name)
+    }
+}
+
 bitflags! {
-    #[derive(Debug, Default)]
+    /// Where a packet sits in a response split across several packets. A
+    /// packet in the middle has neither flag set, and a response that fits
+    /// in one packet has both.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
     pub struct PacketFlags: u32 {
-        const CONTINUE = 0;
         const FIRST = 1;
         const LAST = 2;
     }
 }
 
+impl PacketFlags {
+    /// Whether this is a packet in the middle of a response.
+    #[must_use]
+    pub fn is_continuation(self) -> bool {
+        self.is_empty()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SDO {
     pub topic: Topic,
+    /// The wire id of the topic when `topic` is [`Topic::Unknown`].
+    pub unknown_topic: Option<i64>,
     pub fields: Vec<(Field, Option<Data>)>,
 }
 
@@ -223,7 +387,8 @@ impl SDO {
         }
         return self
             .get_field(PACKET_FLAG)
-            .and_then(|f| f.as_first_u32().and_then(PacketFlags::from_bits));
+            // Bits we don't know about don't stop us using the ones we do.
+            .and_then(|f| f.as_first_u32().map(PacketFlags::from_bits_truncate));
     }
 
     #[must_use]
@@ -308,15 +473,73 @@ impl SDO {
     pub fn new(topic: Topic) -> Self {
         Self {
             topic,
+            unknown_topic: None,
             fields: vec![],
         }
     }
 
+    /// An SDO on the topic with the given wire id, which is
+    /// [`Topic::Unknown`] if the id is missing from [`Topic`].
+    #[must_use]
+    pub fn with_topic_id(id: i64) -> Self {
+        match i32::try_from(id).ok().and_then(Topic::from_id) {
+            Some(topic) => Self::new(topic),
+            None => Self {
+                unknown_topic: Some(id),
+                ..Self::new(Topic::Unknown)
+            },
+        }
+    }
+
+    /// The topic's wire id, including unknown topics.
+    #[must_use]
+    pub fn topic_id(&self) -> i64 {
+        match (self.topic, self.unknown_topic) {
+            (Topic::Unknown, Some(id)) => id,
+            (topic, _) => i64::from(topic as i32),
+        }
+    }
+
     pub fn remove_field(&mut self, id: u32) {
         self.fields
             .retain_mut(|(header, _data)| header.field_id != Some(id));
     }
 
+    /// Split a list response into one SDO per row. Fields with fewer rows
+    /// than the longest field describe the whole response, so they are left
+    /// out. A response without any rows gives an empty `Vec`.
+    #[must_use]
+    pub fn rows(&self) -> Vec<SDO> {
+        let row_count = |data: &Option<Data>| data.as_ref().map_or(1, |d| d.null_rows().len());
+        let rows = self
+            .fields
+            .iter()
+            .map(|(_, data)| row_count(data))
+            .max()
+            .unwrap_or_default();
+        (0..rows)
+            .map(|i| {
+                let mut row = SDO::with_topic_id(self.topic_id());
+                for (header, data) in &self.fields {
+                    if row_count(data) != rows {
+                        continue;
+                    }
+                    if let Some(data) = data.as_ref().and_then(|d| d.row(i)) {
+                        let mut header = header.clone();
+                        header.set_rows(&data);
+                        row.fields.push((header, Some(data)));
+                    }
+                }
+                row
+            })
+            .collect()
+    }
+
+    /// Push a field of any type.
+    pub fn push_data(&mut self, field_id: u32, data: Data) {
+        self.fields.push((Field::for_data(field_id, &data), Some(data)));
+    }
+
     #[must_use]
     pub fn get_field(&self, id: u32) -> Option<&Data> {
         self.fields
@@ -437,16 +660,20 @@ impl SDO {
 #[derive(Debug, Clone)]
 pub struct Message {
     pub id: Option<String>,
-    pub timeout: Option<String>,
+    /// How long the server, and our client, should wait for the request to
+    /// complete. Sent as `TIMEOUT`, rounded by [`Message::rounded_timeout`].
+    pub timeout: Option<Duration>,
     pub page_size: Option<u32>,
     pub sdo: SDO,
 }
 
 impl Message {
+    /// A message with an id from `ids`, usually
+    /// [`RandomIds`](request_id::RandomIds).
     #[must_use]
-    pub fn new(topic: Topic) -> Self {
+    pub fn new(topic: Topic, ids: &mut impl RequestIdGenerator) -> Self {
         Self {
-            id: Some(generate_request_id()),
+            id: Some(ids.next_id()),
             sdo: SDO::new(topic),
             timeout: None,
             page_size: None,
@@ -462,6 +689,20 @@ impl Message {
             page_size: None,
         }
     }
+
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The timeout as sent in `TIMEOUT`, rounded up by
+    /// [`util::round_timeout`] like [`util::format_timeout`] does. Clients
+    /// wait this long too, so that they give up when the server does.
+    #[must_use]
+    pub fn rounded_timeout(&self) -> Option<Duration> {
+        self.timeout.map(util::round_timeout)
+    }
 }
 
 #[derive(Clone)]
@@ -490,6 +731,11 @@ impl std::fmt::Debug for Field {
 }
 
 impl Field {
+    #[must_use]
+    pub fn field_id(&self) -> Option<u32> {
+        self.field_id
+    }
+
     fn new(single_row: bool) -> Self {
         Self {
             data_type: DataType::NoType1,
@@ -501,6 +747,51 @@ impl Field {
             wire_type: WireType::Varint,
         }
     }
+
+    /// Build a header describing `data`, as the `push_*` helpers do.
+    fn for_data(field_id: u32, data: &Data) -> Self {
+        let (data_type, wire_type) = match data {
+            Data::StringW(_) => (DataType::StringW, WireType::LengthDelimited),
+            Data::AsciiString(_) => (DataType::String, WireType::LengthDelimited),
+            Data::Bool(_) => (DataType::Boolean, WireType::Varint),
+            Data::Long(_) => (DataType::Long, WireType::Varint),
+            Data::Short(_) => (DataType::Short, WireType::Varint),
+            Data::LongLong(_) => (DataType::LongLong, WireType::Varint),
+            Data::SDO(_) => (DataType::SDO, WireType::EmbeddedSDO),
+            Data::Double(_) => (DataType::Double, WireType::Bit64),
+            Data::Float(_) => (DataType::Float, WireType::Bit64),
+            Data::DateTime(_) => (DataType::DateTime, WireType::Varint),
+            Data::Char(_) => (DataType::Char, WireType::Varint),
+            Data::Binary(_) => (DataType::Binary, WireType::LengthDelimited),
+            Data::Unknown => (DataType::Unknown, WireType::Unknown),
+        };
+        let mut field = Self {
+            data_type,
+            // Datetimes are always sent with millisecond precision.
+            extra_info: (data_type == DataType::DateTime).then(|| vec![2]),
+            field_id: Some(field_id),
+            null_flags: None,
+            rows: 0,
+            single_row: false,
+            wire_type,
+        };
+        field.set_rows(data);
+        field
+    }
+
+    /// Update the row count and null flags to match `data`.
+    fn set_rows(&mut self, data: &Data) {
+        let nulls = data.null_rows();
+        self.null_flags = nulls.contains(&true).then(|| {
+            let mut flags = vec![0u8; nulls.len().div_ceil(8)];
+            for (i, _) in nulls.iter().enumerate().filter(|(_, null)| **null) {
+                flags[i / 8] |= 1 << (7 - (i % 8));
+            }
+            flags
+        });
+        self.rows = nulls.len() as u32;
+        self.single_row = nulls.len() == 1;
+    }
 }
 
 #[allow(unused)]
@@ -573,3 +864,39 @@ impl From<u8> for WireType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::{HAS_MORE_DATA, ORDER_NUMBER, SECURITY_CODE};
+
+    #[test]
+    fn rows_of_an_empty_response() {
+        assert!(SDO::new(Topic::TdIosOrders).rows().is_empty());
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        sdo.push_long(ORDER_NUMBER, Vec::<Option<u32>>::new());
+        assert!(sdo.rows().is_empty());
+    }
+
+    #[test]
+    fn rows_of_a_one_row_response() {
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        sdo.push_long(ORDER_NUMBER, Some(1));
+        sdo.push_string_w(SECURITY_CODE, Vec::<Option<String>>::new());
+        let rows = sdo.rows();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get_field(ORDER_NUMBER).and_then(Data::as_first_u32), Some(1));
+        assert!(rows[0].get_field(SECURITY_CODE).is_none());
+    }
+
+    #[test]
+    fn rows_leave_out_response_wide_fields() {
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        sdo.push_long(ORDER_NUMBER, vec![Some(1), Some(2), None]);
+        sdo.push_long(HAS_MORE_DATA, Some(1));
+        let rows = sdo.rows();
+        let orders = rows.iter().map(|r| r.get_field(ORDER_NUMBER).and_then(Data::as_first_u32)).collect::<Vec<_>>();
+        assert_eq!(orders, [Some(1), Some(2), None]);
+        assert!(rows.iter().all(|r| r.get_field(HAS_MORE_DATA).is_none()));
+    }
+}
@@ -0,0 +1,205 @@
+//! Logging in and out.
+//!
+//! A session starts with a [`LoginRequest`] on `TrLogin`, answered on
+//! `TdLogin`. `TdLogout` ends it, whether we send it or the server does.
+
+use crate::{ClientType, FromSdo, SdoRequest};
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum Error {
+    #[error("login rejected: {}", .message.as_deref().unwrap_or("no reason given"))]
+    Rejected {
+        number: Option<u32>,
+        message: Option<String>,
+    },
+}
+
+/// Logs in with a password or an ACS login token.
+///
+/// ```
+/// # use sdo::{login::LoginRequest, ClientType};
+/// let login = LoginRequest::new("user", "company")
+///     .password("hunter2")
+///     .client_type(ClientType::WebIress);
+/// ```
+#[derive(Clone, SdoRequest)]
+#[sdo(request = TrLogin, response = TdLogin, reply = LoginResponse)]
+pub struct LoginRequest {
+    #[sdo(field = USER_NAME)]
+    user_name: String,
+    #[sdo(field = PASSWORD)]
+    password: Option<String>,
+    #[sdo(field = COMPANY_NAME)]
+    company_name: String,
+    #[sdo(field = CLIENT_TYPE)]
+    client_type: u32,
+    #[sdo(field = PRODUCT_FULL_VERSION)]
+    product_full_version: Option<String>,
+    #[sdo(field = ACS_LOGIN_TOKEN)]
+    acs_login_token: Option<String>,
+}
+
+impl LoginRequest {
+    #[must_use]
+    pub fn new(user_name: impl Into<String>, company_name: impl Into<String>) -> Self {
+        Self {
+            user_name: user_name.into(),
+            password: None,
+            company_name: company_name.into(),
+            client_type: ClientType::WebIress as u32,
+            product_full_version: None,
+            acs_login_token: None,
+        }
+    }
+
+    #[must_use]
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Defaults to [`ClientType::WebIress`].
+    #[must_use]
+    pub fn client_type(mut self, client_type: ClientType) -> Self {
+        self.client_type = client_type as u32;
+        self
+    }
+
+    #[must_use]
+    pub fn product_full_version(mut self, version: impl Into<String>) -> Self {
+        self.product_full_version = Some(version.into());
+        self
+    }
+
+    /// Log in with a token from ACS instead of a password.
+    #[must_use]
+    pub fn acs_login_token(mut self, token: impl Into<String>) -> Self {
+        self.acs_login_token = Some(token.into());
+        self
+    }
+}
+
+// Hand-written so passwords and tokens stay out of logs.
+impl std::fmt::Debug for LoginRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginRequest")
+            .field("user_name", &self.user_name)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("company_name", &self.company_name)
+            .field("client_type", &self.client_type)
+            .field("product_full_version", &self.product_full_version)
+            .field(
+                "acs_login_token",
+                &self.acs_login_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+/// The `TdLogin` reply.
+#[derive(Debug, Clone, FromSdo)]
+pub struct LoginResponse {
+    #[sdo(field = LOGGED_IN)]
+    pub logged_in: Option<bool>,
+    #[sdo(field = USER_NAME)]
+    pub user_name: Option<String>,
+    #[sdo(field = COMPANY_NAME)]
+    pub company_name: Option<String>,
+    #[sdo(field = SESSION_TYPE)]
+    pub session_type: Option<u32>,
+    #[sdo(field = IS_PINGABLE)]
+    pub is_pingable: Option<bool>,
+    #[sdo(field = ERROR_NUMBER)]
+    pub error_number: Option<u32>,
+    #[sdo(field = ERROR_MESSAGE)]
+    pub error_message: Option<String>,
+}
+
+impl LoginResponse {
+    /// Without an explicit `LOGGED_IN`, a reply without an error counts as
+    /// logged in.
+    #[must_use]
+    pub fn is_logged_in(&self) -> bool {
+        self.logged_in
+            .unwrap_or(self.error_number.is_none() && self.error_message.is_none())
+    }
+
+    /// `Ok` if the login succeeded, else why it didn't.
+    pub fn into_result(self) -> Result<Self, Error> {
+        if self.is_logged_in() {
+            Ok(self)
+        } else {
+            Err(Error::Rejected {
+                number: self.error_number,
+                message: self.error_message,
+            })
+        }
+    }
+}
+
+/// Ends the session. The server sends one too when it ends the session
+/// itself, e.g. because the same user logged in elsewhere.
+#[derive(Debug, Clone, Default, SdoRequest, FromSdo)]
+#[sdo(request = TdLogout, response = TdLogout, reply = Logout)]
+pub struct Logout {
+    #[sdo(field = STATUS_DESCRIPTION)]
+    pub reason: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fields::{ERROR_MESSAGE, ERROR_NUMBER, LOGGED_IN, USER_NAME},
+        Topic, SDO,
+    };
+
+    fn reply(logged_in: Option<bool>) -> SDO {
+        let mut sdo = SDO::new(Topic::TdLogin);
+        sdo.push_string_w(USER_NAME, Some("user".to_owned()));
+        if let Some(logged_in) = logged_in {
+            sdo.push_bool(LOGGED_IN, Some(logged_in));
+        }
+        sdo
+    }
+
+    #[test]
+    fn accepts_logged_in_replies() {
+        let response = LoginResponse::from_sdo(&reply(Some(true))).unwrap();
+        let response = response.into_result().unwrap();
+        assert_eq!(response.user_name.as_deref(), Some("user"));
+        assert!(LoginResponse::from_sdo(&reply(None))
+            .unwrap()
+            .into_result()
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_replies_that_are_not_logged_in() {
+        let mut sdo = reply(Some(false));
+        sdo.push_long(ERROR_NUMBER, Some(12));
+        sdo.push_string_w(ERROR_MESSAGE, Some("bad password".to_owned()));
+        let error = LoginResponse::from_sdo(&sdo)
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert!(matches!(
+            &error,
+            Error::Rejected { number: Some(12), message: Some(m) } if m == "bad password"
+        ));
+        assert_eq!(error.to_string(), "login rejected: bad password");
+
+        let error = LoginResponse::from_sdo(&reply(Some(false)))
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "login rejected: no reason given");
+    }
+
+    #[test]
+    fn errors_without_logged_in_are_rejections() {
+        let mut sdo = reply(None);
+        sdo.push_long(ERROR_NUMBER, Some(12));
+        assert!(!LoginResponse::from_sdo(&sdo).unwrap().is_logged_in());
+    }
+}
@@ -0,0 +1,310 @@
+use time::OffsetDateTime;
+
+use crate::{
+    data::Data,
+    error::SdoServerError,
+    request_id::RequestIdGenerator,
+    Message, Topic, SDO,
+};
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum Error {
+    #[error("unexpected response topic {actual:?}, expected {expected:?}")]
+    UnexpectedTopic { expected: Topic, actual: Topic },
+    #[error("missing field {0}")]
+    MissingField(u32),
+    #[error("field {0} has an unexpected data type")]
+    InvalidField(u32),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Server(#[from] Box<SdoServerError>),
+}
+
+/// A request struct paired with the topic it is sent on and the topic (and
+/// type) it is answered with. Usually derived with `#[derive(SdoRequest)]`.
+pub trait SdoRequest {
+    const TOPIC: Topic;
+    const RESPONSE_TOPIC: Topic;
+    type Response: FromSdo;
+
+    /// Push this request's fields onto `sdo`.
+    fn write_fields(&self, sdo: &mut SDO);
+
+    #[must_use]
+    fn to_sdo(&self) -> SDO {
+        let mut sdo = SDO::new(Self::TOPIC);
+        self.write_fields(&mut sdo);
+        sdo
+    }
+
+    /// Build a message for this request with an id from `ids`.
+    #[must_use]
+    fn to_message(&self, ids: &mut impl RequestIdGenerator) -> Message {
+        let mut message = Message::new(Self::TOPIC, ids);
+        self.write_fields(&mut message.sdo);
+        message
+    }
+
+    /// Parse a reply, checking it arrived on `RESPONSE_TOPIC`. A `TdError`
+    /// reply becomes [`Error::Server`].
+    fn parse_response(sdo: &SDO) -> Result<Self::Response, Error> {
+        if let Some(error) = SdoServerError::from_response(sdo) {
+            return Err(Box::new(error).into());
+        }
+        if sdo.topic != Self::RESPONSE_TOPIC {
+            return Err(Error::UnexpectedTopic {
+                expected: Self::RESPONSE_TOPIC,
+                actual: sdo.topic,
+            });
+        }
+        Self::Response::from_sdo(sdo)
+    }
+}
+
+/// A type that can be read out of a decoded SDO. Usually derived with
+/// `#[derive(FromSdo)]`.
+pub trait FromSdo: Sized {
+    fn from_sdo(sdo: &SDO) -> Result<Self, Error>;
+}
+
+impl FromSdo for SDO {
+    fn from_sdo(sdo: &SDO) -> Result<Self, Error> {
+        Ok(sdo.clone())
+    }
+}
+
+/// A value that can be pushed onto an SDO as a single field.
+pub trait IntoField {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32);
+}
+
+impl IntoField for String {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_string_w(field_id, Some(self.clone()));
+    }
+}
+
+impl IntoField for u32 {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_long(field_id, Some(*self));
+    }
+}
+
+impl IntoField for bool {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_bool(field_id, Some(*self));
+    }
+}
+
+impl IntoField for u64 {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_data(field_id, Data::LongLong(vec![Some(Box::new(*self))]));
+    }
+}
+
+impl IntoField for f64 {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_data(field_id, Data::Double(vec![Some(Box::new(*self))]));
+    }
+}
+
+impl IntoField for char {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_data(field_id, Data::Char(vec![Some(Box::new(*self))]));
+    }
+}
+
+impl IntoField for OffsetDateTime {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_data(field_id, Data::DateTime(vec![Some(Box::new(*self))]));
+    }
+}
+
+impl IntoField for SDO {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_sdo(field_id, Some(self.clone()));
+    }
+}
+
+impl IntoField for Vec<String> {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_string_w(field_id, self.iter().cloned().map(Some).collect::<Vec<_>>());
+    }
+}
+
+impl IntoField for Vec<u32> {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_long(field_id, self.iter().copied().map(Some).collect::<Vec<_>>());
+    }
+}
+
+impl IntoField for Vec<u64> {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_data(field_id, Data::LongLong(self.iter().map(|v| Some(Box::new(*v))).collect()));
+    }
+}
+
+impl IntoField for Vec<f64> {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_data(field_id, Data::Double(self.iter().map(|v| Some(Box::new(*v))).collect()));
+    }
+}
+
+impl IntoField for Vec<SDO> {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        sdo.push_sdo(field_id, self.iter().cloned().map(Some).collect::<Vec<_>>());
+    }
+}
+
+impl<T: IntoField> IntoField for Option<T> {
+    fn push_field(&self, sdo: &mut SDO, field_id: u32) {
+        if let Some(value) = self {
+            value.push_field(sdo, field_id);
+        }
+    }
+}
+
+/// A value that can be read from a single field of an SDO. Single values
+/// come from the first non-null row, vectors skip null rows.
+pub trait FromField: Sized {
+    fn from_data(data: &Data) -> Option<Self>;
+
+    fn read_field(sdo: &SDO, field_id: u32) -> Result<Self, Error> {
+        let data = sdo
+            .get_field(field_id)
+            .ok_or(Error::MissingField(field_id))?;
+        Self::from_data(data).ok_or(Error::InvalidField(field_id))
+    }
+}
+
+impl FromField for String {
+    fn from_data(data: &Data) -> Option<Self> {
+        data.as_first_str().map(ToOwned::to_owned)
+    }
+}
+
+impl FromField for u32 {
+    fn from_data(data: &Data) -> Option<Self> {
+        data.as_vec_u32()?.into_iter().find_map(|o| o)
+    }
+}
+
+impl FromField for u64 {
+    fn from_data(data: &Data) -> Option<Self> {
+        data.as_vec_u64()?.into_iter().find_map(|o| o)
+    }
+}
+
+impl FromField for bool {
+    fn from_data(data: &Data) -> Option<Self> {
+        data.as_first_bool()
+    }
+}
+
+impl FromField for f64 {
+    fn from_data(data: &Data) -> Option<Self> {
+        data.as_vec_f64()?.into_iter().find_map(|o| o)
+    }
+}
+
+impl FromField for char {
+    fn from_data(data: &Data) -> Option<Self> {
+        data.as_vec_char()?.into_iter().find_map(|o| o)
+    }
+}
+
+impl FromField for OffsetDateTime {
+    fn from_data(data: &Data) -> Option<Self> {
+        data.to_vec_datetime()?.into_iter().find_map(|o| o)
+    }
+}
+
+impl FromField for SDO {
+    fn from_data(data: &Data) -> Option<Self> {
+        data.to_vec_sdo()?.into_iter().find_map(|o| o)
+    }
+}
+
+impl FromField for Vec<String> {
+    fn from_data(data: &Data) -> Option<Self> {
+        Some(data.to_vec_string()?.into_iter().flatten().collect())
+    }
+}
+
+impl FromField for Vec<u32> {
+    fn from_data(data: &Data) -> Option<Self> {
+        Some(data.as_vec_u32()?.into_iter().flatten().collect())
+    }
+}
+
+impl FromField for Vec<u64> {
+    fn from_data(data: &Data) -> Option<Self> {
+        Some(data.as_vec_u64()?.into_iter().flatten().collect())
+    }
+}
+
+impl FromField for Vec<f64> {
+    fn from_data(data: &Data) -> Option<Self> {
+        Some(data.as_vec_f64()?.into_iter().flatten().collect())
+    }
+}
+
+impl FromField for Vec<SDO> {
+    fn from_data(data: &Data) -> Option<Self> {
+        Some(data.to_vec_sdo()?.into_iter().flatten().collect())
+    }
+}
+
+/// Optional fields are `None` when absent or null rather than an error. A
+/// field holding something `T` can't be read from is still an error.
+impl<T: FromField> FromField for Option<T> {
+    fn from_data(data: &Data) -> Option<Self> {
+        if data.null_rows().iter().all(|null| *null) {
+            return Some(None);
+        }
+        T::from_data(data).map(Some)
+    }
+
+    fn read_field(sdo: &SDO, field_id: u32) -> Result<Self, Error> {
+        match sdo.get_field(field_id) {
+            Some(data) => Self::from_data(data).ok_or(Error::InvalidField(field_id)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::{ORDER_NUMBER, SECURITY_CODE};
+
+    fn order() -> SDO {
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        sdo.push_long(ORDER_NUMBER, Some(7));
+        sdo.push_string_w(SECURITY_CODE, vec![None]);
+        sdo
+    }
+
+    #[test]
+    fn required_fields_must_be_present_and_the_right_type() {
+        assert_eq!(u32::read_field(&order(), ORDER_NUMBER).unwrap(), 7);
+        assert!(matches!(String::read_field(&order(), ORDER_NUMBER), Err(Error::InvalidField(ORDER_NUMBER))));
+        assert!(matches!(u32::read_field(&order(), 9999), Err(Error::MissingField(9999))));
+    }
+
+    #[test]
+    fn optional_fields_are_none_only_when_absent_or_null() {
+        assert_eq!(Option::<u32>::read_field(&order(), ORDER_NUMBER).unwrap(), Some(7));
+        assert_eq!(Option::<u32>::read_field(&order(), 9999).unwrap(), None);
+        assert_eq!(Option::<String>::read_field(&order(), SECURITY_CODE).unwrap(), None);
+        assert!(matches!(Option::<String>::read_field(&order(), ORDER_NUMBER), Err(Error::InvalidField(ORDER_NUMBER))));
+    }
+
+    #[test]
+    fn optional_fields_are_left_out_when_none() {
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        Some(7u32).push_field(&mut sdo, ORDER_NUMBER);
+        None::<String>.push_field(&mut sdo, SECURITY_CODE);
+        assert_eq!(sdo.fields.len(), 1);
+    }
+}
@@ -0,0 +1,139 @@
+//! Generating request ids.
+//!
+//! Every request carries an id that the server echoes back on its response.
+//! By default ids are random, as the official clients make them. Tests that
+//! compare encoded bytes can use [`CounterIds`] or [`SeededIds`] instead,
+//! so the same messages get the same ids every run.
+//!
+//! ```
+//! # use sdo::{request_id::CounterIds, Message, Topic};
+//! let mut ids = CounterIds::new();
+//! let message = Message::new(Topic::TrLogin, &mut ids);
+//! assert_eq!(message.id.as_deref(), Some("R_1_"));
+//! ```
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// A source of request ids.
+pub trait RequestIdGenerator {
+    fn next_id(&mut self) -> String;
+}
+
+impl<G: RequestIdGenerator + ?Sized> RequestIdGenerator for &mut G {
+    fn next_id(&mut self) -> String {
+        (**self).next_id()
+    }
+}
+
+impl<G: RequestIdGenerator + ?Sized> RequestIdGenerator for Box<G> {
+    fn next_id(&mut self) -> String {
+        (**self).next_id()
+    }
+}
+
+/// Random ids like `R_6181224356346302_`, from the thread's random number
+/// generator. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIds;
+
+impl RequestIdGenerator for RandomIds {
+    fn next_id(&mut self) -> String {
+        format_random(&mut rand::thread_rng())
+    }
+}
+
+/// Ids like the random ones, from a generator seeded with a fixed value.
+#[derive(Debug, Clone)]
+pub struct SeededIds {
+    rng: StdRng,
+}
+
+impl SeededIds {
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl RequestIdGenerator for SeededIds {
+    fn next_id(&mut self) -> String {
+        format_random(&mut self.rng)
+    }
+}
+
+/// Ids `R_1_`, `R_2_` and so on, which never repeat.
+#[derive(Debug, Clone)]
+pub struct CounterIds {
+    next: u64,
+}
+
+impl Default for CounterIds {
+    fn default() -> Self {
+        Self::starting_at(1)
+    }
+}
+
+impl CounterIds {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn starting_at(next: u64) -> Self {
+        Self { next }
+    }
+}
+
+impl RequestIdGenerator for CounterIds {
+    fn next_id(&mut self) -> String {
+        let id = format!("R_{}_", self.next);
+        self.next += 1;
+        id
+    }
+}
+
+fn format_random(rng: &mut impl Rng) -> String {
+    let id = rng.gen::<f64>().to_string();
+    let (_, id) = id.split_once('.').unwrap_or_default();
+    format!("R_{id}_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, Topic};
+
+    #[test]
+    fn counter_ids_count_up() {
+        let mut ids = CounterIds::starting_at(41);
+        assert_eq!(ids.next_id(), "R_41_");
+        assert_eq!(ids.next_id(), "R_42_");
+    }
+
+    #[test]
+    fn seeded_ids_repeat() {
+        let first = (0..3).map(|_| SeededIds::new(7).next_id()).collect::<Vec<_>>();
+        assert!(first.iter().all(|id| *id == first[0]));
+        let mut ids = SeededIds::new(7);
+        assert_eq!(ids.next_id(), first[0]);
+        assert_ne!(ids.next_id(), first[0]);
+        assert!(first[0].starts_with("R_") && first[0].ends_with('_'));
+    }
+
+    #[test]
+    fn golden_bytes() {
+        let mut ids: Box<dyn RequestIdGenerator> = Box::new(CounterIds::new());
+        let bytes = Message::new(Topic::TrPing, &mut ids).encode().unwrap();
+        #[rustfmt::skip]
+        let golden = [
+            // Header SDO on the undefined topic, holding REQUEST_ID "R_1_".
+            0x17, 0x00, 0x4c, 0x94, 0x01, 0x05, 0x00, b'R', b'_', b'1', b'_', 0x00,
+            // The TrPing payload, with the default PAGE_SIZE "1000".
+            0x17, 0xac, 0x0c, 0x4c, 0xc8, 0x14, 0x05, 0x00, b'1', b'0', b'0', b'0', 0x00,
+        ];
+        assert_eq!(bytes, golden);
+    }
+}
@@ -0,0 +1,221 @@
+//! Dispatching messages to handlers by topic.
+//!
+//! ```
+//! # use sdo::{router::{Route, Router}, Message, Topic};
+//! # use tracing::{info, warn};
+//! # let messages = vec![Message::new_with_id(Topic::TdMessage, None)];
+//! let mut news = vec![];
+//! let mut router = Router::new()
+//!     .on(Topic::TdLogin, |message| info!("logged in"))
+//!     .route(Route::new(Topic::TdMessage).message_source("IOS"), |message| {
+//!         news.push(message.clone())
+//!     })
+//!     .fallback(|message| warn!(topic = ?message.sdo.topic, "unhandled"));
+//! for message in messages {
+//!     router.dispatch(&message);
+//! }
+//! ```
+
+use crate::{Message, Topic};
+
+type Handler<'a, R> = Box<dyn FnMut(&Message) -> R + Send + 'a>;
+
+/// Which messages a handler is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    topic: Topic,
+    message_source: Option<String>,
+    target_id: Option<String>,
+}
+
+impl Route {
+    #[must_use]
+    pub fn new(topic: Topic) -> Self {
+        Self {
+            topic,
+            message_source: None,
+            target_id: None,
+        }
+    }
+
+    /// Only match messages with this `MESSAGE_SOURCE`.
+    #[must_use]
+    pub fn message_source(mut self, source: impl Into<String>) -> Self {
+        self.message_source = Some(source.into());
+        self
+    }
+
+    /// Only match messages with this `TARGET_ID`.
+    #[must_use]
+    pub fn target_id(mut self, target_id: impl Into<String>) -> Self {
+        self.target_id = Some(target_id.into());
+        self
+    }
+
+    #[must_use]
+    pub fn matches(&self, message: &Message) -> bool {
+        let sdo = &message.sdo;
+        sdo.topic == self.topic
+            && (self.message_source.is_none()
+                || self.message_source.as_deref() == sdo.message_source())
+            && (self.target_id.is_none() || self.target_id.as_deref() == sdo.target_id())
+    }
+
+    /// How many fields beyond the topic the route checks.
+    fn specificity(&self) -> usize {
+        usize::from(self.message_source.is_some()) + usize::from(self.target_id.is_some())
+    }
+}
+
+impl From<Topic> for Route {
+    fn from(topic: Topic) -> Self {
+        Self::new(topic)
+    }
+}
+
+/// Hands each message to the handler of the most specific route that
+/// matches it, or to the fallback if none do. Routes that are equally
+/// specific are tried in the order they were added.
+pub struct Router<'a, R = ()> {
+    routes: Vec<(Route, Handler<'a, R>)>,
+    fallback: Option<Handler<'a, R>>,
+}
+
+impl<R> Default for Router<'_, R> {
+    fn default() -> Self {
+        Self {
+            routes: vec![],
+            fallback: None,
+        }
+    }
+}
+
+impl<'a, R> Router<'a, R> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle every message on `topic`.
+    #[must_use]
+    pub fn on(self, topic: Topic, handler: impl FnMut(&Message) -> R + Send + 'a) -> Self {
+        self.route(topic, handler)
+    }
+
+    #[must_use]
+    pub fn route(
+        mut self,
+        route: impl Into<Route>,
+        handler: impl FnMut(&Message) -> R + Send + 'a,
+    ) -> Self {
+        self.routes.push((route.into(), Box::new(handler)));
+        self
+    }
+
+    /// Handle messages no route matches.
+    #[must_use]
+    pub fn fallback(mut self, handler: impl FnMut(&Message) -> R + Send + 'a) -> Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Run the handler for `message`. `None` if nothing handled it.
+    pub fn dispatch(&mut self, message: &Message) -> Option<R> {
+        let handler = self
+            .routes
+            .iter_mut()
+            .filter(|(route, _)| route.matches(message))
+            // `min_by_key` keeps the first of equals.
+            .min_by_key(|(route, _)| std::cmp::Reverse(route.specificity()))
+            .map(|(_, handler)| handler)
+            .or(self.fallback.as_mut());
+        handler.map(|handler| handler(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::{MESSAGE_SOURCE, TARGET_ID};
+
+    fn message(topic: Topic, source: Option<&str>, target: Option<&str>) -> Message {
+        let mut message = Message::new_with_id(topic, None);
+        if let Some(source) = source {
+            message
+                .sdo
+                .push_string_w(MESSAGE_SOURCE, Some(source.to_owned()));
+        }
+        if let Some(target) = target {
+            message
+                .sdo
+                .push_string_w(TARGET_ID, Some(target.to_owned()));
+        }
+        message
+    }
+
+    fn router() -> Router<'static, &'static str> {
+        Router::new()
+            .on(Topic::TdMessage, |_| "topic")
+            .route(Route::new(Topic::TdMessage).message_source("IOS"), |_| {
+                "source"
+            })
+            .route(
+                Route::new(Topic::TdMessage)
+                    .message_source("IOS")
+                    .target_id("a"),
+                |_| "source and target",
+            )
+            .route(Route::new(Topic::TdMessage).target_id("b"), |_| "target")
+    }
+
+    #[test]
+    fn the_most_specific_route_wins() {
+        let mut router = router();
+        let mut dispatch =
+            |source, target| router.dispatch(&message(Topic::TdMessage, source, target));
+        assert_eq!(dispatch(None, None), Some("topic"));
+        assert_eq!(dispatch(Some("IOS"), None), Some("source"));
+        assert_eq!(dispatch(Some("IOS"), Some("a")), Some("source and target"));
+        assert_eq!(dispatch(None, Some("b")), Some("target"));
+        assert_eq!(dispatch(Some("News"), Some("a")), Some("topic"));
+    }
+
+    #[test]
+    fn equally_specific_routes_go_in_insertion_order() {
+        let mut router = router();
+        // Both the `source` and `target` routes match.
+        let both = message(Topic::TdMessage, Some("IOS"), Some("b"));
+        assert_eq!(router.dispatch(&both), Some("source"));
+        let mut router = Router::new()
+            .on(Topic::TdLogin, |_| 1)
+            .on(Topic::TdLogin, |_| 2);
+        assert_eq!(
+            router.dispatch(&message(Topic::TdLogin, None, None)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn unmatched_messages_go_to_the_fallback() {
+        let login = message(Topic::TdLogin, None, None);
+        assert_eq!(router().dispatch(&login), None);
+        let mut router = router().fallback(|_| "fallback");
+        assert_eq!(router.dispatch(&login), Some("fallback"));
+        assert_eq!(
+            router.dispatch(&message(Topic::TdMessage, None, None)),
+            Some("topic")
+        );
+    }
+
+    #[test]
+    fn handlers_keep_their_state() {
+        let mut seen = vec![];
+        let mut router = Router::new().on(Topic::TdMessage, |message: &Message| {
+            seen.push(message.sdo.message_source().map(ToOwned::to_owned));
+        });
+        router.dispatch(&message(Topic::TdMessage, Some("IOS"), None));
+        router.dispatch(&message(Topic::TdMessage, None, None));
+        drop(router);
+        assert_eq!(seen, [Some("IOS".to_owned()), None]);
+    }
+}
@@ -1,8 +1,83 @@
-use rand::Rng;
+use std::{fmt::Write, time::Duration};
 
+use crate::request_id::{RandomIds, RequestIdGenerator};
+
+/// A random request id, as [`RandomIds`] makes them.
 #[must_use] pub fn generate_request_id() -> String {
-    let mut rng = rand::thread_rng();
-    let id = rng.gen::<f64>().to_string();
-    let (_, id) = id.split_once('.').unwrap_or_default();
-    format!("R_{id}_")
+    RandomIds.next_id()
+}
+
+/// Round a timeout up to what the `TIMEOUT` field can carry: whole seconds,
+/// and at least one, so a short timeout (even `Duration::ZERO`) doesn't
+/// become no time at all.
+#[must_use] pub fn round_timeout(timeout: Duration) -> Duration {
+    let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+    Duration::from_secs(secs.max(1))
+}
+
+/// Format a timeout for the `TIMEOUT` field, rounded by [`round_timeout`].
+#[must_use] pub fn format_timeout(timeout: Duration) -> String {
+    round_timeout(timeout).as_secs().to_string()
+}
+
+/// Parse a `TIMEOUT` field value.
+#[must_use] pub fn parse_timeout(timeout: &str) -> Option<Duration> {
+    timeout.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Lowercase hex, two digits per byte and no separators.
+#[must_use] pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+/// Parse hex as written by [`to_hex`], in either case. `None` if there is an
+/// odd number of digits or anything else in the string.
+#[must_use] pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    hex.as_bytes().chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeouts_round_up_to_whole_seconds() {
+        assert_eq!(format_timeout(Duration::ZERO), "1");
+        assert_eq!(format_timeout(Duration::from_millis(1)), "1");
+        assert_eq!(format_timeout(Duration::from_secs(30)), "30");
+        assert_eq!(format_timeout(Duration::from_millis(30_001)), "31");
+    }
+
+    #[test]
+    fn timeouts_round_trip() {
+        for secs in [1, 30, 3600] {
+            let timeout = Duration::from_secs(secs);
+            assert_eq!(parse_timeout(&format_timeout(timeout)), Some(timeout));
+        }
+        let timeout = Duration::from_millis(1500);
+        assert_eq!(parse_timeout(&format_timeout(timeout)), Some(round_timeout(timeout)));
+        assert_eq!(parse_timeout(" 5 "), Some(Duration::from_secs(5)));
+        assert_eq!(parse_timeout("5s"), None);
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(from_hex(&to_hex(&bytes)), Some(bytes));
+        assert_eq!(from_hex("DEADbeef"), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(from_hex(""), Some(vec![]));
+    }
+
+    #[test]
+    fn rejects_invalid_hex() {
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("+f"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
 }
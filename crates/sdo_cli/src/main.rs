@@ -0,0 +1,458 @@
+#![warn(clippy::pedantic)]
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::{Parser, Subcommand, ValueEnum};
+use miette::{Context, IntoDiagnostic, Result};
+use sdo::{
+    capture::{
+        self,
+        recording::{self, Pace, Recorder, Replayer},
+        CapturedFrame, CapturedMessage,
+    },
+    decode, dissect, json,
+    util::{from_hex, to_hex},
+};
+use time::format_description::well_known::Rfc3339;
+
+mod tree;
+
+/// Decode and encode Iress SDO messages.
+#[derive(Debug, Parser)]
+#[command(name = "sdo", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Decode bytes into an annotated tree or JSON.
+    Decode {
+        /// How the input bytes are written.
+        #[arg(short, long, value_enum, default_value_t = InputFormat::Auto)]
+        format: InputFormat,
+        /// Decode a bare SDO instead of a message (header and payload SDO).
+        #[arg(long)]
+        sdo: bool,
+        /// Print JSON instead of a tree.
+        #[arg(long)]
+        json: bool,
+        /// Input file; reads stdin when omitted.
+        file: Option<PathBuf>,
+    },
+    /// Annotate every byte range of the input with what it encodes.
+    Dissect {
+        /// How the input bytes are written.
+        #[arg(short, long, value_enum, default_value_t = InputFormat::Auto)]
+        format: InputFormat,
+        /// Dissect a bare SDO instead of a message.
+        #[arg(long)]
+        sdo: bool,
+        /// Print JSON spans instead of aligned text.
+        #[arg(long)]
+        json: bool,
+        /// Input file; reads stdin when omitted.
+        file: Option<PathBuf>,
+    },
+    /// List the SDO messages in a browser HAR capture, in time order.
+    Har {
+        /// Print JSON instead of one line per message.
+        #[arg(long)]
+        json: bool,
+        /// Print each message as a tree.
+        #[arg(short, long, conflicts_with = "json")]
+        verbose: bool,
+        /// Also save the captured frames as a session recording.
+        #[arg(long, value_name = "PATH")]
+        record: Option<PathBuf>,
+        file: PathBuf,
+    },
+    /// List the SDO messages in a pcap capture of unencrypted websocket
+    /// traffic, in time order.
+    Pcap {
+        /// Print JSON instead of one line per message.
+        #[arg(long)]
+        json: bool,
+        /// Print each message as a tree.
+        #[arg(short, long, conflicts_with = "json")]
+        verbose: bool,
+        /// Also save the captured frames as a session recording.
+        #[arg(long, value_name = "PATH")]
+        record: Option<PathBuf>,
+        /// Unframe connections whose websocket upgrade isn't in the capture.
+        #[arg(long)]
+        mid_stream: bool,
+        file: PathBuf,
+    },
+    /// Replay a session recording, printing each message when it is due.
+    Replay {
+        /// Replay this many times faster than recorded.
+        #[arg(long, default_value_t = 1.0, conflicts_with = "instant")]
+        speed: f64,
+        /// Print every message straight away.
+        #[arg(long)]
+        instant: bool,
+        /// Print each message as a tree.
+        #[arg(short, long)]
+        verbose: bool,
+        file: PathBuf,
+    },
+    /// Encode JSON, as printed by `decode --json`, or the tree printed by
+    /// `decode` into bytes. Input starting with `{` is read as JSON.
+    Encode {
+        /// How to write the encoded bytes.
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Hex)]
+        format: OutputFormat,
+        /// Encode a bare SDO instead of a message.
+        #[arg(long)]
+        sdo: bool,
+        /// Input file; reads stdin when omitted.
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum InputFormat {
+    /// Hex if the input is only hex digits, else base64 if it is valid
+    /// base64, else raw.
+    Auto,
+    Hex,
+    Base64,
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Hex,
+    Base64,
+    Raw,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Decode {
+            format,
+            sdo,
+            json,
+            file,
+        } => {
+            let bytes = parse_input(read_input(file.as_deref())?, format)?;
+            print_decoded(&bytes, sdo, json)?;
+        }
+        Command::Dissect {
+            format,
+            sdo,
+            json,
+            file,
+        } => {
+            let bytes = parse_input(read_input(file.as_deref())?, format)?;
+            let dissected = if sdo {
+                dissect::dissect_sdo(&bytes)
+            } else {
+                dissect::dissect_msg(&bytes)
+            };
+            // Show whatever was dissected before reporting an error.
+            let (spans, incomplete) = match dissected {
+                Ok(spans) => (spans, None),
+                Err(mut incomplete) => (std::mem::take(&mut incomplete.spans), Some(incomplete)),
+            };
+            if json {
+                println!("{:#}", dissect::to_json(&spans));
+            } else {
+                print!("{}", dissect::to_text(&bytes, &spans));
+            }
+            if let Some(incomplete) = incomplete {
+                return Err(incomplete.into());
+            }
+        }
+        Command::Har {
+            json,
+            verbose,
+            record,
+            file,
+        } => {
+            let frames = capture::har::read_frames(io::BufReader::new(open(&file)?))?;
+            if let Some(record) = record {
+                save_recording(&record, &frames)?;
+            }
+            print_captured(&capture::decode_frames(&frames), json, verbose);
+        }
+        Command::Pcap {
+            json,
+            verbose,
+            record,
+            mid_stream,
+            file,
+        } => {
+            let config = capture::pcap::PcapConfig::new().assume_websocket(mid_stream);
+            let frames =
+                capture::pcap::read_frames_with(io::BufReader::new(open(&file)?), &config)?;
+            if let Some(record) = record {
+                save_recording(&record, &frames)?;
+            }
+            print_captured(&capture::decode_frames(&frames), json, verbose);
+        }
+        Command::Replay {
+            speed,
+            instant,
+            verbose,
+            file,
+        } => {
+            let pace = if instant {
+                Pace::Instant
+            } else {
+                Pace::Speed(speed)
+            };
+            replay(&file, pace, verbose)?;
+        }
+        Command::Encode { format, sdo, file } => {
+            let input = read_input(file.as_deref())?;
+            let input = std::str::from_utf8(&input)
+                .into_diagnostic()
+                .wrap_err("reading input")?;
+            write_output(&encode_input(input, sdo)?, format)?;
+        }
+    }
+    Ok(())
+}
+
+/// Encode JSON if the input starts with `{`, else the tree.
+fn encode_input(input: &str, sdo: bool) -> Result<Vec<u8>> {
+    if input.trim_start().starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(input)
+            .into_diagnostic()
+            .wrap_err("parsing JSON input")?;
+        if sdo {
+            Ok(json::from_json(&value)?.encode()?)
+        } else {
+            let message = json::message_from_json(&value)?;
+            // Decoded messages carry TIMEOUT and PAGE_SIZE in the payload, so
+            // only add them when they were given separately.
+            if message.timeout.is_some() || message.page_size.is_some() {
+                Ok(message.encode()?)
+            } else {
+                Ok(message.encode_exact()?)
+            }
+        }
+    } else if sdo {
+        Ok(tree::parse_sdo(input)?.encode()?)
+    } else {
+        Ok(tree::parse_message(input)?.encode_exact()?)
+    }
+}
+
+fn print_decoded(bytes: &[u8], sdo: bool, json: bool) -> Result<()> {
+    // Captures often hold several messages back to back.
+    if sdo {
+        for sdo in decode::read_sdos(bytes) {
+            let sdo = sdo?;
+            if json {
+                println!("{:#}", json::to_json(&sdo));
+            } else {
+                print!("{}", tree::sdo(&sdo));
+            }
+        }
+    } else {
+        for message in decode::read_msgs(bytes) {
+            let message = message?;
+            if json {
+                println!("{:#}", json::message_to_json(&message));
+            } else {
+                print!("{}", tree::message(&message));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<fs::File> {
+    fs::File::open(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("opening {}", path.display()))
+}
+
+fn save_recording(path: &Path, frames: &[CapturedFrame]) -> Result<()> {
+    let file = fs::File::create(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("creating {}", path.display()))?;
+    let start = frames
+        .first()
+        .map_or_else(time::OffsetDateTime::now_utc, |f| f.timestamp);
+    let mut recorder = Recorder::with_start(io::BufWriter::new(file), start)?;
+    for frame in frames {
+        recorder.record_frame(frame)?;
+    }
+    recorder.flush()?;
+    Ok(())
+}
+
+/// Print messages as they come due, so consumers reading our output see them
+/// at the recorded pace.
+fn replay(path: &Path, pace: Pace, verbose: bool) -> Result<()> {
+    let frames =
+        recording::Reader::new(io::BufReader::new(open(path)?))?.collect::<Result<Vec<_>, _>>()?;
+    for frame in Replayer::new(frames, pace) {
+        print_captured(&capture::decode_frames(&[frame]), false, verbose);
+        io::stdout().flush().into_diagnostic()?;
+    }
+    Ok(())
+}
+
+fn print_captured(messages: &[CapturedMessage], json: bool, verbose: bool) {
+    if json {
+        let messages = messages
+            .iter()
+            .map(|m| {
+                serde_json::json!({
+                    "timestamp": timestamp(m),
+                    "direction": format!("{:?}", m.direction),
+                    "message": json::message_to_json(&m.message),
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{:#}", serde_json::Value::Array(messages));
+        return;
+    }
+    for m in messages {
+        println!(
+            "{} {} {} {:?}",
+            timestamp(m),
+            m.direction.arrow(),
+            m.message.id.as_deref().unwrap_or("-"),
+            m.message.sdo.topic
+        );
+        if verbose {
+            print!("{}", tree::sdo(&m.message.sdo));
+        }
+    }
+}
+
+fn timestamp(message: &CapturedMessage) -> String {
+    message
+        .timestamp
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| message.timestamp.to_string())
+}
+
+fn read_input(file: Option<&Path>) -> Result<Vec<u8>> {
+    if let Some(path) = file {
+        return fs::read(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("reading {}", path.display()));
+    }
+    let mut input = vec![];
+    io::stdin()
+        .read_to_end(&mut input)
+        .into_diagnostic()
+        .wrap_err("reading stdin")?;
+    Ok(input)
+}
+
+fn parse_input(input: Vec<u8>, format: InputFormat) -> Result<Vec<u8>> {
+    let text = || {
+        String::from_utf8_lossy(&input)
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+    };
+    match format {
+        InputFormat::Raw => Ok(input),
+        InputFormat::Hex => from_hex(&text()).ok_or_else(|| miette::miette!("invalid hex input")),
+        InputFormat::Base64 => BASE64
+            .decode(text())
+            .into_diagnostic()
+            .wrap_err("invalid base64 input"),
+        InputFormat::Auto => {
+            let text = text();
+            if let Some(bytes) = Some(&text)
+                .filter(|t| !t.is_empty())
+                .and_then(|t| from_hex(t))
+            {
+                Ok(bytes)
+            } else if let Ok(bytes) = BASE64.decode(&text) {
+                Ok(bytes)
+            } else {
+                Ok(input)
+            }
+        }
+    }
+}
+
+fn write_output(bytes: &[u8], format: OutputFormat) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    match format {
+        OutputFormat::Hex => writeln!(stdout, "{}", to_hex(bytes)),
+        OutputFormat::Base64 => writeln!(stdout, "{}", BASE64.encode(bytes)),
+        OutputFormat::Raw => stdout.write_all(bytes),
+    }
+    .into_diagnostic()
+}
+
+#[cfg(test)]
+mod tests {
+    use sdo::{
+        decode::read_msgs,
+        fields::{PAGE_SIZE, TIMEOUT, USER_NAME},
+        Topic,
+    };
+
+    use super::*;
+
+    #[test]
+    fn auto_detects_hex_then_base64_then_raw() {
+        let parse =
+            |input: &str| parse_input(input.as_bytes().to_vec(), InputFormat::Auto).unwrap();
+        assert_eq!(parse("17 00\n0a"), [0x17, 0x00, 0x0a]);
+        assert_eq!(parse("FwAK"), [0x17, 0x00, 0x0a]);
+        assert_eq!(parse("not hex!"), b"not hex!");
+        assert_eq!(parse(""), b"");
+        assert!(parse_input(b"abc".to_vec(), InputFormat::Hex).is_err());
+        assert!(parse_input(b"!!".to_vec(), InputFormat::Base64).is_err());
+        assert_eq!(
+            parse_input(b"ab".to_vec(), InputFormat::Raw).unwrap(),
+            b"ab"
+        );
+    }
+
+    fn login() -> sdo::Message {
+        let mut message = sdo::Message::new_with_id(Topic::TrLogin, Some("ab".to_owned()));
+        message
+            .sdo
+            .push_string_w(USER_NAME, Some("alice".to_owned()));
+        message
+    }
+
+    #[test]
+    fn encodes_json_or_the_tree() {
+        let message = login();
+        let bytes = message.encode_exact().unwrap();
+        let json = json::message_to_json(&message).to_string();
+        assert_eq!(encode_input(&json, false).unwrap(), bytes);
+        assert_eq!(
+            encode_input(&tree::message(&message), false).unwrap(),
+            bytes
+        );
+
+        let bytes = message.sdo.encode().unwrap();
+        let json = json::to_json(&message.sdo).to_string();
+        assert_eq!(encode_input(&format!("  {json}"), true).unwrap(), bytes);
+        assert_eq!(encode_input(&tree::sdo(&message.sdo), true).unwrap(), bytes);
+    }
+
+    #[test]
+    fn json_timeouts_and_page_sizes_are_encoded() {
+        let mut json = json::message_to_json(&login());
+        json["timeout"] = "30".into();
+        let bytes = encode_input(&json.to_string(), false).unwrap();
+        let sent = read_msgs(&bytes).next().unwrap().unwrap();
+        assert!(sent.sdo.get_field(TIMEOUT).is_some());
+        assert!(sent.sdo.get_field(PAGE_SIZE).is_some());
+    }
+}
@@ -0,0 +1,321 @@
+//! The annotated tree printed by `sdo decode`, and parsing it back for
+//! `sdo encode`.
+//!
+//! ```text
+//! message 5f3a
+//!   TdIosOrders (1686)
+//!     ORDER_NUMBER (1264) Long[2]: [1,null]
+//!     ORDER_DETAILS (45515) SDO[1]:
+//!       [0]
+//!         UndefinedTopic (-1)
+//!           SECURITY_CODE (4) StringW: "BHP"
+//!     9999: null
+//! ```
+//!
+//! Each level is indented by two spaces. Values are JSON, as in
+//! [`sdo::json`], so parsing goes through the JSON form.
+
+use std::fmt::Write;
+
+use miette::{miette, Result};
+use sdo::{data::Data, fields, json, Message, SDO};
+use serde_json::{json, Map, Value};
+
+/// Render a message as an indented tree with field names, ids and types.
+pub fn message(message: &Message) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "message {}", message.id.as_deref().unwrap_or(NO_ID));
+    write_sdo(&mut out, &message.sdo, 1);
+    out
+}
+
+/// Render a bare SDO as an indented tree.
+pub fn sdo(sdo: &SDO) -> String {
+    let mut out = String::new();
+    write_sdo(&mut out, sdo, 0);
+    out
+}
+
+/// Parse a message rendered by [`message`].
+pub fn parse_message(text: &str) -> Result<Message> {
+    let mut parser = Parser::new(text)?;
+    let (line, content) = parser.expect(0)?;
+    let id = content
+        .strip_prefix("message ")
+        .ok_or_else(|| miette!("line {line}: expected `message <id>`"))?;
+    let sdo = parser.sdo(1)?;
+    parser.finish()?;
+    Ok(Message {
+        id: Some(id.to_owned()).filter(|id| id != NO_ID),
+        timeout: None,
+        page_size: None,
+        sdo: json::from_json(&sdo)?,
+    })
+}
+
+/// Parse a bare SDO rendered by [`sdo`].
+pub fn parse_sdo(text: &str) -> Result<SDO> {
+    let mut parser = Parser::new(text)?;
+    let sdo = parser.sdo(0)?;
+    parser.finish()?;
+    Ok(json::from_json(&sdo)?)
+}
+
+const NO_ID: &str = "<no id>";
+
+fn write_sdo(out: &mut String, sdo: &SDO, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let _ = writeln!(out, "{indent}{:?} ({})", sdo.topic, sdo.topic_id());
+
+    for (header, data) in &sdo.fields {
+        let id = header.field_id().unwrap_or_default();
+        let label = match fields::name(id) {
+            Some(name) => format!("{name} ({id})"),
+            None => id.to_string(),
+        };
+        match data {
+            None | Some(Data::Unknown) => {
+                let _ = writeln!(out, "{indent}  {label}: null");
+            }
+            Some(Data::SDO(rows)) if rows.len() == 1 => match rows[0].as_deref() {
+                Some(row) => {
+                    let _ = writeln!(out, "{indent}  {label} SDO:");
+                    write_sdo(out, row, depth + 2);
+                }
+                None => {
+                    let _ = writeln!(out, "{indent}  {label} SDO: null");
+                }
+            },
+            Some(Data::SDO(rows)) => {
+                let _ = writeln!(out, "{indent}  {label} SDO[{}]:", rows.len());
+                for (i, row) in rows.iter().enumerate() {
+                    if let Some(row) = row {
+                        let _ = writeln!(out, "{indent}    [{i}]");
+                        write_sdo(out, row, depth + 3);
+                    } else {
+                        let _ = writeln!(out, "{indent}    [{i}] null");
+                    }
+                }
+            }
+            Some(data) => {
+                let (data_type, value) = json::data_to_json(data);
+                let rows = data.null_rows().len();
+                if value.is_array() {
+                    let _ = writeln!(out, "{indent}  {label} {data_type}[{rows}]: {value}");
+                } else {
+                    let _ = writeln!(out, "{indent}  {label} {data_type}: {value}");
+                }
+            }
+        }
+    }
+}
+
+/// Reads the tree back into the JSON form of [`sdo::json`].
+struct Parser<'a> {
+    /// Line number, depth and content of each non-blank line.
+    lines: Vec<(usize, usize, &'a str)>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Result<Self> {
+        let mut lines = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let content = line.trim_start_matches(' ');
+            if content.trim().is_empty() {
+                continue;
+            }
+            let indent = line.len() - content.len();
+            if indent % 2 != 0 {
+                return Err(miette!(
+                    "line {}: indentation must be in steps of two spaces",
+                    i + 1
+                ));
+            }
+            lines.push((i + 1, indent / 2, content.trim_end()));
+        }
+        Ok(Self { lines, next: 0 })
+    }
+
+    /// Take the next line, which must be at `depth`.
+    fn expect(&mut self, depth: usize) -> Result<(usize, &'a str)> {
+        match self.lines.get(self.next) {
+            Some(&(line, d, content)) if d == depth => {
+                self.next += 1;
+                Ok((line, content))
+            }
+            Some(&(line, ..)) => Err(miette!(
+                "line {line}: expected indentation of {depth} levels"
+            )),
+            None => Err(miette!("unexpected end of input")),
+        }
+    }
+
+    /// Take the next line if it is at `depth`.
+    fn next_at(&mut self, depth: usize) -> Option<(usize, &'a str)> {
+        self.expect(depth).ok()
+    }
+
+    fn finish(&self) -> Result<()> {
+        match self.lines.get(self.next) {
+            Some(&(line, ..)) => Err(miette!("line {line}: unexpected trailing input")),
+            None => Ok(()),
+        }
+    }
+
+    fn sdo(&mut self, depth: usize) -> Result<Value> {
+        let (_, content) = self.expect(depth)?;
+        // Prefer the id in parentheses, which unknown topics need.
+        let mut words = content.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let topic = match words.next().map(|id| {
+            id.trim_start_matches('(')
+                .trim_end_matches(')')
+                .parse::<i64>()
+        }) {
+            Some(Ok(id)) => json!(id),
+            _ => json!(name),
+        };
+        let mut fields = Map::new();
+        while let Some((line, content)) = self.next_at(depth + 1) {
+            let (id, value) = self.field(line, content, depth + 1)?;
+            // A repeated field is an array of its values.
+            match fields.get_mut(&id) {
+                Some(Value::Array(values)) => values.push(value),
+                Some(first) => *first = json!([first.take(), value]),
+                None => {
+                    fields.insert(id, value);
+                }
+            }
+        }
+        Ok(json!({ "topic": topic, "fields": fields }))
+    }
+
+    /// Parse `LABEL [(ID)] [TYPE[[ROWS]]]: VALUE` into the field's id and
+    /// tagged value.
+    fn field(&mut self, line: usize, content: &str, depth: usize) -> Result<(String, Value)> {
+        let (head, value) = content
+            .split_once(':')
+            .ok_or_else(|| miette!("line {line}: expected `field type: value`"))?;
+        let value = value.trim();
+        let mut head = head.split_whitespace().peekable();
+        let name = head.next().unwrap_or_default();
+        let id = match head.next_if(|t| t.starts_with('(')) {
+            Some(id) => id
+                .trim_start_matches('(')
+                .trim_end_matches(')')
+                .parse()
+                .ok(),
+            None => name.parse().ok().or_else(|| fields::by_name(name)),
+        }
+        .ok_or_else(|| miette!("line {line}: unknown field {name}"))?;
+        let id = id.to_string();
+
+        let Some(data_type) = head.next() else {
+            return match value {
+                "null" => Ok((id, Value::Null)),
+                _ => Err(miette!(
+                    "line {line}: expected a data type for field {name}"
+                )),
+            };
+        };
+        let (data_type, rows) = match data_type.strip_suffix(']').and_then(|t| t.split_once('[')) {
+            Some((data_type, rows)) => {
+                let rows = rows
+                    .parse::<usize>()
+                    .map_err(|_| miette!("line {line}: invalid row count {rows}"))?;
+                (data_type, Some(rows))
+            }
+            None => (data_type, None),
+        };
+
+        let value = match (data_type, rows, value) {
+            ("SDO", None, "") => self.sdo(depth + 1)?,
+            ("SDO", Some(rows), "") => Value::Array(
+                (0..rows)
+                    .map(|i| self.sdo_row(i, depth + 1))
+                    .collect::<Result<_>>()?,
+            ),
+            (_, _, value) => serde_json::from_str(value)
+                .map_err(|error| miette!("line {line}: invalid value for field {name}: {error}"))?,
+        };
+        Ok((id, json!({ data_type: value })))
+    }
+
+    /// Parse `[i]` followed by an SDO, or `[i] null`.
+    fn sdo_row(&mut self, i: usize, depth: usize) -> Result<Value> {
+        let (line, content) = self.expect(depth)?;
+        let index = format!("[{i}]");
+        match content.strip_prefix(&index).map(str::trim) {
+            Some("") => self.sdo(depth + 1),
+            Some("null") => Ok(Value::Null),
+            _ => Err(miette!("line {line}: expected row {index}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdo::{
+        fields::{ORDER_DETAILS, ORDER_NUMBER, SECURITY_CODE},
+        Topic,
+    };
+
+    use super::*;
+
+    fn order() -> SDO {
+        let mut details = SDO::new(Topic::UndefinedTopic);
+        details.push_string_w(SECURITY_CODE, Some("BHP: \"ordinary\"".to_owned()));
+        let mut sdo = SDO::new(Topic::TdIosOrders);
+        sdo.push_long(ORDER_NUMBER, vec![Some(1), None]);
+        sdo.push_sdo(ORDER_DETAILS, vec![Some(details.clone()), None]);
+        sdo.push_sdo(9999, Some(details));
+        sdo
+    }
+
+    #[test]
+    fn round_trips_sdos() {
+        let text = sdo(&order());
+        let parsed = parse_sdo(&text).unwrap();
+        assert_eq!(sdo(&parsed), text);
+        assert_eq!(parsed.encode().unwrap(), order().encode().unwrap());
+    }
+
+    #[test]
+    fn round_trips_messages() {
+        for id in [Some("5f3a".to_owned()), None] {
+            let original = Message {
+                id,
+                timeout: None,
+                page_size: None,
+                sdo: order(),
+            };
+            let parsed = parse_message(&message(&original)).unwrap();
+            assert_eq!(parsed.id, original.id);
+            assert_eq!(
+                parsed.encode_exact().unwrap(),
+                original.encode_exact().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_topics_keep_their_id() {
+        let mut sdo = SDO::with_topic_id(12345);
+        sdo.push_long(ORDER_NUMBER, Some(1));
+        let text = super::sdo(&sdo);
+        assert!(text.starts_with("Unknown (12345)\n"), "{text}");
+        assert_eq!(parse_sdo(&text).unwrap().topic_id(), 12345);
+        assert_eq!(
+            parse_sdo("TdIosOrders\n").unwrap().topic,
+            Topic::TdIosOrders
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_an_error() {
+        let text = "TdIosOrders (1686)\n  ORDER_NUMBER (1264) Long: one\n";
+        let error = parse_sdo(text).unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"), "{error}");
+    }
+}
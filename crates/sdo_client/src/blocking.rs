@@ -0,0 +1,267 @@
+//! A client for code that doesn't run an async runtime.
+//!
+//! Requests are sent one at a time: [`Client::call`] writes a message and
+//! reads until its response has arrived, reassembling multi-packet
+//! responses. Anything else read in the meantime, such as broadcasts, is
+//! kept for [`Client::recv`].
+//!
+//! ```no_run
+//! # use sdo::{login::LoginRequest, Topic};
+//! # fn main() -> Result<(), sdo_client::Error> {
+//! let mut client = sdo_client::blocking::Client::connect("viewpoint:9999")?;
+//! client.login(&LoginRequest::new("user", "company").password("hunter2"))?;
+//! let request = client.message(Topic::TdIosGeneral);
+//! let response = client.call(&request)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use sdo::{
+    assemble::{self, Assembler},
+    login::{LoginRequest, LoginResponse},
+    request_id::{RandomIds, RequestIdGenerator},
+    Message, SdoRequest, SdoServerError, Topic,
+};
+
+use crate::{codec, Error};
+
+/// A blocking connection to an SDO server, with each message framed by its
+/// 4 byte big-endian length. Request ids come from [`RandomIds`] unless
+/// set with [`Client::with_request_ids`].
+pub struct Client<S = TcpStream> {
+    stream: S,
+    ids: Box<dyn RequestIdGenerator + Send>,
+    assembler: Assembler,
+    /// Decoded but not yet handled, when a frame held several messages.
+    incoming: VecDeque<Message>,
+    /// Read while waiting for a response, for [`Client::recv`].
+    unsolicited: VecDeque<Message>,
+}
+
+impl Client {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: Read + Write> Client<S> {
+    /// Talk over `stream`. Set a read timeout on it to stop calls waiting
+    /// forever for a server that has gone quiet.
+    #[must_use]
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            ids: Box::new(RandomIds),
+            assembler: Assembler::default(),
+            incoming: VecDeque::new(),
+            unsolicited: VecDeque::new(),
+        }
+    }
+
+    /// Take request ids from `ids`.
+    #[must_use]
+    pub fn with_request_ids(mut self, ids: impl RequestIdGenerator + Send + 'static) -> Self {
+        self.ids = Box::new(ids);
+        self
+    }
+
+    /// The next id from the client's request id generator.
+    pub fn next_id(&mut self) -> String {
+        self.ids.next_id()
+    }
+
+    /// An empty message on `topic` with the next request id.
+    pub fn message(&mut self, topic: Topic) -> Message {
+        Message::new(topic, &mut self.ids)
+    }
+
+    /// Send a message and wait for the response with the same request id.
+    /// A `TdError` response is returned as [`Error::Server`]. The message's
+    /// timeout is only sent to the server, which answers with an error once
+    /// it passes; it doesn't bound the wait here. Set a read timeout on the
+    /// stream for that.
+    pub fn call(&mut self, message: &Message) -> Result<Message, Error> {
+        let id = message.id.clone().ok_or(Error::MissingRequestId)?;
+        self.notify(message)?;
+        loop {
+            let message = self.read_message()?;
+            if message.id.as_deref() != Some(id.as_str()) {
+                self.unsolicited.push_back(message);
+                if self.assembler.expire().contains(&id) {
+                    return Err(assemble::Error::TimedOut(id).into());
+                }
+                continue;
+            }
+            // Wait for the rest of the packets.
+            let Some(sdo) = self.assembler.push(&id, message.sdo)? else {
+                if self.assembler.expire().contains(&id) {
+                    return Err(assemble::Error::TimedOut(id).into());
+                }
+                continue;
+            };
+            if let Some(error) = SdoServerError::from_response(&sdo) {
+                return Err(Box::new(error).into());
+            }
+            return Ok(Message { sdo, ..message });
+        }
+    }
+
+    /// Send a typed request and parse its response.
+    pub fn request<R: SdoRequest>(&mut self, request: &R) -> Result<R::Response, Error> {
+        let message = request.to_message(&mut self.ids);
+        let response = self.call(&message)?;
+        Ok(R::parse_response(&response.sdo)?)
+    }
+
+    /// Log in, failing if the server rejects the login.
+    pub fn login(&mut self, request: &LoginRequest) -> Result<LoginResponse, Error> {
+        Ok(self.request(request)?.into_result()?)
+    }
+
+    /// Send a message without waiting for a response.
+    pub fn notify(&mut self, message: &Message) -> Result<(), Error> {
+        codec::write_frame(&mut self.stream, &message.encode()?)
+    }
+
+    /// The next message that wasn't a response to a call, waiting for one
+    /// if none have arrived yet.
+    pub fn recv(&mut self) -> Result<Message, Error> {
+        match self.unsolicited.pop_front() {
+            Some(message) => Ok(message),
+            None => self.read_message(),
+        }
+    }
+
+    #[must_use]
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Read the next message, answering pings from the server on the way.
+    fn read_message(&mut self) -> Result<Message, Error> {
+        loop {
+            while let Some(message) = self.incoming.pop_front() {
+                if message.sdo.topic == Topic::TrPing {
+                    self.notify(&Message::new_with_id(Topic::TdPing, message.id))?;
+                    continue;
+                }
+                return Ok(message);
+            }
+            let frame = codec::read_frame(&mut self.stream)?;
+            self.incoming.extend(codec::decode_frame(&frame));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Cursor},
+        time::Duration,
+    };
+
+    use sdo::{fields::PACKET_FLAG, request_id::CounterIds, PacketFlags};
+
+    use super::*;
+
+    /// Reads from `input` and writes to `output`.
+    struct Duplex {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn requests_take_ids_from_the_generator() {
+        let stream = Duplex {
+            input: Cursor::new(vec![]),
+            output: vec![],
+        };
+        let mut client = Client::new(stream).with_request_ids(CounterIds::new());
+        let login = LoginRequest::new("user", "company");
+        // Nothing to read, so the call fails once the request is written.
+        assert!(matches!(client.login(&login), Err(Error::Closed)));
+
+        let expected = login.to_message(&mut CounterIds::new()).encode().unwrap();
+        let written = &client.get_ref().output;
+        assert_eq!(
+            written[..4],
+            u32::try_from(expected.len()).unwrap().to_be_bytes()
+        );
+        assert_eq!(written[4..], expected[..]);
+        assert_eq!(client.next_id(), "R_2_");
+    }
+
+    fn framed(messages: &[Message]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for message in messages {
+            codec::write_frame(&mut bytes, &message.encode_exact().unwrap()).unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn rejects_overlong_frames() {
+        let stream = Duplex {
+            input: Cursor::new(vec![0xff; 8]),
+            output: vec![],
+        };
+        let mut client = Client::new(stream);
+        assert!(matches!(
+            client.recv(),
+            Err(Error::FrameTooLong(0xffff_ffff))
+        ));
+    }
+
+    #[test]
+    fn gives_up_on_partial_responses() {
+        let mut first = Message::new_with_id(Topic::TdIosGeneral, Some("R_1_".to_owned()));
+        first
+            .sdo
+            .push_long(PACKET_FLAG, Some(PacketFlags::FIRST.bits()));
+        let broadcast = Message::new_with_id(Topic::TdIosGeneral, None);
+        let stream = Duplex {
+            input: Cursor::new(framed(&[first, broadcast])),
+            output: vec![],
+        };
+        let mut client = Client::new(stream).with_request_ids(CounterIds::new());
+        client.assembler = Assembler::new(Duration::ZERO);
+        let request = client.message(Topic::TdIosGeneral);
+        assert!(matches!(
+            client.call(&request),
+            Err(Error::Assembly(assemble::Error::TimedOut(id))) if id == "R_1_"
+        ));
+    }
+}
@@ -0,0 +1,593 @@
+//! Sending requests and matching responses back to them.
+
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, Stream, StreamExt,
+};
+use sdo::{
+    assemble::{self, Assembler},
+    request_id::{RandomIds, RequestIdGenerator},
+    Message, SdoRequest, SdoServerError, Topic, BROADCAST_ADDRESS, BROADCAST_UPDATE_ADDRESS,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot,
+    },
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    codec,
+    limit::RateLimiter,
+    transport::{TcpTransport, Transport},
+    watch, Error,
+};
+
+type Writer = SplitSink<Box<dyn Transport>, Bytes>;
+type Reader = SplitStream<Box<dyn Transport>>;
+
+type Responder = oneshot::Sender<Result<Message, Error>>;
+
+type SharedWriter = Arc<tokio::sync::Mutex<Writer>>;
+
+/// Requests waiting for a response, keyed by request id. `None` once the
+/// connection has closed.
+type Pending = Arc<Mutex<Option<HashMap<String, Responder>>>>;
+
+/// Subscriptions waiting for watch updates, keyed by the request id that
+/// started the watch.
+type Watches = Arc<Mutex<HashMap<String, mpsc::UnboundedSender<watch::Event>>>>;
+
+/// How many unread broadcasts a [`Broadcasts`] stream holds before dropping
+/// new ones.
+pub const BROADCAST_CAPACITY: usize = 1024;
+
+/// How often to check for partial responses that have timed out.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where request ids come from, shared between clones of a client.
+pub(crate) type Ids = Arc<Mutex<Box<dyn RequestIdGenerator + Send>>>;
+
+/// A connection to an SDO server.
+///
+/// Responses are matched to requests by the request id in their header, so
+/// any number of requests can be in flight at once. Responses split across
+/// several packets are reassembled before being returned. Messages addressed to
+/// [`BROADCAST_ADDRESS`] or [`BROADCAST_UPDATE_ADDRESS`] go to the
+/// [`Broadcasts`] stream returned alongside the client, unless they are
+/// updates for a [`Subscription`](crate::watch::Subscription). Pings from
+/// the server are answered automatically.
+///
+/// Request ids come from [`RandomIds`] unless the client is given another
+/// generator with [`Client::with_request_ids`].
+///
+/// Cloning a client shares the connection. It is closed once every clone
+/// has been dropped.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+    pub(crate) limiter: Option<RateLimiter>,
+    pub(crate) ids: Ids,
+}
+
+struct Inner {
+    writer: SharedWriter,
+    pending: Pending,
+    watches: Watches,
+    closed: tokio::sync::watch::Receiver<bool>,
+    shutdown: CancellationToken,
+    reader: JoinHandle<()>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl Client {
+    /// Start talking over `io`, with each message framed by its length. Must
+    /// be called from within a tokio runtime, since incoming messages are
+    /// read on a spawned task.
+    pub fn new<T>(io: T) -> (Self, Broadcasts)
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::with_transport(TcpTransport::new(Box::pin(io)))
+    }
+
+    /// Start talking over `transport`, e.g. a
+    /// [`WebSocketTransport`](crate::transport::WebSocketTransport). Must be
+    /// called from within a tokio runtime.
+    pub fn with_transport(transport: impl Transport + 'static) -> (Self, Broadcasts) {
+        Self::with_assembler(transport, Assembler::default())
+    }
+
+    fn with_assembler(
+        transport: impl Transport + 'static,
+        assembler: Assembler,
+    ) -> (Self, Broadcasts) {
+        let (writer, reader) = (Box::new(transport) as Box<dyn Transport>).split();
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let watches = Watches::default();
+        let (broadcasts, rx) = mpsc::channel(BROADCAST_CAPACITY);
+        let (closed_tx, closed) = tokio::sync::watch::channel(false);
+        let shutdown = CancellationToken::new();
+        let router = Router {
+            writer: Arc::clone(&writer),
+            pending: Arc::clone(&pending),
+            watches: Arc::clone(&watches),
+            broadcasts,
+            assembler,
+            closed: closed_tx,
+        };
+        let reader = tokio::spawn(read_loop(reader, router, shutdown.clone()));
+        let client = Self {
+            inner: Arc::new(Inner {
+                writer,
+                pending,
+                watches,
+                closed,
+                shutdown,
+                reader,
+            }),
+            limiter: None,
+            ids: Arc::new(Mutex::new(Box::new(RandomIds))),
+        };
+        (client, Broadcasts { rx })
+    }
+
+    /// A handle to the same connection that takes request ids from `ids`,
+    /// as do clones made from it. Call before sending anything, so every
+    /// request gets its id from the same generator.
+    #[must_use]
+    pub fn with_request_ids(mut self, ids: impl RequestIdGenerator + Send + 'static) -> Self {
+        self.ids = Arc::new(Mutex::new(Box::new(ids)));
+        self
+    }
+
+    /// The next id from the client's request id generator.
+    #[must_use]
+    pub fn next_id(&self) -> String {
+        lock(&self.ids).next_id()
+    }
+
+    /// An empty message on `topic` with the next request id.
+    #[must_use]
+    pub fn message(&self, topic: Topic) -> Message {
+        Message::new_with_id(topic, Some(self.next_id()))
+    }
+
+    /// Send a message and wait for the response with the same request id.
+    /// A `TdError` response is returned as [`Error::Server`]. If the message
+    /// has a timeout and it passes first, [`Error::TimedOut`] is returned.
+    /// The timeout is rounded like the one sent to the server, and starts
+    /// once the rate limiter has let the message go.
+    pub async fn send(&self, message: Message) -> Result<Message, Error> {
+        let id = message.id.clone().ok_or(Error::MissingRequestId)?;
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = lock(&self.inner.pending);
+            let pending = pending.as_mut().ok_or(Error::Closed)?;
+            if pending.contains_key(&id) {
+                return Err(Error::DuplicateRequestId(id));
+            }
+            pending.insert(id.clone(), tx);
+        }
+        // Forget the request if we give up on it, whether because sending
+        // failed or because the caller dropped this future.
+        let guard = PendingGuard {
+            pending: &self.inner.pending,
+            id,
+        };
+        self.throttle(&message).await?;
+        let exchange = async {
+            write(&self.inner.writer, &message).await?;
+            rx.await.map_err(|_| Error::Closed)?
+        };
+        let response = match message.rounded_timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, exchange)
+                .await
+                .map_err(|_| Error::TimedOut(guard.id.clone()))?,
+            None => exchange.await,
+        }?;
+        match SdoServerError::from_response(&response.sdo) {
+            Some(error) => Err(Box::new(error).into()),
+            None => Ok(response),
+        }
+    }
+
+    /// Send a message without waiting for a response, for messages the
+    /// server doesn't answer.
+    pub async fn notify(&self, message: &Message) -> Result<(), Error> {
+        self.throttle(message).await?;
+        write(&self.inner.writer, message).await
+    }
+
+    /// Wait for the rate limiter, if there is one, to let `message` go.
+    async fn throttle(&self, message: &Message) -> Result<(), Error> {
+        match &self.limiter {
+            Some(limiter) => limiter.acquire(message.sdo.topic).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Start routing watch updates for the watch started by request `id` to
+    /// `tx`.
+    pub(crate) fn add_watch(&self, id: String, tx: mpsc::UnboundedSender<watch::Event>) {
+        lock(&self.inner.watches).insert(id, tx);
+    }
+
+    /// Move the watch started by request `id` over to `tx`, after a
+    /// [`watch::Event::Resynced`] and the updates `buffered` has received so
+    /// far. Does nothing if the watch has been removed.
+    pub(crate) fn resume_watch(
+        &self,
+        id: &str,
+        buffered: &mut mpsc::UnboundedReceiver<watch::Event>,
+        tx: mpsc::UnboundedSender<watch::Event>,
+    ) {
+        let mut watches = lock(&self.inner.watches);
+        let Some(route) = watches.get_mut(id) else {
+            return;
+        };
+        let _ = tx.send(watch::Event::Resynced);
+        while let Ok(event) = buffered.try_recv() {
+            let _ = tx.send(event);
+        }
+        *route = tx;
+    }
+
+    pub(crate) fn remove_watch(&self, id: &str) {
+        lock(&self.inner.watches).remove(id);
+    }
+
+    /// Whether two clients share a connection.
+    #[must_use]
+    pub fn same_connection(&self, other: &Client) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Whether the connection has closed.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        *self.inner.closed.borrow()
+    }
+
+    /// How many requests are waiting for a response.
+    pub(crate) fn in_flight(&self) -> usize {
+        lock(&self.inner.pending).as_ref().map_or(0, HashMap::len)
+    }
+
+    /// Stop reading from the connection and treat it as closed, e.g. because
+    /// the server stopped answering.
+    pub(crate) fn close(&self) {
+        self.inner.shutdown.cancel();
+    }
+
+    /// Wait for the connection to close, e.g. to know when to reconnect.
+    pub async fn closed(&self) {
+        let mut closed = self.inner.closed.clone();
+        // Only fails if the reader task is gone, which means we're closed.
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+
+    /// Send a typed request and parse its response.
+    pub async fn request<R: SdoRequest>(&self, request: &R) -> Result<R::Response, Error> {
+        let message = request.to_message(&mut *lock(&self.ids));
+        let response = self.send(message).await?;
+        Ok(R::parse_response(&response.sdo)?)
+    }
+}
+
+struct PendingGuard<'a> {
+    pending: &'a Pending,
+    id: String,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(pending) = lock(self.pending).as_mut() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+async fn write(writer: &SharedWriter, message: &Message) -> Result<(), Error> {
+    let frame = Bytes::from(message.encode()?);
+    writer.lock().await.send(frame).await
+}
+
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Our maps are never left half-updated, so a panic elsewhere doesn't
+    // matter.
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+async fn read_loop(reader: Reader, mut router: Router, shutdown: CancellationToken) {
+    let mut reader = std::pin::pin!(reader.take_until(shutdown.cancelled_owned()));
+    // Partial responses have to time out even when nothing else arrives.
+    let mut expiry = tokio::time::interval(router.assembler.timeout().min(EXPIRY_INTERVAL));
+    expiry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            frame = reader.next() => match frame {
+                Some(Ok(frame)) => {
+                    for message in codec::decode_frame(&frame) {
+                        router.route(message);
+                    }
+                }
+                Some(Err(error)) => {
+                    warn!(%error, "connection failed");
+                    break;
+                }
+                None => break,
+            },
+            _ = expiry.tick() => router.expire(),
+        }
+    }
+    // Dropping the senders wakes every waiting request with `Error::Closed`
+    // and ends every subscription that won't be resubscribed.
+    lock(&router.pending).take();
+    lock(&router.watches).clear();
+    router.closed.send_replace(true);
+}
+
+/// Works out who each incoming message is for.
+struct Router {
+    writer: SharedWriter,
+    pending: Pending,
+    watches: Watches,
+    broadcasts: mpsc::Sender<Message>,
+    assembler: Assembler,
+    closed: tokio::sync::watch::Sender<bool>,
+}
+
+impl Router {
+    fn route(&mut self, message: Message) {
+        if message.sdo.topic == Topic::TrPing {
+            self.pong(message.id);
+            return;
+        }
+        if message.sdo.is_watch_updates() {
+            let id = message.sdo.watch_request_id().or(message.id.as_deref());
+            // Sent under the lock so `resume_watch` can't slip in between.
+            let watches = lock(&self.watches);
+            if let Some(tx) = id.and_then(|id| watches.get(id)) {
+                let _ = tx.send(watch::Event::Update(message.sdo));
+                return;
+            }
+        }
+        match message.id.as_deref() {
+            None | Some(BROADCAST_ADDRESS | BROADCAST_UPDATE_ADDRESS) => {
+                // Nobody listening for broadcasts is fine, and falling
+                // behind mustn't hold up responses; see `Broadcasts`.
+                if let Err(TrySendError::Full(message)) = self.broadcasts.try_send(message) {
+                    warn!(topic = ?message.sdo.topic, "broadcast queue full, dropping broadcast");
+                }
+            }
+            Some(id) => {
+                if !lock(&self.pending)
+                    .as_ref()
+                    .is_some_and(|pending| pending.contains_key(id))
+                {
+                    warn!(id, topic = ?message.sdo.topic, "response to unknown request");
+                    self.assembler.cancel(id);
+                    return;
+                }
+                let id = id.to_owned();
+                let response = match self.assembler.push(&id, message.sdo) {
+                    Ok(Some(sdo)) => Ok(Message { sdo, ..message }),
+                    // Wait for the rest of the packets.
+                    Ok(None) => return,
+                    Err(error) => Err(error.into()),
+                };
+                self.respond(&id, response);
+            }
+        }
+    }
+
+    /// Answer a ping from the server.
+    fn pong(&self, id: Option<String>) {
+        let pong = Message::new_with_id(Topic::TdPing, id);
+        let writer = Arc::clone(&self.writer);
+        // Sent from a task so a busy writer doesn't hold up reading.
+        tokio::spawn(async move {
+            if let Err(error) = write(&writer, &pong).await {
+                warn!(%error, "failed to answer ping");
+            }
+        });
+    }
+
+    fn expire(&mut self) {
+        for id in self.assembler.expire() {
+            let error = assemble::Error::TimedOut(id.clone());
+            self.respond(&id, Err(error.into()));
+        }
+    }
+
+    fn respond(&self, id: &str, response: Result<Message, Error>) {
+        let tx = lock(&self.pending)
+            .as_mut()
+            .and_then(|pending| pending.remove(id));
+        if let Some(tx) = tx {
+            let _ = tx.send(response);
+        }
+    }
+}
+
+/// Messages sent to the broadcast addresses rather than in response to a
+/// request. Ends when the connection closes.
+///
+/// Up to [`BROADCAST_CAPACITY`] broadcasts wait here to be read. Once that
+/// many are waiting, new broadcasts are logged and dropped until some are
+/// read, so a stream nobody reads can't hold up responses or use up memory.
+pub struct Broadcasts {
+    rx: mpsc::Receiver<Message>,
+}
+
+impl Stream for Broadcasts {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Message>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use sdo::{
+        decode, fields::PACKET_FLAG, login::LoginRequest, request_id::CounterIds, PacketFlags,
+    };
+
+    use super::*;
+    use crate::{
+        limit::{RateLimit, RateLimits},
+        ChannelTransport,
+    };
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    fn paused_runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn partial_responses_time_out_without_more_traffic() {
+        runtime().block_on(async {
+            let (ours, mut theirs) = ChannelTransport::pair();
+            let assembler = Assembler::new(Duration::from_millis(20));
+            let (client, _broadcasts) = Client::with_assembler(ours, assembler);
+            let request = tokio::spawn({
+                let client = client.clone();
+                async move { client.send(client.message(Topic::TdIosGeneral)).await }
+            });
+
+            let frame = theirs.next().await.unwrap().unwrap();
+            let message = decode::read_msg(&mut std::io::Cursor::new(frame.to_vec())).unwrap();
+            let mut first = Message::new_with_id(Topic::TdIosGeneral, message.id);
+            first
+                .sdo
+                .push_long(PACKET_FLAG, Some(PacketFlags::FIRST.bits()));
+            theirs.send(first.encode().unwrap().into()).await.unwrap();
+
+            let error = request.await.unwrap().unwrap_err();
+            assert!(matches!(
+                error,
+                Error::Assembly(assemble::Error::TimedOut(_))
+            ));
+        });
+    }
+
+    /// Answer the next request after `delay`.
+    async fn reply_after(theirs: &mut ChannelTransport, delay: Duration) {
+        let frame = theirs.next().await.unwrap().unwrap();
+        let message = decode::read_msg(&mut std::io::Cursor::new(frame.to_vec())).unwrap();
+        tokio::time::sleep(delay).await;
+        let reply = Message::new_with_id(Topic::TdIosGeneral, message.id);
+        theirs.send(reply.encode().unwrap().into()).await.unwrap();
+    }
+
+    #[test]
+    fn timeouts_start_after_the_rate_limiter() {
+        paused_runtime().block_on(async {
+            let (ours, mut theirs) = ChannelTransport::pair();
+            let (client, _broadcasts) = Client::with_transport(ours);
+            let limits = RateLimits::new().topic(
+                Topic::TdIosGeneral,
+                RateLimit::new(1, Duration::from_secs(5)),
+            );
+            let client = client.with_rate_limiter(RateLimiter::new(limits));
+            let requests = (0..2)
+                .map(|_| {
+                    let client = client.clone();
+                    let message = client
+                        .message(Topic::TdIosGeneral)
+                        .with_timeout(Duration::from_secs(1));
+                    tokio::spawn(async move { client.send(message).await })
+                })
+                .collect::<Vec<_>>();
+            reply_after(&mut theirs, Duration::ZERO).await;
+            reply_after(&mut theirs, Duration::from_millis(500)).await;
+            for request in requests {
+                assert!(request.await.unwrap().is_ok());
+            }
+        });
+    }
+
+    #[test]
+    fn timeouts_are_rounded_like_the_one_sent() {
+        paused_runtime().block_on(async {
+            let (ours, mut theirs) = ChannelTransport::pair();
+            let (client, _broadcasts) = Client::with_transport(ours);
+            let mut message = client.message(Topic::TdIosGeneral);
+            message.timeout = Some(Duration::from_millis(500));
+            let request = tokio::spawn(async move { client.send(message).await });
+            reply_after(&mut theirs, Duration::from_millis(800)).await;
+            assert!(request.await.unwrap().is_ok());
+        });
+    }
+
+    #[test]
+    fn drops_broadcasts_nobody_reads() {
+        runtime().block_on(async {
+            let (ours, mut theirs) = ChannelTransport::pair();
+            let (_client, broadcasts) = Client::with_transport(ours);
+            let broadcast = Message::new_with_id(Topic::TdIosGeneral, None)
+                .encode_exact()
+                .unwrap();
+            for _ in 0..BROADCAST_CAPACITY + 10 {
+                theirs.send(broadcast.clone().into()).await.unwrap();
+            }
+            drop(theirs);
+            assert_eq!(broadcasts.count().await, BROADCAST_CAPACITY);
+        });
+    }
+
+    #[test]
+    fn requests_take_ids_from_the_generator() {
+        runtime().block_on(async {
+            let (ours, mut theirs) = ChannelTransport::pair();
+            let (client, _broadcasts) = Client::with_transport(ours);
+            let client = client.with_request_ids(CounterIds::new());
+            let login = LoginRequest::new("user", "company");
+            let request = tokio::spawn({
+                let client = client.clone();
+                let login = login.clone();
+                async move { client.login(&login).await }
+            });
+
+            let frame = theirs.next().await.unwrap().unwrap();
+            let expected = login.to_message(&mut CounterIds::new()).encode().unwrap();
+            assert_eq!(frame[..], expected[..]);
+            let message = decode::read_msg(&mut std::io::Cursor::new(frame.to_vec())).unwrap();
+            assert_eq!(message.id.as_deref(), Some("R_1_"));
+            assert_eq!(client.message(Topic::TrPing).id.as_deref(), Some("R_2_"));
+            request.abort();
+        });
+    }
+}
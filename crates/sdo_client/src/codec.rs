@@ -0,0 +1,177 @@
+//! Framing messages over a byte stream.
+//!
+//! Each frame is a 4 byte big-endian length prefix, counting only the bytes
+//! after it, followed by that many bytes: the encoded header and payload
+//! SDOs of a message. This is [`LengthDelimitedCodec`]'s default framing. A
+//! frame read from the server may hold several messages back to back; see
+//! [`decode_frame`]. Frames are at most [`MAX_FRAME_LEN`] bytes, both ways,
+//! and so are websocket messages; see [`websocket_config`].
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+};
+
+use bytes::{Bytes, BytesMut};
+use sdo::{decode, Message};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+use crate::Error;
+
+/// The longest frame we send or accept, not counting the length prefix.
+pub const MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+/// The length framing, for async streams.
+#[must_use]
+pub fn length_codec() -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(MAX_FRAME_LEN)
+        .new_codec()
+}
+
+/// Websocket settings that limit messages to [`MAX_FRAME_LEN`], as used by
+/// [`WebSocketTransport::connect`](crate::WebSocketTransport::connect).
+#[must_use]
+pub fn websocket_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_FRAME_LEN),
+        max_frame_size: Some(MAX_FRAME_LEN),
+        ..WebSocketConfig::default()
+    }
+}
+
+/// Read one length-prefixed frame from a blocking stream.
+pub fn read_frame(reader: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let mut len = [0; 4];
+    read_exact(reader, &mut len)?;
+    let len = usize::try_from(u32::from_be_bytes(len)).unwrap_or(usize::MAX);
+    if len > MAX_FRAME_LEN {
+        return Err(Error::FrameTooLong(len));
+    }
+    let mut frame = vec![0; len];
+    read_exact(reader, &mut frame)?;
+    Ok(frame)
+}
+
+/// Write one length-prefixed frame to a blocking stream.
+pub fn write_frame(writer: &mut impl Write, frame: &[u8]) -> Result<(), Error> {
+    let len = u32::try_from(frame.len())
+        .ok()
+        .filter(|_| frame.len() <= MAX_FRAME_LEN)
+        .ok_or(Error::FrameTooLong(frame.len()))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<(), Error> {
+    reader.read_exact(buf).map_err(|error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Closed,
+        _ => error.into(),
+    })
+}
+
+#[derive(Debug)]
+pub struct MessageCodec {
+    frames: LengthDelimitedCodec,
+    /// The rest of a frame that held several messages.
+    queued: VecDeque<Message>,
+}
+
+impl MessageCodec {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for MessageCodec {
+    fn default() -> Self {
+        Self {
+            frames: length_codec(),
+            queued: VecDeque::new(),
+        }
+    }
+}
+
+impl Decoder for MessageCodec {
+    type Item = Message;
+    type Error = Error;
+
+    /// Messages that don't decode are logged and skipped, so one message we
+    /// can't parse doesn't take down the whole connection.
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, Error> {
+        while self.queued.is_empty() {
+            let Some(frame) = self.frames.decode(src)? else {
+                return Ok(None);
+            };
+            self.queued.extend(decode_frame(&frame));
+        }
+        Ok(self.queued.pop_front())
+    }
+}
+
+/// Decode every message in a frame; frames may hold several back to back.
+/// Anything after a message that doesn't decode is logged and skipped.
+#[must_use]
+pub fn decode_frame(frame: &[u8]) -> Vec<Message> {
+    decode::read_msgs(frame)
+        .filter_map(|message| {
+            message
+                .map_err(|error| warn!(%error, len = frame.len(), "skipping undecodable frame"))
+                .ok()
+        })
+        .collect()
+}
+
+impl Encoder<&Message> for MessageCodec {
+    type Error = Error;
+
+    fn encode(&mut self, message: &Message, dst: &mut BytesMut) -> Result<(), Error> {
+        self.frames
+            .encode(Bytes::from(message.encode()?), dst)
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdo::Topic;
+
+    use super::*;
+
+    fn frame(messages: &[Message]) -> BytesMut {
+        let mut payload = vec![];
+        for message in messages {
+            payload.extend(message.encode_exact().unwrap());
+        }
+        let mut frame = BytesMut::new();
+        LengthDelimitedCodec::new()
+            .encode(Bytes::from(payload), &mut frame)
+            .unwrap();
+        frame
+    }
+
+    #[test]
+    fn frames_start_with_a_big_endian_length() {
+        let message = Message::new_with_id(Topic::TrPing, Some("R_1_".to_owned()));
+        let framed = frame(std::slice::from_ref(&message));
+        let len = message.encode_exact().unwrap().len();
+        assert_eq!(framed[..4], u32::try_from(len).unwrap().to_be_bytes());
+        assert_eq!(framed.len(), 4 + len);
+    }
+
+    #[test]
+    fn decodes_every_message_in_a_frame() {
+        let ping = Message::new_with_id(Topic::TrPing, Some("R_1_".to_owned()));
+        let reply = Message::new_with_id(Topic::TdPing, Some("R_2_".to_owned()));
+        let mut src = frame(&[ping, reply]);
+        let mut codec = MessageCodec::new();
+        let ids = std::iter::from_fn(|| codec.decode(&mut src).unwrap())
+            .map(|m| m.id.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["R_1_", "R_2_"]);
+    }
+}
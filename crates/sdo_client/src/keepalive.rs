@@ -0,0 +1,167 @@
+//! Keeping a connection alive, and noticing when it isn't.
+//!
+//! Servers that say `IS_PINGABLE` in their login response answer a `TrPing`
+//! with a `TdPing` carrying the same request id. Pinging regularly keeps
+//! idle connections open and tells us when the server has gone quiet.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use sdo::Topic;
+use tokio::{
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+
+use crate::{client::lock, Client, Error};
+
+/// How often to ping, and when to give up.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use sdo_client::{Client, Keepalive};
+/// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+/// # let (client, _broadcasts) = Client::new(sdo_mock::MockServer::new().duplex());
+/// let keepalive = client.keepalive(Keepalive::new().interval(Duration::from_secs(10)));
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct Keepalive {
+    interval: Duration,
+    timeout: Duration,
+    max_missed: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+            max_missed: 3,
+        }
+    }
+}
+
+impl Keepalive {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Time between pings. Defaults to 30 seconds.
+    #[must_use]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// How long to wait for a pong before counting it as missed. Defaults to
+    /// 10 seconds.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many pongs in a row can be missed before the connection is
+    /// closed. Defaults to 3.
+    #[must_use]
+    pub fn max_missed(mut self, max_missed: u32) -> Self {
+        self.max_missed = max_missed.max(1);
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    rtt: Option<Duration>,
+    missed: u32,
+    dead: bool,
+}
+
+/// A running keepalive. Dropping it stops the pings.
+pub struct KeepaliveHandle {
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl KeepaliveHandle {
+    /// The round-trip time of the last answered ping.
+    #[must_use]
+    pub fn rtt(&self) -> Option<Duration> {
+        lock(&self.state).rtt
+    }
+
+    /// How many pings in a row have gone unanswered.
+    #[must_use]
+    pub fn missed(&self) -> u32 {
+        lock(&self.state).missed
+    }
+
+    /// Whether too many pings were missed and the connection was closed.
+    #[must_use]
+    pub fn is_dead(&self) -> bool {
+        lock(&self.state).dead
+    }
+}
+
+impl Drop for KeepaliveHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Client {
+    /// Ping the server until the returned handle is dropped or the
+    /// connection closes. After too many missed pongs the connection is
+    /// closed, failing waiting requests and waking [`Client::closed`].
+    /// Pings skip any rate limiter, so a busy queue can't starve them.
+    #[must_use]
+    pub fn keepalive(&self, config: Keepalive) -> KeepaliveHandle {
+        let state = Arc::default();
+        let mut client = self.clone();
+        client.limiter = None;
+        let task = tokio::spawn(ping_loop(client, config, Arc::clone(&state)));
+        KeepaliveHandle { state, task }
+    }
+}
+
+async fn ping_loop(client: Client, config: Keepalive, state: Arc<Mutex<State>>) {
+    let mut interval = tokio::time::interval_at(Instant::now() + config.interval, config.interval);
+    // A slow pong shouldn't be followed by a burst of pings.
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if client.is_closed() {
+            return;
+        }
+        let start = Instant::now();
+        let ping = client.send(client.message(Topic::TrPing));
+        match tokio::time::timeout(config.timeout, ping).await {
+            Ok(Ok(_)) => {
+                let rtt = start.elapsed();
+                trace!(?rtt, "pong");
+                let mut state = lock(&state);
+                state.rtt = Some(rtt);
+                state.missed = 0;
+                continue;
+            }
+            Ok(Err(Error::Closed)) => return,
+            Ok(Err(error)) => warn!(%error, "ping failed"),
+            Err(_) => warn!(timeout = ?config.timeout, "ping timed out"),
+        }
+        let missed = {
+            let mut state = lock(&state);
+            state.missed += 1;
+            state.missed
+        };
+        if missed >= config.max_missed {
+            warn!(missed, "server stopped answering pings, closing connection");
+            lock(&state).dead = true;
+            client.close();
+            return;
+        }
+    }
+}
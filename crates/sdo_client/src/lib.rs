@@ -0,0 +1,63 @@
+#![warn(clippy::pedantic)]
+#[macro_use]
+extern crate tracing;
+
+pub mod blocking;
+pub mod client;
+pub mod codec;
+pub mod keepalive;
+pub mod limit;
+pub mod login;
+pub mod pager;
+pub mod pool;
+pub mod service;
+pub mod transport;
+pub mod watch;
+
+pub use client::{Broadcasts, Client, BROADCAST_CAPACITY};
+pub use keepalive::{Keepalive, KeepaliveHandle};
+pub use limit::{RateLimit, RateLimitMetrics, RateLimiter, RateLimits};
+pub use pager::Pager;
+pub use pool::{Pool, PoolConfig};
+pub use transport::{
+    ChannelTransport, RecordingTransport, ReplayTransport, TcpTransport, Transport,
+    WebSocketTransport,
+};
+pub use watch::{Subscription, WatchEvent, WatchRegistry, WatchRequest, WatchUpdate};
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("websocket error")]
+    WebSocket(#[from] Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("failed to encode message")]
+    Encode(#[from] sdo::encode::Error),
+    #[error("invalid response")]
+    Response(#[from] sdo::request::Error),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Server(#[from] Box<sdo::SdoServerError>),
+    #[error(transparent)]
+    Login(#[from] sdo::login::Error),
+    #[error("failed to reassemble response")]
+    Assembly(#[from] sdo::assemble::Error),
+    #[error("no IOS service named {0}")]
+    UnknownIosService(String),
+    #[error("last row of the page has none of the key fields to continue from")]
+    MissingPageKey,
+    #[error("request {0} timed out")]
+    TimedOut(String),
+    #[error("too many messages queued for sending")]
+    RateLimited,
+    #[error("message has no request id")]
+    MissingRequestId,
+    #[error("request {0} is already waiting for a response")]
+    DuplicateRequestId(String),
+    #[error("can't watch {0:?}, which has a negative topic id")]
+    InvalidWatchTopic(sdo::Topic),
+    #[error("frame of {0} bytes is longer than the maximum")]
+    FrameTooLong(usize),
+    #[error("connection closed")]
+    Closed,
+}
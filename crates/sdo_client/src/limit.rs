@@ -0,0 +1,413 @@
+//! Throttling outgoing messages.
+//!
+//! Servers disconnect clients that send too fast, so batch jobs can put a
+//! [`RateLimiter`] in front of a client. Each message waits for a token from
+//! the overall bucket and from its topic's bucket, if it has one. Messages
+//! on the same topic are sent in the order they were queued.
+//!
+//! ```
+//! # use sdo::Topic;
+//! # use sdo_client::{Client, RateLimit, RateLimiter, RateLimits};
+//! # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+//! # let (client, _broadcasts) = Client::new(sdo_mock::MockServer::new().duplex());
+//! let limiter = RateLimiter::new(
+//!     RateLimits::new()
+//!         .global(RateLimit::per_second(50))
+//!         .topic(Topic::TdIosOrders, RateLimit::per_second(5))
+//!         .max_queued(1000),
+//! );
+//! let client = client.with_rate_limiter(limiter.clone());
+//! # });
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use sdo::Topic;
+use tokio::time::Instant;
+
+use crate::{client::lock, Client, Error};
+
+/// A token bucket: `count` messages per `per`, with bursts of up to `burst`.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    interval: Duration,
+    burst: u32,
+}
+
+impl RateLimit {
+    /// `count` messages every `per`. Bursts default to `count`.
+    #[must_use]
+    pub fn new(count: u32, per: Duration) -> Self {
+        let count = count.max(1);
+        Self {
+            interval: per / count,
+            burst: count,
+        }
+    }
+
+    #[must_use]
+    pub fn per_second(count: u32) -> Self {
+        Self::new(count, Duration::from_secs(1))
+    }
+
+    /// How many messages can be sent at once after a quiet spell.
+    #[must_use]
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+/// Which limits a [`RateLimiter`] applies.
+#[derive(Debug, Clone)]
+pub struct RateLimits {
+    global: Option<RateLimit>,
+    topics: HashMap<Topic, RateLimit>,
+    max_queued: usize,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            global: None,
+            topics: HashMap::new(),
+            max_queued: 1000,
+        }
+    }
+}
+
+impl RateLimits {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit every message, whatever its topic.
+    #[must_use]
+    pub fn global(mut self, limit: RateLimit) -> Self {
+        self.global = Some(limit);
+        self
+    }
+
+    /// Limit messages on `topic`, on top of the global limit.
+    #[must_use]
+    pub fn topic(mut self, topic: Topic, limit: RateLimit) -> Self {
+        self.topics.insert(topic, limit);
+        self
+    }
+
+    /// How many messages can wait to be sent before more are rejected with
+    /// [`Error::RateLimited`]. Messages that can be sent straight away don't
+    /// count, so `0` rejects only those that would have to wait. Defaults to
+    /// 1000.
+    #[must_use]
+    pub fn max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = max_queued;
+        self
+    }
+}
+
+/// Counts of what the limiter has done so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimitMetrics {
+    pub sent: u64,
+    /// Messages that had to wait before being sent.
+    pub queued: u64,
+    /// Messages rejected because the queue was full.
+    pub rejected: u64,
+    /// Messages waiting right now.
+    pub waiting: usize,
+}
+
+/// Applies [`RateLimits`]. Clones share their buckets, so one limiter can
+/// throttle several clients together.
+#[derive(Clone)]
+pub struct RateLimiter {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    global: Mutex<Option<Bucket>>,
+    /// FIFO queues per limited topic, plus one (`None`) for the rest.
+    lanes: HashMap<Option<Topic>, tokio::sync::Mutex<Option<Bucket>>>,
+    max_queued: usize,
+    waiting: AtomicUsize,
+    sent: AtomicU64,
+    queued: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(limits: RateLimits) -> Self {
+        let mut lanes = limits
+            .topics
+            .into_iter()
+            .map(|(topic, limit)| {
+                (
+                    Some(topic),
+                    tokio::sync::Mutex::new(Some(Bucket::new(limit))),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        lanes.insert(None, tokio::sync::Mutex::new(None));
+        Self {
+            shared: Arc::new(Shared {
+                global: Mutex::new(limits.global.map(Bucket::new)),
+                lanes,
+                max_queued: limits.max_queued,
+                waiting: AtomicUsize::new(0),
+                sent: AtomicU64::new(0),
+                queued: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn metrics(&self) -> RateLimitMetrics {
+        let shared = &*self.shared;
+        RateLimitMetrics {
+            sent: shared.sent.load(Ordering::Relaxed),
+            queued: shared.queued.load(Ordering::Relaxed),
+            rejected: shared.rejected.load(Ordering::Relaxed),
+            waiting: shared.waiting.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Wait until a message on `topic` may be sent.
+    pub(crate) async fn acquire(&self, topic: Topic) -> Result<(), Error> {
+        let shared = &*self.shared;
+        let lane = shared
+            .lanes
+            .get(&Some(topic))
+            .unwrap_or_else(|| &shared.lanes[&None]);
+        // Holding the lane keeps messages on a topic in order. A message
+        // that gets it and a token straight away never joins the queue.
+        let mut free = lane.try_lock().ok();
+        if let Some(bucket) = free.as_mut() {
+            if shared.take(bucket.as_mut()).is_zero() {
+                shared.sent.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
+
+        let waiting = Waiting::enter(&shared.waiting);
+        if waiting.ahead >= shared.max_queued {
+            shared.rejected.fetch_add(1, Ordering::Relaxed);
+            warn!(?topic, "rate limit queue full, rejecting message");
+            return Err(Error::RateLimited);
+        }
+        let mut bucket = match free {
+            Some(bucket) => bucket,
+            None => lane.lock().await,
+        };
+        loop {
+            let wait = shared.take(bucket.as_mut());
+            if wait.is_zero() {
+                break;
+            }
+            tokio::time::sleep(wait).await;
+        }
+
+        drop(waiting);
+        shared.queued.fetch_add(1, Ordering::Relaxed);
+        shared.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Take a token from the global bucket and `lane`, if both have one.
+    /// Otherwise, how long until they will.
+    fn take(&self, lane: Option<&mut Bucket>) -> Duration {
+        let now = Instant::now();
+        let mut global = lock(&self.global);
+        let mut buckets = [global.as_mut(), lane];
+        let wait = buckets
+            .iter_mut()
+            .flatten()
+            .map(|bucket| bucket.refill(now))
+            .max()
+            .unwrap_or_default();
+        if wait.is_zero() {
+            buckets.into_iter().flatten().for_each(Bucket::take);
+        }
+        wait
+    }
+}
+
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Top the bucket up, returning how long until it has a token.
+    fn refill(&mut self, now: Instant) -> Duration {
+        let elapsed = now.duration_since(self.refilled_at);
+        self.refilled_at = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() / self.limit.interval.as_secs_f64())
+            .min(f64::from(self.limit.burst));
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            self.limit.interval.mul_f64(1.0 - self.tokens)
+        }
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+/// Counts a message as waiting until it is sent or given up on.
+struct Waiting<'a> {
+    count: &'a AtomicUsize,
+    /// How many were already waiting.
+    ahead: usize,
+}
+
+impl<'a> Waiting<'a> {
+    fn enter(count: &'a AtomicUsize) -> Self {
+        let ahead = count.fetch_add(1, Ordering::Relaxed);
+        Self { count, ahead }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Client {
+    /// A handle to the same connection that waits on `limiter` before
+    /// sending anything. Other clones of the client aren't limited.
+    #[must_use]
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap()
+    }
+
+    fn limiter(limit: RateLimit, max_queued: usize) -> RateLimiter {
+        RateLimiter::new(
+            RateLimits::new()
+                .topic(Topic::TdIosOrders, limit)
+                .max_queued(max_queued),
+        )
+    }
+
+    #[test]
+    fn refills_at_the_configured_rate() {
+        runtime().block_on(async {
+            let limiter = RateLimiter::new(RateLimits::new().global(RateLimit::per_second(4)));
+            let start = Instant::now();
+            for _ in 0..4 {
+                limiter.acquire(Topic::TdIosOrders).await.unwrap();
+            }
+            assert_eq!(start.elapsed(), Duration::ZERO);
+            for _ in 0..2 {
+                limiter.acquire(Topic::TrLogin).await.unwrap();
+            }
+            assert_eq!(start.elapsed(), Duration::from_millis(500));
+            let metrics = limiter.metrics();
+            assert_eq!((metrics.sent, metrics.queued), (6, 2));
+        });
+    }
+
+    #[test]
+    fn sends_a_topics_messages_in_the_order_they_were_queued() {
+        runtime().block_on(async {
+            let limiter = limiter(RateLimit::per_second(1), 100);
+            let sent = Arc::new(Mutex::new(vec![]));
+            let tasks = (0..5)
+                .map(|i| {
+                    let limiter = limiter.clone();
+                    let sent = sent.clone();
+                    tokio::spawn(async move {
+                        limiter.acquire(Topic::TdIosOrders).await.unwrap();
+                        sent.lock().unwrap().push((i, Instant::now()));
+                    })
+                })
+                .collect::<Vec<_>>();
+            for task in tasks {
+                task.await.unwrap();
+            }
+            let sent = sent.lock().unwrap();
+            assert_eq!(
+                sent.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+                [0, 1, 2, 3, 4]
+            );
+            let gaps = sent.windows(2).map(|w| w[1].1 - w[0].1);
+            assert!(gaps.into_iter().all(|gap| gap == Duration::from_secs(1)));
+        });
+    }
+
+    #[test]
+    fn only_messages_that_wait_count_towards_max_queued() {
+        runtime().block_on(async {
+            let limiter = limiter(RateLimit::per_second(1), 0);
+            assert!(limiter.acquire(Topic::TdIosOrders).await.is_ok());
+            assert!(matches!(
+                limiter.acquire(Topic::TdIosOrders).await,
+                Err(Error::RateLimited)
+            ));
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            assert!(limiter.acquire(Topic::TdIosOrders).await.is_ok());
+            let metrics = limiter.metrics();
+            assert_eq!((metrics.sent, metrics.rejected), (2, 1));
+        });
+    }
+
+    #[test]
+    fn rejects_messages_once_the_queue_is_full() {
+        runtime().block_on(async {
+            let limiter = limiter(RateLimit::per_second(1), 1);
+            limiter.acquire(Topic::TdIosOrders).await.unwrap();
+            let queued = tokio::spawn({
+                let limiter = limiter.clone();
+                async move { limiter.acquire(Topic::TdIosOrders).await }
+            });
+            tokio::task::yield_now().await;
+            assert_eq!(limiter.metrics().waiting, 1);
+            assert!(matches!(
+                limiter.acquire(Topic::TdIosOrders).await,
+                Err(Error::RateLimited)
+            ));
+            assert!(queued.await.unwrap().is_ok());
+            assert_eq!(limiter.metrics().waiting, 0);
+        });
+    }
+}
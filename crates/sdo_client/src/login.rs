@@ -0,0 +1,44 @@
+//! Logging in and out.
+
+use sdo::{
+    ios::{IosLoginRequest, IosLoginResponse, IosService, IosServicesRequest},
+    login::{LoginRequest, LoginResponse, Logout},
+    SdoRequest,
+};
+
+use crate::{client::lock, Client, Error};
+
+impl Client {
+    /// Log in, failing if the server rejects the login.
+    pub async fn login(&self, request: &LoginRequest) -> Result<LoginResponse, Error> {
+        Ok(self.request(request).await?.into_result()?)
+    }
+
+    /// End the session. The server doesn't answer, so this returns once the
+    /// logout is sent.
+    pub async fn logout(&self) -> Result<(), Error> {
+        let message = Logout::default().to_message(&mut *lock(&self.ids));
+        self.notify(&message).await
+    }
+
+    /// The IOS services this user can log in to.
+    pub async fn ios_services(&self) -> Result<Vec<IosService>, Error> {
+        Ok(self.request(&IosServicesRequest::default()).await?.services)
+    }
+
+    /// Log in to an IOS service, failing if the server rejects the login.
+    pub async fn ios_login(&self, request: &IosLoginRequest) -> Result<IosLoginResponse, Error> {
+        Ok(self.request(request).await?.into_result()?)
+    }
+
+    /// Log in to the IOS service called `ios_name`.
+    pub async fn ios_login_to(&self, ios_name: &str) -> Result<IosLoginResponse, Error> {
+        let service = self
+            .ios_services()
+            .await?
+            .into_iter()
+            .find(|service| service.ios_name == ios_name)
+            .ok_or_else(|| Error::UnknownIosService(ios_name.to_owned()))?;
+        self.ios_login(&IosLoginRequest::new(&service)).await
+    }
+}
@@ -0,0 +1,192 @@
+//! Fetching every page of a list request.
+
+use futures::{stream, Stream, TryStreamExt};
+use sdo::{fields::PAGE_DIRECTION, Message, PageDirection, SDO};
+
+use crate::{Client, Error};
+
+/// Re-issues a list request for as long as the server says it
+/// `has_more_data()`.
+///
+/// Each follow-up request carries `PAGE_DIRECTION` and the key fields of the
+/// last row received, so the server knows where to continue from. Which
+/// fields make up the key depends on the topic, e.g. the order number when
+/// listing orders. A page whose last row has none of them ends the stream
+/// with [`Error::MissingPageKey`], rather than asking for the same page
+/// again.
+///
+/// ```
+/// # use futures::TryStreamExt;
+/// # use sdo::{fields, Topic};
+/// # use sdo_client::{Client, Pager};
+/// # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+/// # let mut page = sdo::SDO::new(Topic::TdIosOrders);
+/// # page.push_long(fields::ORDER_NUMBER, vec![Some(1), Some(2)]);
+/// # let server = sdo_mock::MockServer::new().rule(sdo_mock::Rule::on(Topic::TdIosOrders).reply(page));
+/// # let (client, _broadcasts) = Client::new(server.duplex());
+/// let request = client.message(Topic::TdIosOrders);
+/// let orders = Pager::new(client.clone(), request, &[fields::ORDER_NUMBER])
+///     .rows()
+///     .try_collect::<Vec<_>>()
+///     .await?;
+/// # assert_eq!(orders.len(), 2);
+/// # Ok::<_, sdo_client::Error>(())
+/// # }).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Pager {
+    client: Client,
+    request: Message,
+    key_fields: Vec<u32>,
+    direction: PageDirection,
+}
+
+impl Pager {
+    /// Page through `request`'s responses, copying `key_fields` from the
+    /// last row of a page into the request for the next.
+    #[must_use]
+    pub fn new(client: Client, request: Message, key_fields: &[u32]) -> Self {
+        Self {
+            client,
+            request,
+            key_fields: key_fields.to_vec(),
+            direction: PageDirection::Forward,
+        }
+    }
+
+    #[must_use]
+    pub fn direction(mut self, direction: PageDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Every page's response, in order.
+    pub fn pages(self) -> impl Stream<Item = Result<SDO, Error>> + Send + 'static {
+        stream::try_unfold(Some(Ok(self)), |pager| async move {
+            let Some(pager) = pager else {
+                return Ok(None);
+            };
+            let pager = pager?;
+            let page = pager.client.send(pager.request.clone()).await?.sdo;
+            // Any error finding the next page comes after this one.
+            let next = pager.next(&page).transpose();
+            Ok(Some((page, next)))
+        })
+    }
+
+    /// Every row of every page, in order.
+    pub fn rows(self) -> impl Stream<Item = Result<SDO, Error>> + Send + 'static {
+        self.pages()
+            .map_ok(|page| stream::iter(page.rows().into_iter().map(Ok)))
+            .try_flatten()
+    }
+
+    /// The pager for the page after `page`, if there is one.
+    fn next(mut self, page: &SDO) -> Result<Option<Self>, Error> {
+        if !page.has_more_data() {
+            return Ok(None);
+        }
+        // A page without rows gives us no key to continue from, and asking
+        // again would get the same page back.
+        let Some(last) = page.rows().pop() else {
+            return Ok(None);
+        };
+        let keys = self
+            .key_fields
+            .iter()
+            .filter_map(|&field| Some((field, last.get_field(field)?.clone())))
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Err(Error::MissingPageKey);
+        }
+
+        let sdo = &mut self.request.sdo;
+        sdo.remove_field(PAGE_DIRECTION);
+        sdo.push_long(PAGE_DIRECTION, Some(self.direction as u32));
+        for (field, data) in keys {
+            sdo.remove_field(field);
+            sdo.push_data(field, data);
+        }
+        self.request.id = Some(self.client.next_id());
+        Ok(Some(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{StreamExt, TryStreamExt};
+    use sdo::{
+        fields::{HAS_MORE_DATA, ORDER_NUMBER, SECURITY_CODE},
+        Topic,
+    };
+    use sdo_mock::{MockServer, Rule};
+
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    /// Orders 1 and 2, then 3 once asked to continue after 2.
+    fn server() -> MockServer {
+        MockServer::new().rule(Rule::on(Topic::TdIosOrders).reply_with(|request| {
+            let mut page = SDO::new(Topic::TdIosOrders);
+            let after = request
+                .sdo
+                .get_field(ORDER_NUMBER)
+                .and_then(sdo::data::Data::as_first_u32);
+            if after == Some(2) {
+                page.push_long(ORDER_NUMBER, Some(3));
+            } else {
+                page.push_long(ORDER_NUMBER, vec![Some(1), Some(2)]);
+                page.push_long(HAS_MORE_DATA, Some(1));
+            }
+            page
+        }))
+    }
+
+    fn order_numbers(rows: &[SDO]) -> Vec<Option<u32>> {
+        rows.iter()
+            .map(|row| {
+                row.get_field(ORDER_NUMBER)
+                    .and_then(sdo::data::Data::as_first_u32)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn continues_from_the_last_row() {
+        runtime().block_on(async {
+            let server = server();
+            let (client, _broadcasts) = Client::new(server.duplex());
+            let request = client.message(Topic::TdIosOrders);
+            let rows = Pager::new(client.clone(), request, &[ORDER_NUMBER])
+                .rows()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+            assert_eq!(order_numbers(&rows), [Some(1), Some(2), Some(3)]);
+            let requests = server.requests();
+            assert_eq!(requests.len(), 2);
+            assert_ne!(requests[0].id, requests[1].id);
+        });
+    }
+
+    #[test]
+    fn stops_without_a_key_to_continue_from() {
+        runtime().block_on(async {
+            let (client, _broadcasts) = Client::new(server().duplex());
+            let request = client.message(Topic::TdIosOrders);
+            let pages = Pager::new(client.clone(), request, &[SECURITY_CODE])
+                .pages()
+                .collect::<Vec<_>>()
+                .await;
+            assert_eq!(pages.len(), 2);
+            assert!(pages[0].is_ok());
+            assert!(matches!(pages[1], Err(Error::MissingPageKey)));
+        });
+    }
+}
@@ -0,0 +1,534 @@
+//! Spreading requests over several logged-in sessions.
+//!
+//! A server works through one session's requests in order, so a busy
+//! service can keep a [`Pool`] of sessions instead. Each request goes to the
+//! session with the fewest requests in flight. Sessions that close are
+//! replaced in the background, and watches started through the pool are
+//! started again on the replacement for the session they were on.
+//!
+//! ```no_run
+//! # use sdo::{login::LoginRequest, Topic};
+//! # use sdo_client::{Keepalive, Pool, PoolConfig};
+//! # async fn run() -> Result<(), sdo_client::Error> {
+//! let pool = Pool::login(
+//!     PoolConfig::new().size(4).keepalive(Keepalive::new()),
+//!     "viewpoint:9999",
+//!     LoginRequest::new("user", "company").password("hunter2"),
+//! )
+//! .await?;
+//! let response = pool.send(pool.message(Topic::TdIosGeneral)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use sdo::{
+    login::LoginRequest,
+    request_id::{RandomIds, RequestIdGenerator},
+    Message, SdoRequest, Topic,
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{
+    client::{lock, Ids},
+    Client, Error, Keepalive, Subscription, TcpTransport, WatchRegistry, WatchRequest,
+};
+
+type Connect =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<Client, Error>> + Send>> + Send + Sync>;
+
+/// How many sessions to keep, and how to look after them.
+#[derive(Clone)]
+pub struct PoolConfig {
+    size: usize,
+    reconnect_delay: Duration,
+    max_reconnect_delay: Duration,
+    keepalive: Option<Keepalive>,
+    ids: Ids,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 4,
+            reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(30),
+            keepalive: None,
+            ids: Arc::new(Mutex::new(Box::new(RandomIds))),
+        }
+    }
+}
+
+impl fmt::Debug for PoolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolConfig")
+            .field("size", &self.size)
+            .field("reconnect_delay", &self.reconnect_delay)
+            .field("max_reconnect_delay", &self.max_reconnect_delay)
+            .field("keepalive", &self.keepalive)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PoolConfig {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many sessions to keep open. Defaults to 4.
+    #[must_use]
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size.max(1);
+        self
+    }
+
+    /// How long to wait before trying again when replacing a session fails.
+    /// Doubles with each failure, up to `max_reconnect_delay`. Defaults to
+    /// 1 second.
+    #[must_use]
+    pub fn reconnect_delay(mut self, delay: Duration) -> Self {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    /// Defaults to 30 seconds.
+    #[must_use]
+    pub fn max_reconnect_delay(mut self, delay: Duration) -> Self {
+        self.max_reconnect_delay = delay;
+        self
+    }
+
+    /// Ping every session, so ones the server has stopped answering are
+    /// noticed and replaced.
+    #[must_use]
+    pub fn keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Take request ids for every session, including replacements, from
+    /// `ids`. Defaults to [`RandomIds`].
+    #[must_use]
+    pub fn request_ids(mut self, ids: impl RequestIdGenerator + Send + 'static) -> Self {
+        self.ids = Arc::new(Mutex::new(Box::new(ids)));
+        self
+    }
+}
+
+/// A set of logged-in sessions to the same server.
+///
+/// Cloning a pool shares its sessions. They are closed once every clone has
+/// been dropped, along with any clients handed out by [`Pool::session`].
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    slots: Vec<Arc<Slot>>,
+    ids: Ids,
+    /// Where to start looking for the least busy session, so that idle
+    /// sessions take turns.
+    next: AtomicUsize,
+    /// Bumped whenever a session is replaced.
+    replaced: watch::Sender<()>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// One session, and the watches to start again when it's replaced.
+struct Slot {
+    client: Mutex<Client>,
+    watches: WatchRegistry,
+}
+
+impl Pool {
+    /// Open `config.size` sessions with `connect`, which should connect and
+    /// log in. Fails if any of the first sessions can't be opened; after
+    /// that, `connect` is retried until it succeeds. Sessions take request
+    /// ids from the pool's generator once `connect` returns them.
+    pub async fn connect<F, Fut>(config: PoolConfig, connect: F) -> Result<Self, Error>
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Client, Error>> + Send + 'static,
+    {
+        let ids = Arc::clone(&config.ids);
+        let connect: Connect = Arc::new(move || {
+            let session = connect();
+            let ids = Arc::clone(&ids);
+            Box::pin(async move {
+                let mut client = session.await?;
+                client.ids = ids;
+                Ok(client)
+            })
+        });
+        let clients = futures::future::try_join_all((0..config.size).map(|_| connect())).await?;
+        let (replaced, _) = watch::channel(());
+        let slots = clients
+            .into_iter()
+            .map(|client| {
+                Arc::new(Slot {
+                    client: Mutex::new(client),
+                    watches: WatchRegistry::new(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let tasks = slots
+            .iter()
+            .map(|slot| {
+                tokio::spawn(supervise(
+                    Arc::clone(slot),
+                    Arc::clone(&connect),
+                    config.clone(),
+                    replaced.clone(),
+                ))
+            })
+            .collect();
+        Ok(Self {
+            shared: Arc::new(Shared {
+                slots,
+                ids: Arc::clone(&config.ids),
+                next: AtomicUsize::new(0),
+                replaced,
+                tasks,
+            }),
+        })
+    }
+
+    /// Open sessions over TCP to `addr`, each logged in with `request`.
+    /// Broadcasts are dropped; use [`Pool::connect`] to handle them.
+    pub async fn login(
+        config: PoolConfig,
+        addr: impl Into<String>,
+        request: LoginRequest,
+    ) -> Result<Self, Error> {
+        let addr = addr.into();
+        let ids = Arc::clone(&config.ids);
+        Self::connect(config, move || {
+            let addr = addr.clone();
+            let request = request.clone();
+            let ids = Arc::clone(&ids);
+            async move {
+                let (mut client, _broadcasts) =
+                    Client::with_transport(TcpTransport::connect(addr).await?);
+                client.ids = ids;
+                client.login(&request).await?;
+                Ok(client)
+            }
+        })
+        .await
+    }
+
+    /// The open session with the fewest requests in flight, waiting for one
+    /// to be replaced if none are open.
+    pub async fn session(&self) -> Client {
+        self.pick().await.1
+    }
+
+    /// Send a message on the least busy session. If the message has a
+    /// timeout, it includes any wait for a session to open.
+    pub async fn send(&self, message: Message) -> Result<Message, Error> {
+        let session = match message.rounded_timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, self.session())
+                .await
+                .map_err(|_| Error::TimedOut(message.id.clone().unwrap_or_default()))?,
+            None => self.session().await,
+        };
+        session.send(message).await
+    }
+
+    /// Send a typed request on the least busy session and parse its
+    /// response. Its id comes from the pool's generator, and it waits for a
+    /// session the same way [`Pool::send`] does.
+    pub async fn request<R: SdoRequest>(&self, request: &R) -> Result<R::Response, Error> {
+        let message = request.to_message(&mut *lock(&self.shared.ids));
+        let response = self.send(message).await?;
+        Ok(R::parse_response(&response.sdo)?)
+    }
+
+    /// Start a watch on the least busy session. It stays on that session,
+    /// and is started again on its replacement if the session closes.
+    pub async fn watch(&self, request: WatchRequest) -> Result<Subscription, Error> {
+        let (slot, client) = self.pick().await;
+        slot.watches.watch(&client, request).await
+    }
+
+    /// An empty message on `topic` with the next id from the pool's request
+    /// id generator.
+    #[must_use]
+    pub fn message(&self, topic: Topic) -> Message {
+        Message::new(topic, &mut *lock(&self.shared.ids))
+    }
+
+    /// How many sessions the pool keeps.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.shared.slots.len()
+    }
+
+    /// How many sessions are open right now.
+    #[must_use]
+    pub fn open(&self) -> usize {
+        self.shared
+            .slots
+            .iter()
+            .filter(|slot| !lock(&slot.client).is_closed())
+            .count()
+    }
+
+    async fn pick(&self) -> (&Slot, Client) {
+        let mut replaced = self.shared.replaced.subscribe();
+        loop {
+            let slots = &self.shared.slots;
+            let start = self.shared.next.fetch_add(1, Ordering::Relaxed);
+            let least_busy = (0..slots.len())
+                .map(|i| &*slots[(start + i) % slots.len()])
+                .map(|slot| (slot, lock(&slot.client).clone()))
+                .filter(|(_, client)| !client.is_closed())
+                // `min_by_key` keeps the first of equals.
+                .min_by_key(|(_, client)| client.in_flight());
+            if let Some(picked) = least_busy {
+                return picked;
+            }
+            // We hold the sender, so this can't fail.
+            let _ = replaced.changed().await;
+        }
+    }
+}
+
+/// Replace the slot's session whenever it closes.
+async fn supervise(
+    slot: Arc<Slot>,
+    connect: Connect,
+    config: PoolConfig,
+    replaced: watch::Sender<()>,
+) {
+    let mut client = lock(&slot.client).clone();
+    loop {
+        let keepalive = config.keepalive.clone().map(|k| client.keepalive(k));
+        client.closed().await;
+        drop(keepalive);
+        warn!("pooled session closed, replacing it");
+
+        let mut delay = config.reconnect_delay;
+        client = loop {
+            match connect().await {
+                Ok(client) => break client,
+                Err(error) => {
+                    warn!(%error, ?delay, "failed to replace pooled session");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(config.max_reconnect_delay);
+                }
+            }
+        };
+        *lock(&slot.client) = client.clone();
+        replaced.send_replace(());
+        if let Err(error) = slot.watches.resubscribe(&client).await {
+            // Closing it gets us another go with a fresh session.
+            warn!(%error, "failed to resubscribe pooled watches");
+            client.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use sdo::{fields::LOGGED_IN, request_id::CounterIds, SDO};
+    use sdo_mock::{MockServer, Rule};
+    use tokio::sync::Semaphore;
+
+    use super::*;
+    use crate::WatchEvent;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .unwrap()
+    }
+
+    /// A pool whose connections can be dropped from the server's end, and
+    /// whose replacement sessions connect once `gate` has a permit.
+    struct Harness {
+        pool: Pool,
+        server: MockServer,
+        connections: Arc<Mutex<Vec<JoinHandle<()>>>>,
+        gate: Arc<Semaphore>,
+    }
+
+    impl Harness {
+        async fn new(size: usize) -> Self {
+            let server = MockServer::new()
+                .rule(Rule::on(Topic::TdIosGeneral).no_reply())
+                .rule(Rule::on(Topic::TdStartWatch));
+            let connections = Arc::new(Mutex::new(Vec::new()));
+            let gate = Arc::new(Semaphore::new(0));
+            let calls = Arc::new(AtomicUsize::new(0));
+            let config = PoolConfig::new().size(size).request_ids(CounterIds::new());
+            let pool = Pool::connect(config, {
+                let (server, connections, gate) =
+                    (server.clone(), Arc::clone(&connections), Arc::clone(&gate));
+                move || {
+                    let replacement = calls.fetch_add(1, Ordering::Relaxed) >= size;
+                    let (server, connections, gate) =
+                        (server.clone(), Arc::clone(&connections), Arc::clone(&gate));
+                    async move {
+                        if replacement {
+                            gate.acquire().await.unwrap().forget();
+                        }
+                        let (io, server_io) = tokio::io::duplex(64 * 1024);
+                        lock(&connections).push(tokio::spawn(server.serve(server_io)));
+                        Ok(Client::new(io).0)
+                    }
+                }
+            })
+            .await
+            .unwrap();
+            Self {
+                pool,
+                server,
+                connections,
+                gate,
+            }
+        }
+
+        /// Close the `i`th connection made, as if the server dropped it.
+        async fn drop_connection(&self, i: usize, client: &Client) {
+            lock(&self.connections)[i].abort();
+            client.closed().await;
+        }
+    }
+
+    #[test]
+    fn requests_go_to_the_least_busy_session() {
+        runtime().block_on(async {
+            let Harness { pool, server, .. } = Harness::new(2).await;
+            let (first, second) = (pool.session().await, pool.session().await);
+            assert!(!first.same_connection(&second), "idle sessions take turns");
+
+            for _ in 0..2 {
+                let message = pool.message(Topic::TdIosGeneral);
+                tokio::spawn({
+                    let pool = pool.clone();
+                    async move { pool.send(message).await }
+                });
+            }
+            while server.requests().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+            // Both sessions have a request in flight, so neither got both.
+            assert_eq!(first.in_flight(), 1);
+            assert_eq!(second.in_flight(), 1);
+            assert_eq!(pool.session().await.in_flight(), 1);
+        });
+    }
+
+    #[test]
+    fn closed_sessions_are_replaced_and_waited_for() {
+        runtime().block_on(async {
+            let harness = Harness::new(1).await;
+            let pool = &harness.pool;
+            let first = pool.session().await;
+            harness.drop_connection(0, &first).await;
+            assert_eq!(pool.open(), 0);
+
+            // No session is open until the replacement connects.
+            let waiting = tokio::time::timeout(Duration::from_secs(30), pool.session()).await;
+            assert!(waiting.is_err());
+            let message = pool
+                .message(Topic::TdIosGeneral)
+                .with_timeout(Duration::from_secs(1));
+            assert!(matches!(pool.send(message).await, Err(Error::TimedOut(_))));
+
+            harness.gate.add_permits(1);
+            let second = pool.session().await;
+            assert!(!second.same_connection(&first));
+            assert!(!second.is_closed());
+            assert_eq!(pool.open(), 1);
+        });
+    }
+
+    #[test]
+    fn watches_are_started_again_on_the_replacement() {
+        runtime().block_on(async {
+            let harness = Harness::new(1).await;
+            let (pool, server) = (&harness.pool, &harness.server);
+            let mut subscription = pool
+                .watch(WatchRequest::new(Topic::TdIosGeneral).key("BHP"))
+                .await
+                .unwrap();
+            let first = subscription.id();
+
+            harness.gate.add_permits(1);
+            harness.drop_connection(0, &pool.session().await).await;
+            assert!(matches!(
+                subscription.next().await,
+                Some(WatchEvent::Resynced)
+            ));
+            let second = subscription.id();
+            assert_ne!(second, first);
+            let starts = server
+                .requests()
+                .into_iter()
+                .filter(|r| r.sdo.topic == Topic::TdStartWatch)
+                .map(|r| r.id.unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(starts, [first, second.clone()]);
+
+            assert!(server.push_update(&second, SDO::new(Topic::TdIosGeneral)));
+            assert!(matches!(
+                subscription.next().await,
+                Some(WatchEvent::Update(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn requests_take_ids_from_the_pool() {
+        runtime().block_on(async {
+            let mut logged_in = SDO::new(Topic::TdLogin);
+            logged_in.push_bool(LOGGED_IN, Some(true));
+            let server = MockServer::new().rule(Rule::on(Topic::TrLogin).reply(logged_in));
+            let config = PoolConfig::new().size(1).request_ids(CounterIds::new());
+            let pool = Pool::connect(config, {
+                let server = server.clone();
+                move || {
+                    let io = server.duplex();
+                    async move { Ok(Client::new(io).0) }
+                }
+            })
+            .await
+            .unwrap();
+
+            let response = pool
+                .request(&LoginRequest::new("user", "company"))
+                .await
+                .unwrap();
+            assert!(response.is_logged_in());
+            assert_eq!(pool.message(Topic::TrPing).id.as_deref(), Some("R_2_"));
+            let requests = server.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].id.as_deref(), Some("R_1_"));
+        });
+    }
+}
@@ -0,0 +1,52 @@
+//! Calling the client as a [`tower_service::Service`], so standard tower
+//! middleware for timeouts, retries, rate and concurrency limits can wrap
+//! SDO requests.
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use sdo::Topic;
+//! # use sdo_client::Client;
+//! # use tower::{Service, ServiceBuilder, ServiceExt};
+//! # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+//! # let server = sdo_mock::MockServer::new()
+//! #     .rule(sdo_mock::Rule::on(Topic::TdIosGeneral).reply(sdo::SDO::new(Topic::TdIosGeneral)));
+//! # let (client, _broadcasts) = Client::new(server.duplex());
+//! let request = client.message(Topic::TdIosGeneral);
+//! let mut service = ServiceBuilder::new()
+//!     .timeout(Duration::from_secs(10))
+//!     .concurrency_limit(8)
+//!     .service(client);
+//! let sdo = service.ready().await?.call(request).await?;
+//! # Ok::<_, tower::BoxError>(())
+//! # }).unwrap();
+//! ```
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use sdo::{Message, SDO};
+use tower_service::Service;
+
+use crate::{Client, Error};
+
+/// Sends the message and resolves to the assembled reply.
+impl Service<Message> for Client {
+    type Response = SDO;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<SDO, Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.is_closed() {
+            return Poll::Ready(Err(Error::Closed));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, message: Message) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move { Ok(client.send(message).await?.sdo) })
+    }
+}
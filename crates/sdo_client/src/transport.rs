@@ -0,0 +1,522 @@
+//! How frames of bytes reach the server.
+//!
+//! A [`Transport`] sends and receives whole frames, each holding one or more
+//! encoded messages. The client doesn't care what carries them, so the same
+//! code can talk to a server over TCP, a websocket, or an in-memory channel
+//! in tests.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::Write,
+    pin::Pin,
+    task::{ready, Context, Poll, Waker},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{channel::mpsc, Sink, SinkExt, Stream, StreamExt};
+use sdo::capture::{
+    recording::{Pace, Recorder},
+    CapturedFrame, Direction,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Instant, Sleep},
+};
+use tokio_tungstenite::{tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use crate::{codec, Error};
+
+/// A connection that carries frames of encoded messages.
+///
+/// Implemented for anything that is both a stream of received frames and a
+/// sink for frames to send.
+pub trait Transport:
+    Stream<Item = Result<Bytes, Error>> + Sink<Bytes, Error = Error> + Send + Unpin
+{
+}
+
+impl<T> Transport for T where
+    T: Stream<Item = Result<Bytes, Error>> + Sink<Bytes, Error = Error> + Send + Unpin
+{
+}
+
+/// Frames over a byte stream, each prefixed by its 4 byte big-endian
+/// length; see [`codec`].
+pub struct TcpTransport<T = TcpStream> {
+    frames: Framed<T, LengthDelimitedCodec>,
+}
+
+impl TcpTransport {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> TcpTransport<T> {
+    #[must_use]
+    pub fn new(io: T) -> Self {
+        Self {
+            frames: Framed::new(io, codec::length_codec()),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Stream for TcpTransport<T> {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.frames
+            .poll_next_unpin(cx)
+            .map(|frame| frame.map(|frame| Ok(frame?.freeze())))
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Sink<Bytes> for TcpTransport<T> {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        SinkExt::<Bytes>::poll_ready_unpin(&mut self.frames, cx).map_err(Error::Io)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Bytes) -> Result<(), Error> {
+        self.frames.start_send_unpin(frame).map_err(Error::Io)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        SinkExt::<Bytes>::poll_flush_unpin(&mut self.frames, cx).map_err(Error::Io)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        SinkExt::<Bytes>::poll_close_unpin(&mut self.frames, cx).map_err(Error::Io)
+    }
+}
+
+/// Frames as binary websocket messages, of at most
+/// [`MAX_FRAME_LEN`](codec::MAX_FRAME_LEN) bytes.
+pub struct WebSocketTransport<S = MaybeTlsStream<TcpStream>> {
+    ws: WebSocketStream<S>,
+}
+
+impl WebSocketTransport {
+    /// Connect to a `ws://` URL.
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let (ws, _) = tokio_tungstenite::connect_async_with_config(
+            url,
+            Some(codec::websocket_config()),
+            false,
+        )
+        .await
+        .map_err(Box::new)?;
+        Ok(Self::new(ws))
+    }
+}
+
+impl<S> WebSocketTransport<S> {
+    /// Frames over `ws`. Configure it with [`codec::websocket_config`] so
+    /// that oversized messages are refused before they are read in full.
+    #[must_use]
+    pub fn new(ws: WebSocketStream<S>) -> Self {
+        Self { ws }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Stream for WebSocketTransport<S> {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let message = match ready!(self.ws.poll_next_unpin(cx)) {
+                Some(Ok(message)) => message,
+                Some(Err(error)) => return Poll::Ready(Some(Err(Box::new(error).into()))),
+                None => return Poll::Ready(None),
+            };
+            match message {
+                WsMessage::Binary(frame) if frame.len() > codec::MAX_FRAME_LEN => {
+                    return Poll::Ready(Some(Err(Error::FrameTooLong(frame.len()))))
+                }
+                WsMessage::Binary(frame) => return Poll::Ready(Some(Ok(frame.into()))),
+                WsMessage::Close(_) => return Poll::Ready(None),
+                WsMessage::Text(text) => warn!(len = text.len(), "skipping text message"),
+                // Pings are answered by tungstenite.
+                WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_) => {}
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sink<Bytes> for WebSocketTransport<S> {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.ws.poll_ready_unpin(cx).map_err(|e| Box::new(e).into())
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Bytes) -> Result<(), Error> {
+        if frame.len() > codec::MAX_FRAME_LEN {
+            return Err(Error::FrameTooLong(frame.len()));
+        }
+        self.ws
+            .start_send_unpin(WsMessage::Binary(frame.into()))
+            .map_err(|e| Box::new(e).into())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.ws.poll_flush_unpin(cx).map_err(|e| Box::new(e).into())
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.ws.poll_close_unpin(cx).map_err(|e| Box::new(e).into())
+    }
+}
+
+/// One end of an in-memory connection, for tests.
+pub struct ChannelTransport {
+    tx: mpsc::UnboundedSender<Bytes>,
+    rx: mpsc::UnboundedReceiver<Bytes>,
+}
+
+impl ChannelTransport {
+    /// Two connected ends. Frames sent on one are received on the other.
+    #[must_use]
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, b_rx) = mpsc::unbounded();
+        let (b_tx, a_rx) = mpsc::unbounded();
+        (Self { tx: a_tx, rx: a_rx }, Self { tx: b_tx, rx: b_rx })
+    }
+}
+
+impl Stream for ChannelTransport {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx).map(|frame| frame.map(Ok))
+    }
+}
+
+impl Sink<Bytes> for ChannelTransport {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.tx.poll_ready_unpin(cx).map_err(|_| Error::Closed)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Bytes) -> Result<(), Error> {
+        self.tx.start_send_unpin(frame).map_err(|_| Error::Closed)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.tx.poll_flush_unpin(cx).map_err(|_| Error::Closed)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.tx.poll_close_unpin(cx).map_err(|_| Error::Closed)
+    }
+}
+
+/// Records every frame sent and received over another transport, e.g. to
+/// replay the session later with [`ReplayTransport`] or `sdo replay`.
+///
+/// Frames are written as they pass through, so give the recorder a buffered
+/// writer. A recording that fails to write is logged rather than closing
+/// the connection.
+pub struct RecordingTransport<T, W: Write> {
+    inner: T,
+    recorder: Recorder<W>,
+}
+
+impl<T, W: Write> RecordingTransport<T, W> {
+    #[must_use]
+    pub fn new(inner: T, recorder: Recorder<W>) -> Self {
+        Self { inner, recorder }
+    }
+
+    fn record(&mut self, direction: Direction, frame: &[u8]) {
+        if let Err(error) = self.recorder.record(direction, frame) {
+            warn!(%error, "failed to record frame");
+        }
+    }
+}
+
+impl<T: Transport, W: Write + Unpin> Stream for RecordingTransport<T, W> {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let frame = ready!(self.inner.poll_next_unpin(cx));
+        if let Some(Ok(frame)) = &frame {
+            self.record(Direction::Received, frame);
+        }
+        Poll::Ready(frame)
+    }
+}
+
+impl<T: Transport, W: Write + Unpin> Sink<Bytes> for RecordingTransport<T, W> {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Bytes) -> Result<(), Error> {
+        self.record(Direction::Sent, &frame);
+        self.inner.start_send_unpin(frame)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.inner.poll_flush_unpin(cx))?;
+        if let Err(error) = self.recorder.flush() {
+            warn!(%error, "failed to flush recording");
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
+
+/// Plays back what the server sent in a recording, to drive a
+/// [`Client`](crate::Client) without a server.
+///
+/// Each received frame is held back until the client has sent as many
+/// frames as had been sent before it when recording, so responses follow
+/// their requests, and then until it is due at the given [`Pace`]. What the
+/// client sends is otherwise ignored, so for responses to reach their
+/// requests the client needs the request ids used when recording, e.g. from
+/// the same [`CounterIds`](sdo::request_id::CounterIds). The connection
+/// closes after the last frame.
+pub struct ReplayTransport {
+    frames: VecDeque<Replayed>,
+    /// When replay started.
+    started: Option<Instant>,
+    sent: usize,
+    sleep: Option<Pin<Box<Sleep>>>,
+    /// Woken when the client sends a frame we're waiting for.
+    waiting: Option<Waker>,
+}
+
+struct Replayed {
+    frame: Bytes,
+    /// How many frames had been sent before this one was received.
+    sent_before: usize,
+    /// How long after replay starts the frame is due.
+    delay: Option<Duration>,
+}
+
+impl ReplayTransport {
+    #[must_use]
+    pub fn new(frames: impl IntoIterator<Item = CapturedFrame>, pace: Pace) -> Self {
+        let mut first = None;
+        let mut sent = 0;
+        let frames = frames
+            .into_iter()
+            .filter_map(|frame| {
+                let first = *first.get_or_insert(frame.timestamp);
+                match frame.direction {
+                    Direction::Sent => {
+                        sent += 1;
+                        None
+                    }
+                    Direction::Received => Some(Replayed {
+                        delay: pace.delay(first, frame.timestamp),
+                        frame: frame.bytes.into(),
+                        sent_before: sent,
+                    }),
+                }
+            })
+            .collect();
+        Self {
+            frames,
+            started: None,
+            sent: 0,
+            sleep: None,
+            waiting: None,
+        }
+    }
+}
+
+impl Stream for ReplayTransport {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        let Some(next) = self.frames.front() else {
+            return Poll::Ready(None);
+        };
+        if self.sent < next.sent_before {
+            self.waiting = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        if let Some(delay) = next.delay {
+            let sleep = self
+                .sleep
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(started + delay)));
+            ready!(sleep.as_mut().poll(cx));
+            self.sleep = None;
+        }
+        Poll::Ready(self.frames.pop_front().map(|r| Ok(r.frame)))
+    }
+}
+
+impl Sink<Bytes> for ReplayTransport {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, _frame: Bytes) -> Result<(), Error> {
+        self.sent += 1;
+        if let Some(waker) = self.waiting.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use sdo::{
+        capture::recording::Reader, fields::SECURITY_CODE, request_id::CounterIds, Topic, SDO,
+    };
+    use sdo_mock::{MockServer, Rule};
+
+    use super::*;
+    use crate::Client;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    /// A recording target we can still read while the client owns it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn channel_pairs_carry_frames_both_ways() {
+        runtime().block_on(async {
+            let (mut a, mut b) = ChannelTransport::pair();
+            a.send(Bytes::from_static(b"ping")).await.unwrap();
+            assert_eq!(b.next().await.unwrap().unwrap(), "ping");
+            b.send(Bytes::from_static(b"pong")).await.unwrap();
+            assert_eq!(a.next().await.unwrap().unwrap(), "pong");
+            drop(a);
+            assert!(b.next().await.is_none());
+            assert!(matches!(
+                b.send(Bytes::from_static(b"gone")).await,
+                Err(Error::Closed)
+            ));
+        });
+    }
+
+    #[test]
+    fn websockets_carry_binary_frames() {
+        runtime().block_on(async {
+            let (client, server) = tokio::io::duplex(1024);
+            let (client, server) = tokio::join!(
+                tokio_tungstenite::client_async("ws://localhost/", client),
+                tokio_tungstenite::accept_async(server),
+            );
+            let mut transport = WebSocketTransport::new(client.unwrap().0);
+            let mut server = server.unwrap();
+
+            transport.send(Bytes::from_static(b"ping")).await.unwrap();
+            assert_eq!(
+                server.next().await.unwrap().unwrap(),
+                WsMessage::Binary(b"ping".to_vec())
+            );
+            assert!(matches!(
+                transport
+                    .send(Bytes::from(vec![0; codec::MAX_FRAME_LEN + 1]))
+                    .await,
+                Err(Error::FrameTooLong(_))
+            ));
+
+            // Text is skipped, and closing ends the stream.
+            server.send(WsMessage::Text("hello".into())).await.unwrap();
+            server
+                .send(WsMessage::Binary(b"pong".to_vec()))
+                .await
+                .unwrap();
+            server.close(None).await.unwrap();
+            assert_eq!(transport.next().await.unwrap().unwrap(), "pong");
+            assert!(transport.next().await.is_none());
+        });
+    }
+
+    fn security_code(sdo: &SDO) -> Option<&str> {
+        sdo.get_field(SECURITY_CODE)
+            .and_then(sdo::data::Data::as_first_str)
+    }
+
+    #[test]
+    fn replays_a_recorded_session() {
+        runtime().block_on(async {
+            let mut reply = SDO::new(Topic::TdIosGeneral);
+            reply.push_string_w(SECURITY_CODE, Some("BHP".to_owned()));
+            let server = MockServer::new().rule(Rule::on(Topic::TdIosGeneral).reply(reply));
+            let recording = Shared::default();
+            let transport = RecordingTransport::new(
+                TcpTransport::new(server.duplex()),
+                Recorder::new(recording.clone()).unwrap(),
+            );
+            let (client, _broadcasts) = Client::with_transport(transport);
+            let client = client.with_request_ids(CounterIds::new());
+            let recorded = client
+                .send(client.message(Topic::TdIosGeneral))
+                .await
+                .unwrap();
+
+            let bytes = recording.0.lock().unwrap().clone();
+            let frames = Reader::new(&bytes[..])
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let directions = frames.iter().map(|f| f.direction).collect::<Vec<_>>();
+            assert_eq!(directions, [Direction::Sent, Direction::Received]);
+
+            let (client, _broadcasts) =
+                Client::with_transport(ReplayTransport::new(frames, Pace::Original));
+            let client = client.with_request_ids(CounterIds::new());
+            let replayed = client
+                .send(client.message(Topic::TdIosGeneral))
+                .await
+                .unwrap();
+            assert_eq!(replayed.id, recorded.id);
+            assert_eq!(security_code(&recorded.sdo), Some("BHP"));
+            assert_eq!(security_code(&replayed.sdo), Some("BHP"));
+            client.closed().await;
+        });
+    }
+}
@@ -0,0 +1,453 @@
+//! Streaming subscriptions.
+//!
+//! A watch is started with a `TdStartWatch` request naming the topic and the
+//! keys to watch. The server then pushes updates flagged `IS_WATCH_UPDATES`
+//! and tagged with the starting request's id, until a `TrStopWatch` for that
+//! id ends it.
+
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::Stream;
+use sdo::{
+    fields::{HINT_WATCH_KEYS, WATCH_KEY, WATCH_REQUEST_ID, WATCH_TOPIC},
+    request_id::RequestIdGenerator,
+    Message, Topic, SDO,
+};
+use tokio::sync::mpsc;
+
+use crate::{client::lock, Client, Error};
+
+/// What to watch.
+#[derive(Debug, Clone)]
+pub struct WatchRequest {
+    topic: Topic,
+    keys: Vec<String>,
+    hint_keys: bool,
+    timeout: Option<Duration>,
+}
+
+impl WatchRequest {
+    #[must_use]
+    pub fn new(topic: Topic) -> Self {
+        Self {
+            topic,
+            keys: vec![],
+            hint_keys: false,
+            timeout: None,
+        }
+    }
+
+    #[must_use]
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+
+    #[must_use]
+    pub fn keys<I: IntoIterator<Item = impl Into<String>>>(mut self, keys: I) -> Self {
+        self.keys.extend(keys.into_iter().map(Into::into));
+        self
+    }
+
+    /// Ask the server to tag each update with the index of its key.
+    #[must_use]
+    pub fn hint_keys(mut self, hint_keys: bool) -> Self {
+        self.hint_keys = hint_keys;
+        self
+    }
+
+    /// Give up on starting the watch, and stop it, if the server hasn't
+    /// answered within `timeout`.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    #[must_use]
+    pub fn topic(&self) -> Topic {
+        self.topic
+    }
+
+    /// The `TdStartWatch` request, with an id from `ids`. Fails for topics
+    /// with negative ids, which `WATCH_TOPIC` can't hold.
+    pub fn to_message(&self, ids: &mut impl RequestIdGenerator) -> Result<Message, Error> {
+        let topic =
+            u32::try_from(self.topic as i32).map_err(|_| Error::InvalidWatchTopic(self.topic))?;
+        let mut message = Message::new(Topic::TdStartWatch, ids);
+        message.timeout = self.timeout;
+        let sdo = &mut message.sdo;
+        sdo.push_long(WATCH_TOPIC, Some(topic));
+        sdo.push_string_w(
+            WATCH_KEY,
+            self.keys.iter().cloned().map(Some).collect::<Vec<_>>(),
+        );
+        if self.hint_keys {
+            sdo.push_bool(HINT_WATCH_KEYS, Some(true));
+        }
+        Ok(message)
+    }
+}
+
+/// One update pushed for a watch.
+#[derive(Debug, Clone)]
+pub struct WatchUpdate {
+    /// The watched topic, from the update's `WATCH_TOPIC`.
+    pub topic: Option<Topic>,
+    /// Which of the watch's keys this update is for, when the server says.
+    pub key_index: Option<u32>,
+    pub key: Option<String>,
+    pub sdo: SDO,
+}
+
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    Update(WatchUpdate),
+    /// The watch has been started again on a new connection, and updates
+    /// after this come from the new watch. Updates may have been missed
+    /// while disconnected, so reload anything that matters.
+    Resynced,
+}
+
+/// What the client sends down a subscription's channel.
+#[derive(Debug)]
+pub(crate) enum Event {
+    Update(SDO),
+    Resynced,
+}
+
+/// The connection a watch is currently running on.
+struct Active {
+    client: Client,
+    id: String,
+}
+
+/// The events for one watch. Dropping it stops the watch.
+pub struct Subscription {
+    request: WatchRequest,
+    active: Arc<Mutex<Active>>,
+    rx: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Subscription {
+    /// The id of the request that (most recently) started the watch.
+    #[must_use]
+    pub fn id(&self) -> String {
+        lock(&self.active).id.clone()
+    }
+
+    #[must_use]
+    pub fn request(&self) -> &WatchRequest {
+        &self.request
+    }
+
+    fn update(&self, sdo: SDO) -> WatchUpdate {
+        let key_index = sdo.watch_key_index();
+        WatchUpdate {
+            topic: sdo
+                .watch_topic()
+                .and_then(|t| i32::try_from(t).ok())
+                .and_then(Topic::from_id),
+            key_index,
+            key: key_index.and_then(|i| self.request.keys.get(i as usize).cloned()),
+            sdo,
+        }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = WatchEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WatchEvent>> {
+        self.rx.poll_recv(cx).map(|event| {
+            event.map(|event| match event {
+                Event::Update(sdo) => WatchEvent::Update(self.update(sdo)),
+                Event::Resynced => WatchEvent::Resynced,
+            })
+        })
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let Active { client, id } = &*lock(&self.active);
+        client.remove_watch(id);
+        if client.is_closed() {
+            return;
+        }
+        // Stopping needs a write, which we can't wait for here. The server
+        // doesn't answer `TrStopWatch`, so nothing waits for a reply.
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!(id, "no runtime to stop watch on");
+            return;
+        };
+        let stop = stop_message(client, id);
+        let client = client.clone();
+        runtime.spawn(async move {
+            if let Err(error) = client.notify(&stop).await {
+                warn!(%error, "failed to stop watch");
+            }
+        });
+    }
+}
+
+impl Client {
+    /// Start a watch. Events arrive on the returned subscription until it
+    /// is dropped or the connection closes.
+    pub async fn watch(&self, request: WatchRequest) -> Result<Subscription, Error> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let id = self.start_watch(&request, tx).await?;
+        Ok(Subscription {
+            request,
+            active: Arc::new(Mutex::new(Active {
+                client: self.clone(),
+                id,
+            })),
+            rx,
+        })
+    }
+
+    /// Send the request starting a watch, returning its id.
+    async fn start_watch(
+        &self,
+        request: &WatchRequest,
+        tx: mpsc::UnboundedSender<Event>,
+    ) -> Result<String, Error> {
+        let message = request.to_message(&mut *lock(&self.ids))?;
+        let id = message.id.clone().ok_or(Error::MissingRequestId)?;
+        // Updates can beat the response, so listen before sending.
+        self.add_watch(id.clone(), tx);
+        if let Err(error) = self.send(message).await {
+            self.remove_watch(&id);
+            // The server may have started the watch after we gave up on it.
+            if let Error::TimedOut(_) = error {
+                if let Err(error) = self.notify(&stop_message(self, &id)).await {
+                    warn!(%error, "failed to stop timed out watch");
+                }
+            }
+            return Err(error);
+        }
+        Ok(id)
+    }
+}
+
+fn stop_message(client: &Client, id: &str) -> Message {
+    let mut stop = client.message(Topic::TrStopWatch);
+    stop.sdo
+        .push_string_w(WATCH_REQUEST_ID, Some(id.to_owned()));
+    stop
+}
+
+/// Keeps track of active watches so they survive reconnecting.
+///
+/// Watches started through a registry carry on after their connection
+/// drops. Once a new connection is up and logged in, hand it to
+/// [`WatchRegistry::resubscribe`] and every watch that is still subscribed
+/// to is started again. Its subscription gets a [`WatchEvent::Resynced`]
+/// once the new watch has started, ahead of the new watch's updates.
+///
+/// ```no_run
+/// # use sdo_client::{Client, WatchRegistry};
+/// # async fn connect() -> std::io::Result<tokio::net::TcpStream> {
+/// #     tokio::net::TcpStream::connect("viewpoint:9999").await
+/// # }
+/// # async fn login(client: &Client) -> Result<(), sdo_client::Error> {
+/// #     Ok(())
+/// # }
+/// # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let watches = WatchRegistry::new();
+/// loop {
+///     let (client, _broadcasts) = Client::new(connect().await?);
+///     login(&client).await?;
+///     watches.resubscribe(&client).await?;
+///     client.closed().await;
+/// }
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct WatchRegistry {
+    watches: Arc<Mutex<Vec<Registered>>>,
+}
+
+struct Registered {
+    request: WatchRequest,
+    tx: mpsc::UnboundedSender<Event>,
+    /// Gone once the subscription is dropped.
+    active: Weak<Mutex<Active>>,
+}
+
+impl WatchRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a watch on `client` that will be resubscribed after
+    /// reconnecting.
+    pub async fn watch(
+        &self,
+        client: &Client,
+        request: WatchRequest,
+    ) -> Result<Subscription, Error> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let id = client.start_watch(&request, tx.clone()).await?;
+        let active = Arc::new(Mutex::new(Active {
+            client: client.clone(),
+            id,
+        }));
+        lock(&self.watches).push(Registered {
+            request: request.clone(),
+            tx,
+            active: Arc::downgrade(&active),
+        });
+        Ok(Subscription {
+            request,
+            active,
+            rx,
+        })
+    }
+
+    /// Start every active watch again on `client`. Stops at the first watch
+    /// that fails to start; calling again retries the ones left over.
+    pub async fn resubscribe(&self, client: &Client) -> Result<(), Error> {
+        let watches = {
+            let mut watches = lock(&self.watches);
+            watches.retain(|w| w.active.strong_count() > 0);
+            watches
+                .iter()
+                .map(|w| (w.request.clone(), w.tx.clone(), w.active.clone()))
+                .collect::<Vec<_>>()
+        };
+        for (request, tx, active) in watches {
+            let Some(active) = active.upgrade() else {
+                continue;
+            };
+            if lock(&active).client.same_connection(client) {
+                continue;
+            }
+            // Updates arriving while the watch starts are held back, so they
+            // come after the marker, which is only sent once it has started.
+            let (buffer, mut buffered) = mpsc::unbounded_channel();
+            let id = client.start_watch(&request, buffer).await?;
+            client.resume_watch(&id, &mut buffered, tx);
+            *lock(&active) = Active {
+                client: client.clone(),
+                id,
+            };
+        }
+        Ok(())
+    }
+
+    /// How many watches are still subscribed to.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        lock(&self.watches)
+            .iter()
+            .filter(|w| w.active.strong_count() > 0)
+            .count()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use sdo::fields::SECURITY_CODE;
+    use sdo_mock::{MockServer, Rule};
+
+    use super::*;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    fn server() -> MockServer {
+        let mut update = SDO::new(Topic::TdIosGeneral);
+        update.push_string_w(SECURITY_CODE, Some("BHP".to_owned()));
+        MockServer::new().rule(
+            Rule::on(Topic::TdStartWatch)
+                .reply(SDO::new(Topic::TdStartWatch))
+                .updates([update]),
+        )
+    }
+
+    #[test]
+    fn negative_topics_cannot_be_watched() {
+        let mut ids = sdo::request_id::CounterIds::new();
+        let message = WatchRequest::new(Topic::TdIosGeneral)
+            .to_message(&mut ids)
+            .unwrap();
+        assert_eq!(
+            message.sdo.watch_topic(),
+            u32::try_from(Topic::TdIosGeneral as i32).ok()
+        );
+        for topic in [Topic::UndefinedTopic, Topic::Unknown] {
+            assert!(matches!(
+                WatchRequest::new(topic).to_message(&mut ids),
+                Err(Error::InvalidWatchTopic(t)) if t == topic
+            ));
+        }
+    }
+
+    #[test]
+    fn resynced_comes_before_the_new_watchs_updates() {
+        runtime().block_on(async {
+            let server = server();
+            let watches = WatchRegistry::new();
+            let (first, _broadcasts) = Client::new(server.duplex());
+            let mut subscription = watches
+                .watch(&first, WatchRequest::new(Topic::TdIosGeneral).key("BHP"))
+                .await
+                .unwrap();
+            assert!(matches!(
+                subscription.next().await,
+                Some(WatchEvent::Update(_))
+            ));
+
+            first.close();
+            let (second, _broadcasts) = Client::new(server.duplex());
+            watches.resubscribe(&second).await.unwrap();
+            assert!(matches!(
+                subscription.next().await,
+                Some(WatchEvent::Resynced)
+            ));
+            assert!(matches!(
+                subscription.next().await,
+                Some(WatchEvent::Update(_))
+            ));
+            assert_eq!(subscription.id(), server.requests()[1].id.clone().unwrap());
+        });
+    }
+
+    #[test]
+    fn dropping_a_subscription_stops_the_watch() {
+        runtime().block_on(async {
+            let server = server();
+            let (client, _broadcasts) = Client::new(server.duplex());
+            let subscription = client
+                .watch(WatchRequest::new(Topic::TdIosGeneral).key("BHP"))
+                .await
+                .unwrap();
+            let id = subscription.id();
+            drop(subscription);
+            while server.requests().len() < 2 {
+                tokio::task::yield_now().await;
+            }
+            let stop = &server.requests()[1];
+            assert_eq!(stop.sdo.topic, Topic::TrStopWatch);
+            assert_eq!(stop.sdo.watch_request_id(), Some(id.as_str()));
+        });
+    }
+}
@@ -1,14 +1,264 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, ToTokens};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Data, DeriveInput, Expr, ExprLit, ExprPath,
+    Fields, Generics, Lit, Path, Type, WherePredicate,
+};
+
+/// Derives `sdo::request::SdoRequest` for a struct.
+///
+/// ```
+/// # use sdo::{FromSdo, SdoRequest};
+/// #[derive(SdoRequest)]
+/// #[sdo(request = TrLogin, response = TdLogin, reply = LoginResponse)]
+/// struct LoginRequest {
+///     #[sdo(field = USER_NAME)]
+///     user_name: String,
+/// }
+///
+/// #[derive(FromSdo)]
+/// struct LoginResponse {
+///     #[sdo(field = ACS_LOGIN_TOKEN)]
+///     token: Option<String>,
+/// }
+/// ```
+///
+/// Topics are resolved against `sdo::Topic` and bare field names against
+/// `sdo::fields`. Without `reply` the response is left as a raw `SDO`.
+#[proc_macro_derive(SdoRequest, attributes(sdo))]
+pub fn derive_sdo_request(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_sdo_request(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `sdo::request::FromSdo` for a struct, reading each field marked
+/// with `#[sdo(field = ...)]` out of the SDO. Fields marked `#[sdo(skip)]`
+/// are filled with their `Default`.
+#[proc_macro_derive(FromSdo, attributes(sdo))]
+pub fn derive_from_sdo(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_sdo(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct ContainerAttrs {
+    request: Option<Path>,
+    response: Option<Path>,
+    reply: Option<Type>,
+}
+
+impl ContainerAttrs {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let mut attrs = Self::default();
+        for attr in input.attrs.iter().filter(|a| a.path().is_ident("sdo")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("request") {
+                    attrs.request = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("response") {
+                    attrs.response = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("reply") {
+                    attrs.reply = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("expected `request`, `response` or `reply`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(attrs)
+    }
+}
+
+enum FieldAttr {
+    Id(TokenStream2),
+    Skip,
+}
+
+struct SdoField<'a> {
+    ident: &'a syn::Ident,
+    ty: &'a Type,
+    attr: FieldAttr,
+}
+
+fn named_fields(input: &DeriveInput) -> syn::Result<Vec<SdoField<'_>>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "sdo derives only support structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        if matches!(data.fields, Fields::Unit) {
+            return Ok(vec![]);
+        }
+        return Err(syn::Error::new(
+            data.fields.span(),
+            "sdo derives require named fields",
+        ));
+    };
+
+    fields
+        .named
+        .iter()
+        .map(|field| {
+            let mut attr = None;
+            for a in field.attrs.iter().filter(|a| a.path().is_ident("sdo")) {
+                a.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("field") {
+                        let expr: Expr = meta.value()?.parse()?;
+                        attr = Some(FieldAttr::Id(field_id(&expr)?));
+                    } else if meta.path.is_ident("skip") {
+                        attr = Some(FieldAttr::Skip);
+                    } else {
+                        return Err(meta.error("expected `field` or `skip`"));
+                    }
+                    Ok(())
+                })?;
+            }
+            let ident = field.ident.as_ref().expect("named field");
+            let attr = attr.ok_or_else(|| {
+                syn::Error::new(
+                    field.span(),
+                    "missing `#[sdo(field = ...)]` or `#[sdo(skip)]`",
+                )
+            })?;
+            Ok(SdoField {
+                ident,
+                ty: &field.ty,
+                attr,
+            })
+        })
+        .collect()
+}
+
+/// Bare identifiers refer to constants in `sdo::fields`; anything else
+/// (integer literals, qualified paths) is used as written.
+fn field_id(expr: &Expr) -> syn::Result<TokenStream2> {
+    match expr {
+        Expr::Path(ExprPath { path, .. }) if path.get_ident().is_some() => {
+            Ok(quote!(::sdo::fields::#path))
+        }
+        Expr::Path(_)
+        | Expr::Lit(ExprLit {
+            lit: Lit::Int(_), ..
+        }) => Ok(expr.to_token_stream()),
+        _ => Err(syn::Error::new(
+            expr.span(),
+            "expected a field constant or integer id",
+        )),
+    }
+}
+
+fn topic(path: &Path) -> TokenStream2 {
+    if path.get_ident().is_some() {
+        quote!(::sdo::Topic::#path)
+    } else {
+        path.to_token_stream()
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn expand_sdo_request(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let attrs = ContainerAttrs::parse(input)?;
+    let Some(request) = attrs.request.as_ref().map(topic) else {
+        return Err(syn::Error::new(
+            input.span(),
+            "missing `#[sdo(request = ...)]`",
+        ));
+    };
+    let Some(response) = attrs.response.as_ref().map(topic) else {
+        return Err(syn::Error::new(
+            input.span(),
+            "missing `#[sdo(response = ...)]`",
+        ));
+    };
+    let reply = attrs
+        .reply
+        .map_or_else(|| quote!(::sdo::SDO), ToTokens::into_token_stream);
+
+    let fields = named_fields(input)?;
+    let pushes = fields.iter().filter_map(|f| {
+        let FieldAttr::Id(id) = &f.attr else {
+            return None;
+        };
+        let ident = f.ident;
+        Some(quote! {
+            ::sdo::request::IntoField::push_field(&self.#ident, sdo, #id);
+        })
+    });
+
+    let bounds = fields
+        .iter()
+        .filter(|f| matches!(f.attr, FieldAttr::Id(_)))
+        .map(|f| {
+            let ty = f.ty;
+            parse_quote!(#ty: ::sdo::request::IntoField)
+        })
+        .chain([parse_quote!(#reply: ::sdo::request::FromSdo)]);
+    let generics = bounded_generics(input, bounds);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::sdo::request::SdoRequest for #name #ty_generics #where_clause {
+            const TOPIC: ::sdo::Topic = #request;
+            const RESPONSE_TOPIC: ::sdo::Topic = #response;
+            type Response = #reply;
+
+            fn write_fields(&self, sdo: &mut ::sdo::SDO) {
+                #(#pushes)*
+            }
+        }
+    })
+}
+
+fn expand_from_sdo(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(input)?;
+    let inits = fields.iter().map(|f| {
+        let ident = f.ident;
+        let ty = f.ty;
+        match &f.attr {
+            FieldAttr::Id(id) => quote! {
+                #ident: <#ty as ::sdo::request::FromField>::read_field(sdo, #id)?
+            },
+            FieldAttr::Skip => quote! {
+                #ident: ::core::default::Default::default()
+            },
+        }
+    });
+
+    let bounds = fields.iter().map(|f| {
+        let ty = f.ty;
+        match f.attr {
+            FieldAttr::Id(_) => parse_quote!(#ty: ::sdo::request::FromField),
+            FieldAttr::Skip => parse_quote!(#ty: ::core::default::Default),
+        }
+    });
+    let generics = bounded_generics(input, bounds);
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::sdo::request::FromSdo for #name #ty_generics #where_clause {
+            fn from_sdo(sdo: &::sdo::SDO) -> ::core::result::Result<Self, ::sdo::request::Error> {
+                ::core::result::Result::Ok(Self {
+                    #(#inits,)*
+                })
+            }
+        }
+    })
+}
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+/// The struct's generics, plus `bounds` if it has type parameters, so that
+/// e.g. a `T` field is only read when `T: FromField`.
+fn bounded_generics(
+    input: &DeriveInput,
+    bounds: impl IntoIterator<Item = WherePredicate>,
+) -> Generics {
+    let mut generics = input.generics.clone();
+    if generics.type_params().next().is_some() {
+        generics.make_where_clause().predicates.extend(bounds);
     }
+    generics
 }
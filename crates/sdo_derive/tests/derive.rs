@@ -0,0 +1,168 @@
+use sdo::{
+    data::Data,
+    fields::{ORDER_NUMBER, USER_NAME},
+    request::{Error, FromField, IntoField},
+    FromSdo, SdoRequest, Topic, SDO,
+};
+use time::{macros::datetime, OffsetDateTime};
+
+#[derive(SdoRequest)]
+#[sdo(request = TrLogin, response = TdLogin, reply = LoginResponse)]
+struct LoginRequest {
+    #[sdo(field = USER_NAME)]
+    user_name: String,
+    #[sdo(field = 9999)]
+    note: Option<String>,
+    #[sdo(skip)]
+    #[allow(dead_code)]
+    attempts: u32,
+}
+
+#[derive(Debug, FromSdo)]
+struct LoginResponse {
+    #[sdo(field = USER_NAME)]
+    user_name: String,
+    #[sdo(field = sdo::fields::ORDER_NUMBER)]
+    order: Option<u32>,
+    #[sdo(skip)]
+    cached: Vec<u32>,
+}
+
+#[derive(SdoRequest)]
+#[sdo(request = TrLogin, response = TdLogin)]
+struct Unit;
+
+#[derive(SdoRequest, FromSdo)]
+#[sdo(request = TdIosOrders, response = TdIosOrders, reply = Generic<T>)]
+struct Generic<T> {
+    #[sdo(field = SECURITY_CODE)]
+    value: T,
+}
+
+#[derive(Debug, PartialEq, SdoRequest, FromSdo)]
+#[sdo(request = TdIosOrders, response = TdIosOrders, reply = Values)]
+struct Values {
+    #[sdo(field = 1)]
+    text: String,
+    #[sdo(field = 2)]
+    small: u32,
+    #[sdo(field = 3)]
+    large: u64,
+    #[sdo(field = 4)]
+    flag: bool,
+    #[sdo(field = 5)]
+    price: f64,
+    #[sdo(field = 6)]
+    side: char,
+    #[sdo(field = 7)]
+    at: OffsetDateTime,
+    #[sdo(field = 8)]
+    texts: Vec<String>,
+    #[sdo(field = 9)]
+    smalls: Vec<u32>,
+    #[sdo(field = 10)]
+    larges: Vec<u64>,
+    #[sdo(field = 11)]
+    prices: Vec<f64>,
+}
+
+fn login_reply() -> SDO {
+    let mut sdo = SDO::new(Topic::TdLogin);
+    sdo.push_string_w(USER_NAME, Some("alice".to_owned()));
+    sdo
+}
+
+#[test]
+fn writes_marked_fields_onto_the_request_topic() {
+    let request = LoginRequest {
+        user_name: "alice".to_owned(),
+        note: None,
+        attempts: 3,
+    };
+    let sdo = request.to_sdo();
+    assert_eq!(sdo.topic, Topic::TrLogin);
+    assert_eq!(
+        sdo.get_field(USER_NAME).and_then(Data::as_first_str),
+        Some("alice")
+    );
+    assert_eq!(sdo.fields.len(), 1);
+
+    let sdo = Unit.to_sdo();
+    assert!(sdo.fields.is_empty());
+    assert_eq!(<Unit as SdoRequest>::RESPONSE_TOPIC, Topic::TdLogin);
+}
+
+#[test]
+fn reads_the_reply() {
+    let response = LoginRequest::parse_response(&login_reply()).unwrap();
+    assert_eq!(response.user_name, "alice");
+    assert_eq!(response.order, None);
+    assert!(response.cached.is_empty());
+
+    let mut reply = login_reply();
+    reply.push_long(ORDER_NUMBER, Some(7));
+    let response = LoginRequest::parse_response(&reply).unwrap();
+    assert_eq!(response.order, Some(7));
+}
+
+#[test]
+fn rejects_replies_on_the_wrong_topic_or_with_bad_fields() {
+    let mut reply = login_reply();
+    reply.topic = Topic::TdIosOrders;
+    assert!(matches!(
+        LoginRequest::parse_response(&reply),
+        Err(Error::UnexpectedTopic { .. })
+    ));
+
+    let reply = SDO::new(Topic::TdLogin);
+    assert!(matches!(
+        LoginRequest::parse_response(&reply),
+        Err(Error::MissingField(USER_NAME))
+    ));
+
+    let mut reply = login_reply();
+    reply.push_string_w(ORDER_NUMBER, Some("seven".to_owned()));
+    assert!(matches!(
+        LoginResponse::from_sdo(&reply),
+        Err(Error::InvalidField(ORDER_NUMBER))
+    ));
+}
+
+#[test]
+fn generic_structs_use_their_parameters_field_traits() {
+    fn round_trip<T: IntoField + FromField>(value: T) -> T {
+        let sdo = Generic { value }.to_sdo();
+        Generic::<T>::parse_response(&sdo).unwrap().value
+    }
+    assert_eq!(round_trip("BHP".to_owned()), "BHP");
+    assert_eq!(round_trip(42u32), 42);
+}
+
+#[test]
+fn requests_and_replies_cover_the_same_field_types() {
+    let values = Values {
+        text: "BHP".to_owned(),
+        small: 7,
+        large: u64::from(u32::MAX) + 1,
+        flag: true,
+        price: 45.5,
+        side: 'B',
+        at: datetime!(2024-03-01 10:00 UTC),
+        texts: vec!["a".to_owned(), "b".to_owned()],
+        smalls: vec![1, 2],
+        larges: vec![3, 4],
+        prices: vec![0.5, 1.5],
+    };
+    let sdo = values.to_sdo();
+    assert_eq!(Values::parse_response(&sdo).unwrap(), values);
+}
+
+#[test]
+fn single_values_come_from_the_first_non_null_row() {
+    let mut reply = SDO::new(Topic::TdLogin);
+    reply.push_string_w(USER_NAME, vec![None, Some("alice".to_owned())]);
+    reply.push_long(ORDER_NUMBER, vec![None, Some(7), Some(8)]);
+    let response = LoginResponse::from_sdo(&reply).unwrap();
+    assert_eq!(response.user_name, "alice");
+    assert_eq!(response.order, Some(7));
+}
@@ -0,0 +1,359 @@
+#![warn(clippy::pedantic)]
+//! A scriptable SDO server to run clients against in tests.
+//!
+//! ```
+//! # use bytes::Bytes;
+//! # use futures::{SinkExt, StreamExt};
+//! # use sdo::{decode, fields, request_id::CounterIds, Message, Topic, SDO};
+//! # use sdo_mock::{MockServer, Rule};
+//! # use tokio_util::codec::{Framed, LengthDelimitedCodec};
+//! # tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async {
+//! let mut logged_in = SDO::new(Topic::TdLogin);
+//! logged_in.push_bool(fields::LOGGED_IN, Some(true));
+//! let server = MockServer::new().rule(Rule::on(Topic::TrLogin).reply(logged_in));
+//!
+//! // Usually `sdo_client::Client::new(server.duplex())`.
+//! let mut io = Framed::new(server.duplex(), LengthDelimitedCodec::new());
+//! let request = Message::new(Topic::TrLogin, &mut CounterIds::new());
+//! io.send(Bytes::from(request.encode()?)).await?;
+//! let reply = io.next().await.unwrap()?;
+//! let reply = decode::read_msgs(&reply).next().unwrap()?;
+//! assert_eq!((reply.id, reply.sdo.topic), (request.id, Topic::TdLogin));
+//! assert_eq!(server.requests()[0].sdo.topic, Topic::TrLogin);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! # }).unwrap();
+//! ```
+#[macro_use]
+extern crate tracing;
+
+pub mod rule;
+pub mod transport;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use sdo::{
+    decode,
+    fields::{ERROR_MESSAGE, IS_WATCH_UPDATES, PACKET_FLAG, WATCH_REQUEST_ID},
+    Message, PacketFlags, Topic, SDO,
+};
+use tokio::sync::mpsc;
+
+pub use rule::Rule;
+pub use transport::Listener;
+
+/// Answers requests from its [`Rule`]s.
+///
+/// Each request is answered by the first rule that matches it, with the
+/// request's id. Requests no rule matches are answered on `TdError`.
+/// Cloning a server shares its rules, the requests it has seen and the
+/// watches it has started, so a clone is a handle for adding rules and
+/// pushing watch updates while clients are connected.
+#[derive(Clone, Default)]
+pub struct MockServer {
+    rules: Arc<Mutex<Vec<Rule>>>,
+    requests: Arc<Mutex<Vec<Message>>>,
+    watches: Arc<Mutex<HashMap<String, Connection>>>,
+    max_rows_per_packet: Option<usize>,
+}
+
+/// Sends encoded messages to one connection, outside of any reply.
+pub(crate) type Connection = mpsc::UnboundedSender<Vec<u8>>;
+
+impl MockServer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn rule(self, rule: Rule) -> Self {
+        self.add_rule(rule);
+        self
+    }
+
+    /// Add a rule, after the existing ones.
+    pub fn add_rule(&self, rule: Rule) {
+        lock(&self.rules).push(rule);
+    }
+
+    /// Split replies with more rows than this into multi-packet responses.
+    #[must_use]
+    pub fn max_rows_per_packet(mut self, rows: usize) -> Self {
+        self.max_rows_per_packet = Some(rows.max(1));
+        self
+    }
+
+    /// Every request received so far, in order.
+    #[must_use]
+    pub fn requests(&self) -> Vec<Message> {
+        lock(&self.requests).clone()
+    }
+
+    /// Push `update` for the watch started by request `watch_id`, on the
+    /// connection that started it. Returns `false` if that watch isn't
+    /// running, because it was never started, has been stopped, or its
+    /// connection has closed.
+    pub fn push_update(&self, watch_id: &str, update: SDO) -> bool {
+        let Some(connection) = lock(&self.watches).get(watch_id).cloned() else {
+            return false;
+        };
+        let update = watch_update(watch_id, update);
+        match update.encode_exact() {
+            Ok(bytes) => connection.send(bytes).is_ok(),
+            Err(error) => {
+                warn!(%error, "failed to encode watch update");
+                false
+            }
+        }
+    }
+
+    /// Encoded replies to every message in `frame`, received on
+    /// `connection`.
+    fn replies_to(&self, frame: &[u8], connection: &Connection) -> Vec<Vec<u8>> {
+        let mut replies = vec![];
+        // Frames may hold several messages back to back.
+        for request in decode::read_msgs(frame) {
+            let request = match request {
+                Ok(request) => request,
+                Err(error) => {
+                    warn!(%error, len = frame.len(), "skipping undecodable frame");
+                    break;
+                }
+            };
+            self.track_watch(&request, connection);
+            for reply in self.answer(&request) {
+                match reply.encode_exact() {
+                    Ok(bytes) => replies.push(bytes),
+                    Err(error) => warn!(%error, topic = ?reply.sdo.topic, "failed to encode reply"),
+                }
+            }
+        }
+        replies
+    }
+
+    fn answer(&self, request: &Message) -> Vec<Message> {
+        lock(&self.requests).push(request.clone());
+        let reply = |sdo| Message {
+            id: request.id.clone(),
+            timeout: None,
+            page_size: None,
+            sdo,
+        };
+
+        let rules = lock(&self.rules);
+        let Some(rule) = rules.iter().find(|rule| rule.matches(request)) else {
+            warn!(topic = ?request.sdo.topic, "no rule for request");
+            let mut error = SDO::new(Topic::TdError);
+            error.push_string_w(
+                ERROR_MESSAGE,
+                Some(format!("no mock rule for {:?}", request.sdo.topic)),
+            );
+            return vec![reply(error)];
+        };
+
+        let mut replies = rule
+            .replies(request)
+            .iter()
+            .flat_map(|sdo| self.packets(sdo))
+            .map(reply)
+            .collect::<Vec<_>>();
+        if let Some(id) = &request.id {
+            replies.extend(
+                rule.watch_updates()
+                    .iter()
+                    .map(|update| watch_update(id, update.clone())),
+            );
+        }
+        replies
+    }
+
+    /// Remember which connection started each watch, until it is stopped.
+    fn track_watch(&self, request: &Message, connection: &Connection) {
+        let mut watches = lock(&self.watches);
+        watches.retain(|_, connection| !connection.is_closed());
+        match request.sdo.topic {
+            Topic::TdStartWatch => {
+                if let Some(id) = &request.id {
+                    watches.insert(id.clone(), connection.clone());
+                }
+            }
+            Topic::TrStopWatch => {
+                if let Some(id) = request.sdo.watch_request_id() {
+                    watches.remove(id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Split `sdo` into packets of at most `max_rows_per_packet` rows.
+    fn packets(&self, sdo: &SDO) -> Vec<SDO> {
+        let rows = sdo.rows();
+        let Some(max) = self.max_rows_per_packet.filter(|&max| rows.len() > max) else {
+            return vec![sdo.clone()];
+        };
+        let chunks = rows.chunks(max).collect::<Vec<_>>();
+        let last = chunks.len() - 1;
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut packet = SDO::with_topic_id(sdo.topic_id());
+                if i == 0 {
+                    // Fields `rows` left out describe the whole response.
+                    packet.fields.extend(
+                        sdo.fields
+                            .iter()
+                            .filter(|(h, _)| {
+                                h.field_id()
+                                    .is_none_or(|id| chunk[0].get_field(id).is_none())
+                            })
+                            .cloned(),
+                    );
+                }
+                for (header, _) in &chunk[0].fields {
+                    let Some(id) = header.field_id() else {
+                        continue;
+                    };
+                    let mut data = chunk.iter().filter_map(|row| row.get_field(id).cloned());
+                    let Some(mut merged) = data.next() else {
+                        continue;
+                    };
+                    for row in data {
+                        // Every row of a field has the same type.
+                        let _ = merged.extend(row);
+                    }
+                    packet.push_data(id, merged);
+                }
+                let mut flags = PacketFlags::empty();
+                flags.set(PacketFlags::FIRST, i == 0);
+                flags.set(PacketFlags::LAST, i == last);
+                packet.push_long(PACKET_FLAG, Some(flags.bits()));
+                packet
+            })
+            .collect()
+    }
+}
+
+/// `sdo` flagged as an update for the watch started by request `id`.
+fn watch_update(id: &str, mut sdo: SDO) -> Message {
+    sdo.push_bool(IS_WATCH_UPDATES, Some(true));
+    sdo.push_string_w(WATCH_REQUEST_ID, Some(id.to_owned()));
+    Message {
+        id: Some(id.to_owned()),
+        timeout: None,
+        page_size: None,
+        sdo,
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Nothing is left half-updated under these locks.
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use sdo::{
+        fields::{ORDER_NUMBER, SECURITY_CODE},
+        request_id::CounterIds,
+    };
+
+    use super::*;
+
+    fn request(topic: Topic, ids: &mut CounterIds) -> Message {
+        Message::new(topic, ids)
+    }
+
+    /// Decode each encoded reply.
+    fn decode(replies: &[Vec<u8>]) -> Vec<Message> {
+        replies
+            .iter()
+            .map(|reply| decode::read_msgs(reply).next().unwrap().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn replies_carry_only_the_rules_fields() {
+        let mut reply = SDO::new(Topic::TdIosGeneral);
+        reply.push_string_w(SECURITY_CODE, Some("BHP".to_owned()));
+        let server = MockServer::new().rule(Rule::on(Topic::TdIosGeneral).reply(reply.clone()));
+        let (connection, _) = mpsc::unbounded_channel();
+        let request = request(Topic::TdIosGeneral, &mut CounterIds::new());
+        let replies = server.replies_to(&request.encode().unwrap(), &connection);
+        assert_eq!(
+            replies,
+            [Message {
+                sdo: reply,
+                ..request
+            }
+            .encode_exact()
+            .unwrap()]
+        );
+    }
+
+    #[test]
+    fn rules_without_replies_answer_with_an_empty_sdo() {
+        let server = MockServer::new()
+            .rule(Rule::on(Topic::TdIosGeneral))
+            .rule(Rule::on(Topic::TrStopWatch).no_reply());
+        let (connection, _) = mpsc::unbounded_channel();
+        let mut ids = CounterIds::new();
+        let mut frame = request(Topic::TdIosGeneral, &mut ids).encode().unwrap();
+        frame.extend(request(Topic::TrStopWatch, &mut ids).encode().unwrap());
+        let replies = decode(&server.replies_to(&frame, &connection));
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].id.as_deref(), Some("R_1_"));
+        assert_eq!(replies[0].sdo.topic, Topic::TdIosGeneral);
+        assert!(replies[0].sdo.fields.is_empty());
+    }
+
+    #[test]
+    fn splits_rows_into_packets() {
+        let mut reply = SDO::new(Topic::TdIosOrders);
+        reply.push_long(ORDER_NUMBER, (1..=5).map(Some).collect::<Vec<_>>());
+        let server = MockServer::new()
+            .rule(Rule::on(Topic::TdIosOrders).reply(reply))
+            .max_rows_per_packet(2);
+        let (connection, _) = mpsc::unbounded_channel();
+        let request = request(Topic::TdIosOrders, &mut CounterIds::new());
+        let packets = decode(&server.replies_to(&request.encode().unwrap(), &connection));
+        let flags = packets
+            .iter()
+            .map(|p| p.sdo.packet_flag().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flags,
+            [PacketFlags::FIRST, PacketFlags::empty(), PacketFlags::LAST]
+        );
+        let rows = packets
+            .iter()
+            .map(|p| p.sdo.get_field(ORDER_NUMBER).unwrap().null_rows().len())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, [2, 2, 1]);
+    }
+
+    #[test]
+    fn pushes_updates_to_the_connection_that_started_the_watch() {
+        let server = MockServer::new().rule(Rule::on(Topic::TdStartWatch));
+        let (connection, mut pushed) = mpsc::unbounded_channel();
+        let mut ids = CounterIds::new();
+        let start = request(Topic::TdStartWatch, &mut ids);
+        server.replies_to(&start.encode().unwrap(), &connection);
+
+        assert!(server.push_update("R_1_", SDO::new(Topic::TdIosGeneral)));
+        assert!(!server.push_update("R_2_", SDO::new(Topic::TdIosGeneral)));
+        let update = decode(&[pushed.try_recv().unwrap()]).remove(0);
+        assert!(update.sdo.is_watch_updates());
+        assert_eq!(update.sdo.watch_request_id(), Some("R_1_"));
+
+        let mut stop = request(Topic::TrStopWatch, &mut ids);
+        stop.sdo
+            .push_string_w(WATCH_REQUEST_ID, Some("R_1_".to_owned()));
+        server.replies_to(&stop.encode().unwrap(), &connection);
+        assert!(!server.push_update("R_1_", SDO::new(Topic::TdIosGeneral)));
+    }
+}
@@ -0,0 +1,107 @@
+//! Scripted answers to requests.
+
+use sdo::{request::FromField, Message, Topic, SDO};
+
+type Matcher = Box<dyn Fn(&Message) -> bool + Send + Sync>;
+type Replier = Box<dyn Fn(&Message) -> SDO + Send + Sync>;
+
+/// How to answer requests on one topic.
+///
+/// A rule matches requests on its topic whose fields pass every
+/// [`Rule::when`]. It answers with its replies, in order, followed by any
+/// watch updates.
+///
+/// ```
+/// # use sdo::{fields, Topic, SDO};
+/// # use sdo_mock::Rule;
+/// let mut reply = SDO::new(Topic::TdLogin);
+/// reply.push_bool(fields::LOGGED_IN, Some(true));
+/// Rule::on(Topic::TrLogin)
+///     .when(fields::USER_NAME, "bob".to_string())
+///     .reply(reply);
+/// ```
+pub struct Rule {
+    topic: Topic,
+    matchers: Vec<Matcher>,
+    repliers: Vec<Replier>,
+    updates: Vec<SDO>,
+    silent: bool,
+}
+
+impl Rule {
+    /// Answer requests on `topic`. Without any replies the request is
+    /// answered with an empty SDO on its own topic, so clients waiting for
+    /// a response get one; see [`Rule::no_reply`] for requests the server
+    /// doesn't answer.
+    #[must_use]
+    pub fn on(topic: Topic) -> Self {
+        Self {
+            topic,
+            matchers: vec![],
+            repliers: vec![],
+            updates: vec![],
+            silent: false,
+        }
+    }
+
+    /// Only match requests where `field` reads as `value`.
+    #[must_use]
+    pub fn when<T>(self, field: u32, value: T) -> Self
+    where
+        T: FromField + PartialEq + Send + Sync + 'static,
+    {
+        self.matching(move |request| {
+            T::read_field(&request.sdo, field).is_ok_and(|actual| actual == value)
+        })
+    }
+
+    /// Only match requests that pass `matcher`.
+    #[must_use]
+    pub fn matching(mut self, matcher: impl Fn(&Message) -> bool + Send + Sync + 'static) -> Self {
+        self.matchers.push(Box::new(matcher));
+        self
+    }
+
+    /// Answer with `sdo`, sent with the request's id.
+    #[must_use]
+    pub fn reply(self, sdo: SDO) -> Self {
+        self.reply_with(move |_| sdo.clone())
+    }
+
+    /// Answer with an SDO built from the request.
+    #[must_use]
+    pub fn reply_with(mut self, reply: impl Fn(&Message) -> SDO + Send + Sync + 'static) -> Self {
+        self.repliers.push(Box::new(reply));
+        self
+    }
+
+    /// Accept matching requests without answering, like `TrStopWatch`.
+    #[must_use]
+    pub fn no_reply(mut self) -> Self {
+        self.silent = true;
+        self
+    }
+
+    /// After replying, push `updates` as updates for the watch the request
+    /// started.
+    #[must_use]
+    pub fn updates(mut self, updates: impl IntoIterator<Item = SDO>) -> Self {
+        self.updates.extend(updates);
+        self
+    }
+
+    pub(crate) fn matches(&self, request: &Message) -> bool {
+        request.sdo.topic == self.topic && self.matchers.iter().all(|m| m(request))
+    }
+
+    pub(crate) fn replies(&self, request: &Message) -> Vec<SDO> {
+        if self.repliers.is_empty() && !self.silent {
+            return vec![SDO::with_topic_id(request.sdo.topic_id())];
+        }
+        self.repliers.iter().map(|r| r(request)).collect()
+    }
+
+    pub(crate) fn watch_updates(&self) -> &[SDO] {
+        &self.updates
+    }
+}
@@ -0,0 +1,145 @@
+//! Serving a [`MockServer`] over TCP, websockets or an in-memory pipe.
+//!
+//! Over TCP and in-memory pipes each message is framed by a 4 byte
+//! big-endian length, as `sdo_client` expects. Over websockets each binary
+//! message holds one or more messages back to back.
+
+use std::{future::Future, io, net::SocketAddr};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use crate::MockServer;
+
+/// A server listening on a local port. Dropping it stops accepting
+/// connections.
+pub struct Listener {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Listener {
+    #[must_use]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl MockServer {
+    /// Serve one connection over an in-memory pipe, returning the client's
+    /// end. Must be called from within a tokio runtime.
+    #[must_use]
+    pub fn duplex(&self) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(self.clone().serve(server));
+        client
+    }
+
+    /// Serve length-framed messages over `io` until it closes.
+    pub async fn serve<T>(self, io: T)
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut frames = Framed::new(io, LengthDelimitedCodec::new());
+        let (connection, mut pushed) = mpsc::unbounded_channel();
+        loop {
+            let replies = tokio::select! {
+                frame = frames.next() => match frame {
+                    Some(Ok(frame)) => self.replies_to(&frame, &connection),
+                    Some(Err(error)) => {
+                        warn!(%error, "connection failed");
+                        return;
+                    }
+                    None => return,
+                },
+                Some(update) = pushed.recv() => vec![update],
+            };
+            for reply in replies {
+                if let Err(error) = frames.send(Bytes::from(reply)).await {
+                    warn!(%error, "failed to send reply");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Serve messages over a websocket connection until it closes.
+    pub async fn serve_websocket(self, stream: TcpStream) {
+        let mut ws = match tokio_tungstenite::accept_async(stream).await {
+            Ok(ws) => ws,
+            Err(error) => {
+                warn!(%error, "websocket handshake failed");
+                return;
+            }
+        };
+        let (connection, mut pushed) = mpsc::unbounded_channel();
+        loop {
+            let replies = tokio::select! {
+                message = ws.next() => match message {
+                    Some(Ok(WsMessage::Binary(frame))) => self.replies_to(&frame, &connection),
+                    Some(Ok(WsMessage::Close(_))) | None => return,
+                    // Pings are answered by tungstenite.
+                    Some(Ok(_)) => continue,
+                    Some(Err(error)) => {
+                        warn!(%error, "connection failed");
+                        return;
+                    }
+                },
+                Some(update) = pushed.recv() => vec![update],
+            };
+            for reply in replies {
+                if let Err(error) = ws.send(WsMessage::Binary(reply)).await {
+                    warn!(%error, "failed to send reply");
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Accept length-framed TCP connections on `addr`, e.g.
+    /// `"127.0.0.1:0"` for any free port.
+    pub async fn listen_tcp(&self, addr: impl ToSocketAddrs) -> io::Result<Listener> {
+        self.listen(addr, MockServer::serve).await
+    }
+
+    /// Accept websocket connections on `addr`.
+    pub async fn listen_websocket(&self, addr: impl ToSocketAddrs) -> io::Result<Listener> {
+        self.listen(addr, MockServer::serve_websocket).await
+    }
+
+    async fn listen<F, Fut>(&self, addr: impl ToSocketAddrs, serve: F) -> io::Result<Listener>
+    where
+        F: Fn(MockServer, TcpStream) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let server = self.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, peer)) => {
+                        trace!(%peer, "accepted connection");
+                        tokio::spawn(serve(server.clone(), stream));
+                    }
+                    Err(error) => warn!(%error, "failed to accept connection"),
+                }
+            }
+        });
+        Ok(Listener { addr, task })
+    }
+}